@@ -0,0 +1,375 @@
+//! Do-not-disturb schedules
+//!
+//! Quiet hours are given in local wall-clock time and resolved against a
+//! time zone when evaluated, so they follow daylight saving time changes.
+
+use chrono::{
+    DateTime, Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Weekday,
+};
+
+/// Days searched ahead for the next quiet-hours transition
+const LOOKAHEAD_DAYS: u64 = 8;
+
+/// Longest daylight saving gap skipped when resolving a local time, in minutes
+const MAX_GAP_MINUTES: i64 = 180;
+
+/// Which notifications break through do-not-disturb
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum DndMode {
+    /// Nothing breaks through
+    #[default]
+    Silence,
+    /// Only critical notifications break through
+    CriticalOnly,
+    /// Only allowed apps and categories break through
+    AllowList,
+}
+
+/// What do-not-disturb does with a notification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DndOutcome {
+    /// Show the notification as usual
+    Show,
+    /// Keep the notification off screen
+    Suppress {
+        /// Still play its sound
+        sound: bool,
+        /// Keep it in history
+        history: bool,
+    },
+}
+
+/// Check if a category is `allowed` or in its class (e.g. `call.incoming`
+/// for `call`)
+pub fn category_matches(category: &str, allowed: &str) -> bool {
+    category == allowed
+        || category
+            .strip_prefix(allowed)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+/// A recurring quiet-hours range in local time
+///
+/// A range whose end is not after its start runs past midnight into the next
+/// day, so `22:00`-`07:00` covers the night and equal times cover a full day.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct QuietHours {
+    /// Days the range starts on (e.g. `["Mon", "Fri"]`), every day if empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Local time the range starts at (e.g. `"22:00"`)
+    pub start: NaiveTime,
+    /// Local time the range ends at (e.g. `"07:00"`)
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    /// Check if the range starts on the given day
+    pub fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    /// Start and end of the range starting on `date`, if it runs that day
+    fn interval_on<Tz: TimeZone>(
+        &self,
+        tz: &Tz,
+        date: NaiveDate,
+    ) -> Option<(DateTime<Tz>, DateTime<Tz>)> {
+        if !self.starts_on(date.weekday()) {
+            return None;
+        }
+        let end_date = if self.end > self.start {
+            date
+        } else {
+            date.succ_opt()?
+        };
+        Some((
+            resolve_local(tz, date.and_time(self.start))?,
+            resolve_local(tz, end_date.and_time(self.end))?,
+        ))
+    }
+}
+
+/// Instant of a local wall-clock time
+///
+/// Times repeated when clocks go back resolve to their first occurrence,
+/// times skipped when clocks go forward to the first minute after the gap.
+fn resolve_local<Tz: TimeZone>(tz: &Tz, local: NaiveDateTime) -> Option<DateTime<Tz>> {
+    (0..=MAX_GAP_MINUTES).find_map(|minutes| {
+        tz.from_local_datetime(&(local + TimeDelta::minutes(minutes)))
+            .earliest()
+    })
+}
+
+/// Check if any quiet-hours range covers the given instant
+pub fn quiet_hours_active<Tz: TimeZone>(ranges: &[QuietHours], at: &DateTime<Tz>) -> bool {
+    let tz = &at.timezone();
+    let today = at.date_naive();
+    // A range that started yesterday may still run past midnight
+    [today.pred_opt(), Some(today)]
+        .into_iter()
+        .flatten()
+        .flat_map(|date| ranges.iter().filter_map(move |r| r.interval_on(tz, date)))
+        .any(|(start, end)| start <= *at && *at < end)
+}
+
+/// Starts and ends of quiet hours after the given instant, in order
+///
+/// Covers the next week; overlapping ranges may make some of them no
+/// transition at all.
+pub fn quiet_hours_edges<Tz: TimeZone>(
+    ranges: &[QuietHours],
+    at: &DateTime<Tz>,
+) -> Vec<DateTime<Tz>> {
+    let tz = &at.timezone();
+    let Some(first) = at.date_naive().pred_opt() else {
+        return Vec::new();
+    };
+    let mut edges: Vec<_> = (0..=LOOKAHEAD_DAYS)
+        .filter_map(|days| first.checked_add_days(Days::new(days)))
+        .flat_map(|date| ranges.iter().filter_map(move |r| r.interval_on(tz, date)))
+        .flat_map(|(start, end)| [start, end])
+        .filter(|edge| edge > at)
+        .collect();
+    edges.sort();
+    edges.dedup();
+    edges
+}
+
+/// Next instant at which quiet hours start or end
+pub fn next_quiet_hours_transition<Tz: TimeZone>(
+    ranges: &[QuietHours],
+    at: &DateTime<Tz>,
+) -> Option<DateTime<Tz>> {
+    let active = quiet_hours_active(ranges, at);
+    quiet_hours_edges(ranges, at)
+        .into_iter()
+        .find(|edge| quiet_hours_active(ranges, edge) != active)
+}
+
+/// Next occurrence of a local time, for do-not-disturb "until 14:00"
+///
+/// A time that already passed today means tomorrow.
+pub fn next_local_time<Tz: TimeZone>(now: &DateTime<Tz>, time: NaiveTime) -> Option<DateTime<Tz>> {
+    let tz = now.timezone();
+    let today = now.date_naive();
+    [Some(today), today.succ_opt()]
+        .into_iter()
+        .flatten()
+        .filter_map(|date| resolve_local(&tz, date.and_time(time)))
+        .find(|at| at > now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, MappedLocalTime, NaiveDate, Utc};
+
+    /// Central European time with its 2025 daylight saving switches
+    #[derive(Debug, Clone, Copy)]
+    struct Cet;
+
+    impl Cet {
+        const WINTER: i32 = 3600;
+        const SUMMER: i32 = 7200;
+
+        fn summer(utc: &NaiveDateTime) -> bool {
+            let start = NaiveDate::from_ymd_opt(2025, 3, 30)
+                .unwrap()
+                .and_hms_opt(1, 0, 0)
+                .unwrap();
+            let end = NaiveDate::from_ymd_opt(2025, 10, 26)
+                .unwrap()
+                .and_hms_opt(1, 0, 0)
+                .unwrap();
+            (start..end).contains(utc)
+        }
+    }
+
+    impl TimeZone for Cet {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Cet
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> MappedLocalTime<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_local_datetime(
+            &self,
+            local: &NaiveDateTime,
+        ) -> MappedLocalTime<FixedOffset> {
+            let offsets: Vec<_> = [Cet::SUMMER, Cet::WINTER]
+                .into_iter()
+                .map(|secs| FixedOffset::east_opt(secs).unwrap())
+                .filter(|offset| {
+                    let utc = *local - TimeDelta::seconds(offset.local_minus_utc().into());
+                    self.offset_from_utc_datetime(&utc) == *offset
+                })
+                .collect();
+            match offsets[..] {
+                [] => MappedLocalTime::None,
+                [offset] => MappedLocalTime::Single(offset),
+                [earliest, latest, ..] => MappedLocalTime::Ambiguous(earliest, latest),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            let secs = if Cet::summer(utc) {
+                Cet::SUMMER
+            } else {
+                Cet::WINTER
+            };
+            FixedOffset::east_opt(secs).unwrap()
+        }
+    }
+
+    fn time(hour: u32, min: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, min, 0).unwrap()
+    }
+
+    fn local(y: i32, m: u32, d: u32, hour: u32, min: u32) -> DateTime<Cet> {
+        Cet.with_ymd_and_hms(y, m, d, hour, min, 0)
+            .earliest()
+            .unwrap()
+    }
+
+    fn nightly() -> Vec<QuietHours> {
+        vec![QuietHours {
+            days: vec![],
+            start: time(22, 0),
+            end: time(7, 0),
+        }]
+    }
+
+    #[test]
+    fn test_range_across_midnight() {
+        let ranges = nightly();
+        // Wednesday 15 January 2025
+        assert!(!quiet_hours_active(&ranges, &local(2025, 1, 15, 21, 59)));
+        assert!(quiet_hours_active(&ranges, &local(2025, 1, 15, 22, 0)));
+        assert!(quiet_hours_active(&ranges, &local(2025, 1, 16, 3, 0)));
+        assert!(!quiet_hours_active(&ranges, &local(2025, 1, 16, 7, 0)));
+    }
+
+    #[test]
+    fn test_weekdays_are_start_days() {
+        let ranges = vec![QuietHours {
+            days: vec![Weekday::Fri],
+            start: time(22, 0),
+            end: time(9, 0),
+        }];
+        // Friday night runs into Saturday morning
+        assert!(quiet_hours_active(&ranges, &local(2025, 1, 17, 23, 0)));
+        assert!(quiet_hours_active(&ranges, &local(2025, 1, 18, 8, 0)));
+        // Saturday night is not covered
+        assert!(!quiet_hours_active(&ranges, &local(2025, 1, 18, 23, 0)));
+        assert!(!quiet_hours_active(&ranges, &local(2025, 1, 16, 23, 0)));
+    }
+
+    #[test]
+    fn test_equal_times_cover_whole_day() {
+        let ranges = vec![QuietHours {
+            days: vec![Weekday::Sun],
+            start: time(0, 0),
+            end: time(0, 0),
+        }];
+        assert!(quiet_hours_active(&ranges, &local(2025, 1, 19, 0, 0)));
+        assert!(quiet_hours_active(&ranges, &local(2025, 1, 19, 23, 59)));
+        assert!(!quiet_hours_active(&ranges, &local(2025, 1, 20, 0, 0)));
+    }
+
+    #[test]
+    fn test_next_transition() {
+        let ranges = nightly();
+        assert_eq!(
+            next_quiet_hours_transition(&ranges, &local(2025, 1, 15, 12, 0)),
+            Some(local(2025, 1, 15, 22, 0))
+        );
+        assert_eq!(
+            next_quiet_hours_transition(&ranges, &local(2025, 1, 15, 22, 0)),
+            Some(local(2025, 1, 16, 7, 0))
+        );
+        assert_eq!(
+            next_quiet_hours_transition(&[], &local(2025, 1, 15, 12, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_overlapping_ranges_merge() {
+        let mut ranges = nightly();
+        ranges.push(QuietHours {
+            days: vec![],
+            start: time(6, 0),
+            end: time(9, 0),
+        });
+        assert_eq!(
+            next_quiet_hours_transition(&ranges, &local(2025, 1, 15, 23, 0)),
+            Some(local(2025, 1, 16, 9, 0))
+        );
+    }
+
+    #[test]
+    fn test_dst_spring_forward() {
+        // Clocks jump from 02:00 to 03:00 on Sunday 30 March 2025
+        let ranges = vec![QuietHours {
+            days: vec![],
+            start: time(2, 30),
+            end: time(8, 0),
+        }];
+        let start = next_quiet_hours_transition(&ranges, &local(2025, 3, 30, 1, 0)).unwrap();
+        assert_eq!(start, local(2025, 3, 30, 3, 0));
+
+        // The night is an hour shorter in real time
+        let end = next_quiet_hours_transition(&ranges, &start).unwrap();
+        assert_eq!(end, local(2025, 3, 30, 8, 0));
+        assert_eq!(end - start, TimeDelta::hours(5));
+    }
+
+    #[test]
+    fn test_dst_fall_back() {
+        // Clocks go back from 03:00 to 02:00 on Sunday 26 October 2025
+        let ranges = nightly();
+        let start = local(2025, 10, 25, 22, 0);
+        let end = next_quiet_hours_transition(&ranges, &start).unwrap();
+        assert_eq!(end, local(2025, 10, 26, 7, 0));
+        assert_eq!(end - start, TimeDelta::hours(10));
+
+        // Both 02:30s are within quiet hours
+        let first = local(2025, 10, 26, 2, 30);
+        assert!(quiet_hours_active(&ranges, &first));
+        assert!(quiet_hours_active(&ranges, &(first + TimeDelta::hours(1))));
+    }
+
+    #[test]
+    fn test_next_local_time() {
+        let now = local(2025, 1, 15, 12, 0);
+        assert_eq!(
+            next_local_time(&now, time(14, 0)),
+            Some(local(2025, 1, 15, 14, 0))
+        );
+        assert_eq!(
+            next_local_time(&now, time(9, 0)),
+            Some(local(2025, 1, 16, 9, 0))
+        );
+        assert_eq!(
+            next_local_time(&now, time(12, 0)),
+            Some(local(2025, 1, 16, 12, 0))
+        );
+
+        let utc = Utc.with_ymd_and_hms(2025, 1, 15, 23, 0, 0).unwrap();
+        assert_eq!(
+            next_local_time(&utc, time(1, 0)),
+            Some(Utc.with_ymd_and_hms(2025, 1, 16, 1, 0, 0).unwrap())
+        );
+    }
+}
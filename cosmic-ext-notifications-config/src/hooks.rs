@@ -0,0 +1,71 @@
+//! Executables run when notifications arrive or close
+//!
+//! Hooks are attached to per-app rules and content rules. The daemon runs
+//! them directly, never through a shell, and passes the notification in
+//! environment variables and as JSON on stdin rather than on the command
+//! line.
+
+use std::path::PathBuf;
+
+/// When a hook runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum HookEvent {
+    /// A notification arrived or replaced an open one
+    Notified,
+    /// A notification was closed, for any reason
+    Closed,
+}
+
+/// An executable run for matching notifications
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Hook {
+    /// Executable to run, looked up in `PATH` if it has no slash
+    pub command: PathBuf,
+    /// Fixed arguments, passed as they are
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Events that run the hook (default: `Notified`)
+    #[serde(default = "default_hook_events")]
+    pub on: Vec<HookEvent>,
+    /// Seconds the hook may run before it is killed (default: 10)
+    #[serde(default = "default_hook_timeout")]
+    pub timeout_secs: u32,
+}
+
+impl Hook {
+    pub fn runs_on(&self, event: HookEvent) -> bool {
+        self.on.contains(&event)
+    }
+}
+
+fn default_hook_events() -> Vec<HookEvent> {
+    vec![HookEvent::Notified]
+}
+
+const fn default_hook_timeout() -> u32 {
+    10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_deserialization_with_defaults() {
+        let hook: Hook = serde_json::from_str(r#"{"command":"/usr/bin/pager-log"}"#).unwrap();
+        assert_eq!(hook.command, PathBuf::from("/usr/bin/pager-log"));
+        assert!(hook.args.is_empty());
+        assert!(hook.runs_on(HookEvent::Notified));
+        assert!(!hook.runs_on(HookEvent::Closed));
+        assert_eq!(hook.timeout_secs, 10);
+
+        let hook: Hook = serde_json::from_str(
+            r#"{"command":"lights","args":["red"],"on":["Closed"],"timeout_secs":2}"#,
+        )
+        .unwrap();
+        assert_eq!(hook.args, ["red"]);
+        assert!(hook.runs_on(HookEvent::Closed));
+        assert!(!hook.runs_on(HookEvent::Notified));
+        assert_eq!(hook.timeout_secs, 2);
+    }
+}
@@ -1,5 +1,14 @@
+use chrono::{DateTime, TimeZone, Utc};
 use cosmic_config::{CosmicConfigEntry, cosmic_config_derive::CosmicConfigEntry};
 
+pub mod dnd;
+pub mod hooks;
+pub mod rules;
+
+pub use dnd::{DndMode, DndOutcome, QuietHours};
+pub use hooks::{Hook, HookEvent};
+pub use rules::{AppPattern, Evaluation, Rule, RuleAction, RuleError, RuleOutcome, Rules, Subject};
+
 pub const ID: &str = "io.github.olafkfreund.CosmicExtNotifications";
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
@@ -26,6 +35,37 @@ pub enum GroupingMode {
     ByCategory,
 }
 
+/// Token-bucket rate limit for notifications from one app
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct RateLimit {
+    /// Notifications allowed per minute on average, 0 disables the limit
+    pub per_minute: u32,
+    /// Notifications allowed in a quick burst before the average applies
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            per_minute: 60,
+            burst: 60,
+        }
+    }
+}
+
+/// What happens to notifications over the rate limit
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum RateLimitPolicy {
+    /// Reject them
+    Drop,
+    /// Reject them, but count them on one summary card per app
+    /// (e.g. "Slack: 14 more notifications")
+    #[default]
+    Coalesce,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct AppRule {
     /// The app_name to match (from notification)
@@ -42,6 +82,21 @@ pub struct AppRule {
     pub sound_enabled: bool,
     /// Override timeout in milliseconds
     pub timeout_override: Option<u32>,
+    /// Override the global rate limit
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    /// Let this app through do-not-disturb in `AllowList` mode
+    #[serde(default)]
+    pub dnd_allow: bool,
+    /// Override whether this app's notifications play a sound while suppressed
+    #[serde(default)]
+    pub dnd_sound: Option<bool>,
+    /// Override whether this app's notifications are kept in history while suppressed
+    #[serde(default)]
+    pub dnd_history: Option<bool>,
+    /// Executables to run when this app's notifications arrive or close
+    #[serde(default)]
+    pub hooks: Vec<Hook>,
 }
 
 impl Default for AppRule {
@@ -53,6 +108,11 @@ impl Default for AppRule {
             urgency_override: None,
             sound_enabled: true,
             timeout_override: None,
+            rate_limit: None,
+            dnd_allow: false,
+            dnd_sound: None,
+            dnd_history: None,
+            hooks: Vec::new(),
         }
     }
 }
@@ -61,6 +121,25 @@ impl Default for AppRule {
 #[version = 3]
 pub struct NotificationsConfig {
     pub do_not_disturb: bool,
+    /// When manual do-not-disturb turns itself off, `None` to keep it on
+    #[serde(default)]
+    pub do_not_disturb_until: Option<DateTime<Utc>>,
+    /// Recurring local-time ranges during which do-not-disturb is on
+    #[serde(default)]
+    pub quiet_hours: Vec<QuietHours>,
+    /// Which notifications break through do-not-disturb (default: Silence)
+    #[serde(default)]
+    pub dnd_mode: DndMode,
+    /// Categories let through in `AllowList` mode, e.g. `call.incoming` or
+    /// the whole `call` class
+    #[serde(default)]
+    pub dnd_allowed_categories: Vec<String>,
+    /// Whether suppressed notifications still play a sound (default: false)
+    #[serde(default)]
+    pub dnd_sound: bool,
+    /// Whether suppressed notifications are kept in history (default: true)
+    #[serde(default = "default_true")]
+    pub dnd_history: bool,
     pub anchor: Anchor,
     /// The maximum number of notifications that can be displayed at once.
     pub max_notifications: u32,
@@ -94,6 +173,11 @@ pub struct NotificationsConfig {
     #[serde(default)]
     pub app_rules: Vec<AppRule>,
 
+    /// Rules matching notifications by app pattern, category, urgency,
+    /// content and hints
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+
     /// How to group notifications
     #[serde(default)]
     pub grouping_mode: GroupingMode,
@@ -105,12 +189,62 @@ pub struct NotificationsConfig {
     /// Whether to show group count badge (e.g., "Firefox (3)")
     #[serde(default = "default_true")]
     pub show_group_count: bool,
+
+    /// Whether card timers pause while the pointer hovers a card (default: true)
+    #[serde(default = "default_true")]
+    pub pause_on_hover: bool,
+
+    /// Whether to show a bar counting down until a card expires (default: false)
+    #[serde(default)]
+    pub show_countdown: bool,
+
+    /// Whether notification history is kept on disk across restarts
+    /// (default: false)
+    ///
+    /// Bodies can hold one-time codes and private messages, so this is
+    /// opt-in.
+    #[serde(default)]
+    pub persist_history: bool,
+
+    /// Maximum number of notifications kept in history (default: 500)
+    #[serde(default = "default_history_max_count")]
+    pub history_max_count: u32,
+
+    /// Maximum age of history entries in days, 0 keeps them forever (default: 30)
+    #[serde(default = "default_history_max_age_days")]
+    pub history_max_age_days: u32,
+
+    /// Maximum disk space used by history, including images, in MiB (default: 50)
+    #[serde(default = "default_history_max_size_mb")]
+    pub history_max_size_mb: u32,
+
+    /// Rate limit for each app, unless its rule overrides it (default: 60 per minute, bursts of 60)
+    ///
+    /// This replaced a fixed window of 60 notifications per minute; the default
+    /// burst of 60 lets the same 60 through at once, and refills them gradually.
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+
+    /// What happens to notifications over the rate limit (default: Coalesce)
+    #[serde(default)]
+    pub rate_limit_policy: RateLimitPolicy,
+
+    /// Whether events are streamed as JSON lines on a Unix socket in
+    /// `$XDG_RUNTIME_DIR` (default: false)
+    #[serde(default)]
+    pub event_socket: bool,
 }
 
 impl Default for NotificationsConfig {
     fn default() -> Self {
         Self {
             do_not_disturb: false,
+            do_not_disturb_until: None,
+            quiet_hours: Vec::new(),
+            dnd_mode: DndMode::default(),
+            dnd_allowed_categories: Vec::new(),
+            dnd_sound: false,
+            dnd_history: default_true(),
             anchor: Anchor::default(),
             max_notifications: 3,
             max_per_app: 2,
@@ -123,9 +257,19 @@ impl Default for NotificationsConfig {
             enable_links: default_true(),
             enable_animations: default_true(),
             app_rules: Vec::new(),
+            rules: Vec::new(),
             grouping_mode: GroupingMode::default(),
             max_per_group: default_max_per_group(),
             show_group_count: default_true(),
+            pause_on_hover: default_true(),
+            show_countdown: false,
+            persist_history: false,
+            history_max_count: default_history_max_count(),
+            history_max_age_days: default_history_max_age_days(),
+            history_max_size_mb: default_history_max_size_mb(),
+            rate_limit: RateLimit::default(),
+            rate_limit_policy: RateLimitPolicy::default(),
+            event_socket: false,
         }
     }
 }
@@ -133,9 +277,23 @@ impl Default for NotificationsConfig {
 impl NotificationsConfig {
     /// Find a rule matching the given app_name and optional desktop_entry
     pub fn find_app_rule(&self, app_name: &str, desktop_entry: Option<&str>) -> Option<&AppRule> {
+        self.find_app_rule_by_entries(app_name, desktop_entry.as_slice())
+    }
+
+    /// Find a rule matching the given app_name or any of the desktop entries
+    /// a notification may belong to, trying the entries in order
+    pub fn find_app_rule_by_entries(
+        &self,
+        app_name: &str,
+        desktop_entries: &[&str],
+    ) -> Option<&AppRule> {
         // First try to match by desktop_entry (more specific)
-        if let Some(entry) = desktop_entry {
-            if let Some(rule) = self.app_rules.iter().find(|r| r.desktop_entry.as_deref() == Some(entry)) {
+        for entry in desktop_entries {
+            if let Some(rule) = self
+                .app_rules
+                .iter()
+                .find(|r| r.desktop_entry.as_deref() == Some(*entry))
+            {
                 return Some(rule);
             }
         }
@@ -150,6 +308,87 @@ impl NotificationsConfig {
             .unwrap_or(true)
     }
 
+    /// Check if manual do-not-disturb is on and has not run out
+    pub fn manual_dnd_active_at<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> bool {
+        self.do_not_disturb && self.do_not_disturb_until.is_none_or(|until| *now < until)
+    }
+
+    /// Check if do-not-disturb is in effect, manually or from quiet hours
+    pub fn dnd_active_at<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> bool {
+        self.manual_dnd_active_at(now) || dnd::quiet_hours_active(&self.quiet_hours, now)
+    }
+
+    /// Next instant do-not-disturb may change: quiet hours start or end, or
+    /// manual do-not-disturb runs out
+    pub fn next_dnd_transition<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<DateTime<Utc>> {
+        let manual_end = self
+            .do_not_disturb_until
+            .filter(|until| self.do_not_disturb && *now < *until);
+        let scheduled = dnd::next_quiet_hours_transition(&self.quiet_hours, now)
+            .map(|at| at.with_timezone(&Utc));
+        manual_end.into_iter().chain(scheduled).min()
+    }
+
+    /// When do-not-disturb turns off again, if it is on and will end by itself
+    ///
+    /// Manual do-not-disturb without an end never does, and neither do quiet
+    /// hours covering the whole week.
+    pub fn dnd_ends_at<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<DateTime<Utc>> {
+        // Manual do-not-disturb running into quiet hours, or back-to-back
+        // ranges, are several transitions that keep it on
+        const MAX_TRANSITIONS: usize = 16;
+
+        if !self.dnd_active_at(now) {
+            return None;
+        }
+        let tz = now.timezone();
+        let mut at = now.clone();
+        for _ in 0..MAX_TRANSITIONS {
+            let next = self.next_dnd_transition(&at)?.with_timezone(&tz);
+            if !self.dnd_active_at(&next) {
+                return Some(next.with_timezone(&Utc));
+            }
+            at = next;
+        }
+        None
+    }
+
+    /// What do-not-disturb does with a notification while it is on
+    ///
+    /// `rule` is the sending app's rule; suppressed notifications play a
+    /// sound and go to history as it overrides.
+    pub fn dnd_outcome(
+        &self,
+        rule: Option<&AppRule>,
+        urgency: u8,
+        category: Option<&str>,
+    ) -> DndOutcome {
+        let breaks_through = match self.dnd_mode {
+            DndMode::Silence => false,
+            DndMode::CriticalOnly => urgency >= 2,
+            DndMode::AllowList => {
+                rule.is_some_and(|r| r.dnd_allow)
+                    || category.is_some_and(|category| {
+                        self.dnd_allowed_categories
+                            .iter()
+                            .any(|allowed| dnd::category_matches(category, allowed))
+                    })
+            }
+        };
+        if breaks_through {
+            return DndOutcome::Show;
+        }
+        DndOutcome::Suppress {
+            sound: rule.and_then(|r| r.dnd_sound).unwrap_or(self.dnd_sound),
+            history: rule.and_then(|r| r.dnd_history).unwrap_or(self.dnd_history),
+        }
+    }
+
+    /// Rate limit for an app, from its rule or the global setting
+    pub fn rate_limit_for(&self, rule: Option<&AppRule>) -> RateLimit {
+        rule.and_then(|r| r.rate_limit).unwrap_or(self.rate_limit)
+    }
+
     /// Check if sounds are enabled for an app
     pub fn is_sound_enabled_for_app(&self, app_name: &str, desktop_entry: Option<&str>) -> bool {
         self.find_app_rule(app_name, desktop_entry)
@@ -171,6 +410,18 @@ const fn default_max_per_group() -> u32 {
     3
 }
 
+const fn default_history_max_count() -> u32 {
+    500
+}
+
+const fn default_history_max_age_days() -> u32 {
+    30
+}
+
+const fn default_history_max_size_mb() -> u32 {
+    50
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -193,6 +444,37 @@ mod tests {
         assert_eq!(config.max_image_size, 128);
         assert!(config.enable_links);
         assert!(config.enable_animations);
+
+        // Card timer fields
+        assert!(config.pause_on_hover);
+        assert!(!config.show_countdown);
+
+        // History fields
+        assert!(!config.persist_history);
+        assert_eq!(config.history_max_count, 500);
+        assert_eq!(config.history_max_age_days, 30);
+        assert_eq!(config.history_max_size_mb, 50);
+
+        // Do-not-disturb fields
+        assert_eq!(config.do_not_disturb_until, None);
+        assert!(config.quiet_hours.is_empty());
+        assert_eq!(config.dnd_mode, DndMode::Silence);
+        assert!(config.dnd_allowed_categories.is_empty());
+        assert!(!config.dnd_sound);
+        assert!(config.dnd_history);
+
+        // Rate limit fields
+        assert_eq!(
+            config.rate_limit,
+            RateLimit {
+                per_minute: 60,
+                burst: 60
+            }
+        );
+        assert_eq!(config.rate_limit_policy, RateLimitPolicy::Coalesce);
+
+        assert!(!config.event_socket);
+        assert!(config.rules.is_empty());
     }
 
     #[test]
@@ -233,6 +515,10 @@ mod tests {
         assert_eq!(config.max_image_size, 128);
         assert!(config.enable_links);
         assert!(config.enable_animations);
+        assert!(config.pause_on_hover);
+        assert!(!config.show_countdown);
+        assert!(!config.persist_history);
+        assert_eq!(config.history_max_count, 500);
     }
 
     #[test]
@@ -398,6 +684,7 @@ mod tests {
         assert_eq!(rule.urgency_override, None);
         assert!(rule.sound_enabled);
         assert_eq!(rule.timeout_override, None);
+        assert!(rule.hooks.is_empty());
     }
 
     #[test]
@@ -410,6 +697,7 @@ mod tests {
             urgency_override: Some(1),
             sound_enabled: false,
             timeout_override: Some(10000),
+            ..Default::default()
         });
 
         // Should find rule by app_name
@@ -433,6 +721,7 @@ mod tests {
             urgency_override: Some(2),
             sound_enabled: false,
             timeout_override: Some(15000),
+            ..Default::default()
         });
 
         // Should find rule by desktop_entry
@@ -449,6 +738,28 @@ mod tests {
         assert!(rule.is_none());
     }
 
+    #[test]
+    fn test_find_app_rule_by_any_desktop_entry() {
+        let mut config = NotificationsConfig::default();
+        config.app_rules.push(AppRule {
+            app_name: "Terminal".to_string(),
+            desktop_entry: Some("com.system76.CosmicTerm".to_string()),
+            ..Default::default()
+        });
+        config.app_rules.push(AppRule {
+            app_name: "firefox".to_string(),
+            desktop_entry: Some("firefox".to_string()),
+            ..Default::default()
+        });
+
+        // The first entry with a rule wins
+        let rule = config.find_app_rule_by_entries("", &["firefox", "com.system76.CosmicTerm"]);
+        assert_eq!(rule.unwrap().app_name, "firefox");
+        let rule = config.find_app_rule_by_entries("", &["unknown", "com.system76.CosmicTerm"]);
+        assert_eq!(rule.unwrap().app_name, "Terminal");
+        assert!(config.find_app_rule_by_entries("", &[]).is_none());
+    }
+
     #[test]
     fn test_app_rule_precedence() {
         let mut config = NotificationsConfig::default();
@@ -461,6 +772,7 @@ mod tests {
             urgency_override: Some(0),
             sound_enabled: true,
             timeout_override: Some(5000),
+            ..Default::default()
         });
 
         // Add specific desktop_entry rule
@@ -471,6 +783,7 @@ mod tests {
             urgency_override: Some(2),
             sound_enabled: false,
             timeout_override: Some(10000),
+            ..Default::default()
         });
 
         // Desktop entry rule should take precedence
@@ -496,6 +809,7 @@ mod tests {
             urgency_override: None,
             sound_enabled: true,
             timeout_override: None,
+            ..Default::default()
         });
 
         // Disabled app
@@ -515,6 +829,7 @@ mod tests {
             urgency_override: None,
             sound_enabled: false,
             timeout_override: None,
+            ..Default::default()
         });
 
         // Sound disabled for specific app
@@ -533,6 +848,7 @@ mod tests {
             urgency_override: Some(1),
             sound_enabled: false,
             timeout_override: Some(8000),
+            ..Default::default()
         };
 
         let json = serde_json::to_string(&rule).unwrap();
@@ -569,6 +885,7 @@ mod tests {
             urgency_override: Some(2),
             sound_enabled: false,
             timeout_override: Some(10000),
+            ..Default::default()
         });
 
         let json = serde_json::to_string(&config).unwrap();
@@ -579,6 +896,177 @@ mod tests {
         assert!(json.contains("firefox.desktop"));
     }
 
+    #[test]
+    fn test_manual_dnd_until() {
+        let now = Utc.with_ymd_and_hms(2025, 1, 15, 12, 0, 0).unwrap();
+        let mut config = NotificationsConfig {
+            do_not_disturb: true,
+            ..Default::default()
+        };
+        assert!(config.dnd_active_at(&now));
+        assert_eq!(config.next_dnd_transition(&now), None);
+
+        // "For 1 hour"
+        let until = now + chrono::TimeDelta::hours(1);
+        config.do_not_disturb_until = Some(until);
+        assert!(config.dnd_active_at(&now));
+        assert!(!config.dnd_active_at(&until));
+        assert_eq!(config.next_dnd_transition(&now), Some(until));
+        assert_eq!(config.next_dnd_transition(&until), None);
+    }
+
+    #[test]
+    fn test_dnd_from_quiet_hours() {
+        let config: NotificationsConfig = serde_json::from_str(
+            r#"{
+                "do_not_disturb": false,
+                "anchor": "Top",
+                "max_notifications": 3,
+                "max_per_app": 2,
+                "max_timeout_urgent": null,
+                "max_timeout_normal": 5000,
+                "max_timeout_low": 3000,
+                "quiet_hours": [
+                    { "days": ["Mon", "Tuesday"], "start": "22:00", "end": "07:00" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.quiet_hours.len(), 1);
+
+        // Monday 13 January 2025
+        let evening = Utc.with_ymd_and_hms(2025, 1, 13, 21, 0, 0).unwrap();
+        assert!(!config.dnd_active_at(&evening));
+        let start = Utc.with_ymd_and_hms(2025, 1, 13, 22, 0, 0).unwrap();
+        assert_eq!(config.next_dnd_transition(&evening), Some(start));
+        assert!(config.dnd_active_at(&start));
+
+        // Wednesday night is free
+        let wednesday = Utc.with_ymd_and_hms(2025, 1, 15, 23, 0, 0).unwrap();
+        assert!(!config.dnd_active_at(&wednesday));
+    }
+
+    #[test]
+    fn test_dnd_ends_at() {
+        let evening = Utc.with_ymd_and_hms(2025, 1, 13, 21, 0, 0).unwrap();
+        let mut config = NotificationsConfig {
+            quiet_hours: vec![QuietHours {
+                days: vec![],
+                start: chrono::NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+                end: chrono::NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(config.dnd_ends_at(&evening), None);
+
+        // Manual do-not-disturb running into quiet hours lasts until morning
+        config.do_not_disturb = true;
+        config.do_not_disturb_until = Some(Utc.with_ymd_and_hms(2025, 1, 13, 23, 0, 0).unwrap());
+        assert_eq!(
+            config.dnd_ends_at(&evening),
+            Some(Utc.with_ymd_and_hms(2025, 1, 14, 7, 0, 0).unwrap())
+        );
+
+        // Without an end it stays on
+        config.do_not_disturb_until = None;
+        assert_eq!(config.dnd_ends_at(&evening), None);
+    }
+
+    #[test]
+    fn test_dnd_modes() {
+        let mut config = NotificationsConfig {
+            dnd_allowed_categories: vec!["call".to_string(), "x-calendar.reminder".to_string()],
+            ..Default::default()
+        };
+        let silenced = DndOutcome::Suppress {
+            sound: false,
+            history: true,
+        };
+
+        // Silence holds back even critical notifications
+        assert_eq!(config.dnd_outcome(None, 2, None), silenced);
+
+        config.dnd_mode = DndMode::CriticalOnly;
+        assert_eq!(config.dnd_outcome(None, 2, None), DndOutcome::Show);
+        assert_eq!(config.dnd_outcome(None, 1, Some("call.incoming")), silenced);
+
+        config.dnd_mode = DndMode::AllowList;
+        assert_eq!(
+            config.dnd_outcome(None, 1, Some("call.incoming")),
+            DndOutcome::Show
+        );
+        assert_eq!(
+            config.dnd_outcome(None, 1, Some("x-calendar.reminder")),
+            DndOutcome::Show
+        );
+        assert_eq!(config.dnd_outcome(None, 1, Some("callback")), silenced);
+        assert_eq!(config.dnd_outcome(None, 2, Some("email.arrived")), silenced);
+
+        let allowed = AppRule {
+            app_name: "Alarm Clock".to_string(),
+            dnd_allow: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.dnd_outcome(Some(&allowed), 0, None),
+            DndOutcome::Show
+        );
+    }
+
+    #[test]
+    fn test_dnd_suppression_overrides() {
+        let config = NotificationsConfig {
+            dnd_sound: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.dnd_outcome(None, 1, None),
+            DndOutcome::Suppress {
+                sound: true,
+                history: true,
+            }
+        );
+
+        let rule = AppRule {
+            app_name: "Steam".to_string(),
+            dnd_sound: Some(false),
+            dnd_history: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.dnd_outcome(Some(&rule), 1, None),
+            DndOutcome::Suppress {
+                sound: false,
+                history: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_for_app_rule() {
+        let mut config = NotificationsConfig::default();
+        config.app_rules.push(AppRule {
+            app_name: "slack".to_string(),
+            rate_limit: Some(RateLimit {
+                per_minute: 6,
+                burst: 2,
+            }),
+            ..Default::default()
+        });
+
+        let rule = config.find_app_rule("slack", None);
+        assert_eq!(
+            config.rate_limit_for(rule),
+            RateLimit {
+                per_minute: 6,
+                burst: 2
+            }
+        );
+
+        let rule = config.find_app_rule("firefox", None);
+        assert_eq!(config.rate_limit_for(rule), config.rate_limit);
+    }
+
     #[test]
     fn test_config_backward_compatibility_without_app_rules() {
         // Old config without app_rules field (version 2)
@@ -669,6 +1157,7 @@ mod tests {
             urgency_override: Some(0),
             sound_enabled: true,
             timeout_override: None,
+            ..Default::default()
         });
 
         // Test normal urgency override
@@ -679,6 +1168,7 @@ mod tests {
             urgency_override: Some(1),
             sound_enabled: true,
             timeout_override: None,
+            ..Default::default()
         });
 
         // Test critical urgency override
@@ -689,6 +1179,7 @@ mod tests {
             urgency_override: Some(2),
             sound_enabled: true,
             timeout_override: None,
+            ..Default::default()
         });
 
         let low = config.find_app_rule("low-priority", None);
@@ -0,0 +1,549 @@
+//! Content-matching notification rules
+//!
+//! Unlike per-app rules, which match one app exactly, a rule matches any
+//! combination of app pattern, category, urgency, summary and body regexes
+//! and hint names, and then applies a list of actions. Rules are compiled
+//! once per configuration with [`Rules::new`] and evaluated against each
+//! incoming notification, or dry-run with [`Rules::evaluate`].
+
+use crate::{Hook, dnd::category_matches};
+use regex::{Regex, RegexBuilder};
+use std::fmt;
+
+/// Largest compiled size of one rule's regex, in bytes
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// Pattern an app name or desktop entry is matched against
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum AppPattern {
+    /// Whole-name glob, where `*` matches any run of characters and `?` one
+    /// character (e.g. `org.gnome.*`)
+    Glob(String),
+    /// Regex found anywhere in the name, anchor it with `^` and `$` to match
+    /// the whole name
+    Regex(String),
+}
+
+/// What a matching rule does to a notification
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum RuleAction {
+    /// Reject the notification
+    Drop,
+    /// Do not play its sound
+    Mute,
+    /// Change its urgency (0=low, 1=normal, 2=critical)
+    SetUrgency(u8),
+    /// Mark it transient, so it is not saved to disk or sent to applets
+    ForceTransient,
+    /// Show it, but do not keep it in history once its card closes
+    SkipHistory,
+    /// Show it for this many milliseconds, whatever the urgency maximums say
+    SetTimeout(u32),
+    /// Remove its body text
+    RedactBody,
+    /// Put it in history without showing a card
+    HistoryOnly,
+}
+
+/// A content-matching rule
+///
+/// Every condition that is set must hold for the rule to match; a rule
+/// without conditions matches every notification.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Rule {
+    /// Name shown in logs and dry runs, the rule's position if empty
+    #[serde(default)]
+    pub name: String,
+    /// Whether the rule is evaluated (default: true)
+    #[serde(default = "crate::default_true")]
+    pub enabled: bool,
+    /// Rules with higher priorities are evaluated first, rules of equal
+    /// priority in the order they are listed (default: 0)
+    #[serde(default)]
+    pub priority: i32,
+    /// App name, resolved app ID or desktop entry to match
+    #[serde(default)]
+    pub app: Option<AppPattern>,
+    /// Category to match, a class like `im` also matching `im.received`
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Urgency levels to match, any if empty
+    #[serde(default)]
+    pub urgency: Vec<u8>,
+    /// Regex found anywhere in the summary
+    #[serde(default)]
+    pub summary: Option<String>,
+    /// Regex found anywhere in the body
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Hints that must all be present (e.g. `image-data`)
+    #[serde(default)]
+    pub hints: Vec<String>,
+    /// What to do with matching notifications
+    #[serde(default)]
+    pub actions: Vec<RuleAction>,
+    /// Executables to run when matching notifications arrive or close
+    #[serde(default)]
+    pub hooks: Vec<Hook>,
+    /// Skip the rules after this one when it matches (default: false)
+    #[serde(default)]
+    pub stop: bool,
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            enabled: true,
+            priority: 0,
+            app: None,
+            category: None,
+            urgency: Vec::new(),
+            summary: None,
+            body: None,
+            hints: Vec::new(),
+            actions: Vec::new(),
+            hooks: Vec::new(),
+            stop: false,
+        }
+    }
+}
+
+/// The parts of a notification rules look at
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Subject<'a> {
+    pub app_name: &'a str,
+    /// App IDs the notification may belong to, from the sender's identity
+    /// and the `desktop-entry` hint
+    pub desktop_entries: &'a [&'a str],
+    pub category: Option<&'a str>,
+    pub urgency: u8,
+    pub summary: &'a str,
+    pub body: &'a str,
+    /// Names of the hints the notification carries
+    pub hints: &'a [&'a str],
+}
+
+/// Combined effect of the rules matching a notification
+///
+/// Where matching rules disagree on a value, the first one evaluated wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct RuleOutcome {
+    pub drop: bool,
+    pub mute: bool,
+    pub urgency: Option<u8>,
+    pub transient: bool,
+    pub skip_history: bool,
+    pub timeout: Option<u32>,
+    pub redact_body: bool,
+    pub history_only: bool,
+    /// Hooks of every matching rule
+    pub hooks: Vec<Hook>,
+}
+
+impl RuleOutcome {
+    fn apply(&mut self, action: &RuleAction) {
+        match action {
+            RuleAction::Drop => self.drop = true,
+            RuleAction::Mute => self.mute = true,
+            RuleAction::SetUrgency(urgency) => {
+                self.urgency.get_or_insert((*urgency).min(2));
+            }
+            RuleAction::ForceTransient => self.transient = true,
+            RuleAction::SkipHistory => self.skip_history = true,
+            RuleAction::SetTimeout(timeout) => {
+                self.timeout.get_or_insert(*timeout);
+            }
+            RuleAction::RedactBody => self.redact_body = true,
+            RuleAction::HistoryOnly => self.history_only = true,
+        }
+    }
+}
+
+/// Result of a dry run: which rules matched, in evaluation order, and what
+/// they do together
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Evaluation {
+    pub matched: Vec<String>,
+    pub outcome: RuleOutcome,
+}
+
+/// A rule that could not be compiled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleError {
+    /// Name of the rule
+    pub rule: String,
+    pub message: String,
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rule {}: {}", self.rule, self.message)
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    name: String,
+    app: Option<Regex>,
+    category: Option<String>,
+    urgency: Vec<u8>,
+    summary: Option<Regex>,
+    body: Option<Regex>,
+    hints: Vec<String>,
+    actions: Vec<RuleAction>,
+    hooks: Vec<Hook>,
+    stop: bool,
+}
+
+impl CompiledRule {
+    fn new(index: usize, rule: &Rule) -> Result<Self, RuleError> {
+        let name = if rule.name.is_empty() {
+            format!("#{}", index + 1)
+        } else {
+            rule.name.clone()
+        };
+        let compile = |pattern: &str| {
+            RegexBuilder::new(pattern)
+                .size_limit(REGEX_SIZE_LIMIT)
+                .build()
+                .map_err(|err| RuleError {
+                    rule: name.clone(),
+                    message: err.to_string(),
+                })
+        };
+
+        let app = match &rule.app {
+            Some(AppPattern::Glob(glob)) => Some(compile(&glob_to_regex(glob))?),
+            Some(AppPattern::Regex(regex)) => Some(compile(regex)?),
+            None => None,
+        };
+        let summary = rule.summary.as_deref().map(compile).transpose()?;
+        let body = rule.body.as_deref().map(compile).transpose()?;
+
+        Ok(Self {
+            app,
+            category: rule.category.clone(),
+            urgency: rule.urgency.clone(),
+            summary,
+            body,
+            hints: rule.hints.clone(),
+            actions: rule.actions.clone(),
+            hooks: rule.hooks.clone(),
+            stop: rule.stop,
+            name,
+        })
+    }
+
+    fn matches(&self, subject: &Subject<'_>) -> bool {
+        self.app.as_ref().is_none_or(|app| {
+            app.is_match(subject.app_name)
+                || subject
+                    .desktop_entries
+                    .iter()
+                    .any(|entry| app.is_match(entry))
+        }) && self.category.as_deref().is_none_or(|allowed| {
+            subject
+                .category
+                .is_some_and(|category| category_matches(category, allowed))
+        }) && (self.urgency.is_empty() || self.urgency.contains(&subject.urgency))
+            && self
+                .summary
+                .as_ref()
+                .is_none_or(|re| re.is_match(subject.summary))
+            && self
+                .body
+                .as_ref()
+                .is_none_or(|re| re.is_match(subject.body))
+            && self
+                .hints
+                .iter()
+                .all(|hint| subject.hints.contains(&hint.as_str()))
+    }
+}
+
+/// Anchored regex matching the same names as a glob
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Enabled rules compiled and sorted for evaluation
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    rules: Vec<CompiledRule>,
+}
+
+impl Rules {
+    /// Compile the enabled rules, skipping and returning the ones with an
+    /// invalid pattern
+    pub fn new(rules: &[Rule]) -> (Self, Vec<RuleError>) {
+        let mut sorted: Vec<_> = rules
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.enabled)
+            .collect();
+        // Stable, so equal priorities keep their order
+        sorted.sort_by_key(|(_, r)| std::cmp::Reverse(r.priority));
+
+        let mut compiled = Vec::with_capacity(sorted.len());
+        let mut errors = Vec::new();
+        for (index, rule) in sorted {
+            match CompiledRule::new(index, rule) {
+                Ok(rule) => compiled.push(rule),
+                Err(err) => errors.push(err),
+            }
+        }
+        (Self { rules: compiled }, errors)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Evaluate the rules against a notification
+    ///
+    /// Conditions are checked against the notification as it arrived, so
+    /// one rule changing the urgency does not change which others match.
+    pub fn evaluate(&self, subject: &Subject<'_>) -> Evaluation {
+        let mut evaluation = Evaluation::default();
+        for rule in self.rules.iter().filter(|rule| rule.matches(subject)) {
+            evaluation.matched.push(rule.name.clone());
+            for action in &rule.actions {
+                evaluation.outcome.apply(action);
+            }
+            evaluation.outcome.hooks.extend_from_slice(&rule.hooks);
+            if rule.stop {
+                break;
+            }
+        }
+        evaluation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(summary: &str) -> Subject<'_> {
+        Subject {
+            app_name: "GitLab CI",
+            desktop_entries: &["org.gitlab.Runner"],
+            category: Some("x-ci.build"),
+            urgency: 1,
+            summary,
+            body: "Pipeline #42 on main",
+            hints: &["desktop-entry", "category"],
+        }
+    }
+
+    fn hook(command: &str) -> Hook {
+        Hook {
+            command: command.into(),
+            args: Vec::new(),
+            on: vec![crate::HookEvent::Notified],
+            timeout_secs: 10,
+        }
+    }
+
+    fn rules(rules: Vec<Rule>) -> Rules {
+        let (rules, errors) = Rules::new(&rules);
+        assert_eq!(errors, []);
+        rules
+    }
+
+    #[test]
+    fn test_silence_success_escalate_failure() {
+        let rules = rules(vec![
+            Rule {
+                name: "Quiet green builds".to_string(),
+                app: Some(AppPattern::Glob("GitLab*".to_string())),
+                summary: Some("Build succeeded".to_string()),
+                actions: vec![RuleAction::Mute, RuleAction::HistoryOnly],
+                ..Rule::default()
+            },
+            Rule {
+                name: "Loud red builds".to_string(),
+                app: Some(AppPattern::Glob("GitLab*".to_string())),
+                summary: Some("Build failed".to_string()),
+                actions: vec![RuleAction::SetUrgency(2), RuleAction::SetTimeout(0)],
+                ..Rule::default()
+            },
+        ]);
+
+        let quiet = rules.evaluate(&build("Build succeeded"));
+        assert_eq!(quiet.matched, ["Quiet green builds"]);
+        assert!(quiet.outcome.mute && quiet.outcome.history_only);
+        assert_eq!(quiet.outcome.urgency, None);
+
+        let loud = rules.evaluate(&build("Build failed"));
+        assert_eq!(loud.matched, ["Loud red builds"]);
+        assert_eq!(loud.outcome.urgency, Some(2));
+        assert_eq!(loud.outcome.timeout, Some(0));
+        assert!(!loud.outcome.mute);
+
+        assert_eq!(
+            rules.evaluate(&build("Build started")),
+            Evaluation::default()
+        );
+    }
+
+    #[test]
+    fn test_conditions() {
+        let matches = |rule: Rule| {
+            !rules(vec![Rule {
+                actions: vec![RuleAction::Drop],
+                ..rule
+            }])
+            .evaluate(&build("Build failed"))
+            .matched
+            .is_empty()
+        };
+
+        assert!(matches(Rule::default()));
+        assert!(matches(Rule {
+            app: Some(AppPattern::Glob("org.gitlab.*".to_string())),
+            ..Rule::default()
+        }));
+        assert!(!matches(Rule {
+            app: Some(AppPattern::Glob("GitLab".to_string())),
+            ..Rule::default()
+        }));
+        assert!(matches(Rule {
+            app: Some(AppPattern::Regex("(?i)gitlab".to_string())),
+            ..Rule::default()
+        }));
+        assert!(matches(Rule {
+            category: Some("x-ci".to_string()),
+            ..Rule::default()
+        }));
+        assert!(!matches(Rule {
+            category: Some("x-c".to_string()),
+            ..Rule::default()
+        }));
+        assert!(matches(Rule {
+            urgency: vec![1, 2],
+            ..Rule::default()
+        }));
+        assert!(!matches(Rule {
+            urgency: vec![2],
+            ..Rule::default()
+        }));
+        assert!(matches(Rule {
+            body: Some(r"#\d+ on main$".to_string()),
+            ..Rule::default()
+        }));
+        assert!(matches(Rule {
+            hints: vec!["category".to_string()],
+            ..Rule::default()
+        }));
+        assert!(!matches(Rule {
+            hints: vec!["category".to_string(), "image-data".to_string()],
+            ..Rule::default()
+        }));
+        assert!(!matches(Rule {
+            enabled: false,
+            ..Rule::default()
+        }));
+    }
+
+    #[test]
+    fn test_priority_and_stop() {
+        let rules = rules(vec![
+            Rule {
+                name: "Low".to_string(),
+                actions: vec![RuleAction::SetUrgency(0), RuleAction::RedactBody],
+                ..Rule::default()
+            },
+            Rule {
+                name: "High".to_string(),
+                priority: 10,
+                actions: vec![RuleAction::SetUrgency(2)],
+                ..Rule::default()
+            },
+            Rule {
+                name: "Also low".to_string(),
+                actions: vec![RuleAction::ForceTransient],
+                hooks: vec![hook("lights")],
+                ..Rule::default()
+            },
+            Rule {
+                name: "Hook only".to_string(),
+                hooks: vec![hook("pager-log")],
+                ..Rule::default()
+            },
+        ]);
+        let evaluation = rules.evaluate(&build("Build failed"));
+        assert_eq!(evaluation.matched, ["High", "Low", "Also low", "Hook only"]);
+        // The first rule to set a value wins, hooks of all of them run
+        assert_eq!(evaluation.outcome.urgency, Some(2));
+        assert!(evaluation.outcome.redact_body && evaluation.outcome.transient);
+        assert_eq!(
+            evaluation.outcome.hooks,
+            [hook("lights"), hook("pager-log")]
+        );
+
+        let rules = self::rules(vec![
+            Rule {
+                priority: 1,
+                summary: Some("failed".to_string()),
+                actions: vec![RuleAction::SkipHistory],
+                stop: true,
+                ..Rule::default()
+            },
+            Rule {
+                actions: vec![RuleAction::Drop],
+                ..Rule::default()
+            },
+        ]);
+        let evaluation = rules.evaluate(&build("Build failed"));
+        assert_eq!(evaluation.matched, ["#1"]);
+        assert!(evaluation.outcome.skip_history && !evaluation.outcome.drop);
+        assert!(rules.evaluate(&build("Build started")).outcome.drop);
+    }
+
+    #[test]
+    fn test_invalid_rules_are_skipped() {
+        let (rules, errors) = Rules::new(&[
+            Rule {
+                name: "Broken".to_string(),
+                summary: Some("(unclosed".to_string()),
+                actions: vec![RuleAction::Drop],
+                ..Rule::default()
+            },
+            Rule {
+                actions: vec![RuleAction::Mute],
+                ..Rule::default()
+            },
+        ]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].rule, "Broken");
+        assert_eq!(rules.evaluate(&build("Build failed")).matched, ["#2"]);
+    }
+
+    #[test]
+    fn test_glob_escapes_regex_syntax() {
+        assert_eq!(glob_to_regex("org.gnome.*"), r"^org\.gnome\..*$");
+        assert_eq!(glob_to_regex("a?c"), "^a.c$");
+    }
+
+    #[test]
+    fn test_rule_deserialization_defaults() {
+        let rule: Rule =
+            serde_json::from_str(r#"{"summary": "failed", "actions": [{"SetUrgency": 2}]}"#)
+                .unwrap();
+        assert!(rule.enabled);
+        assert_eq!(rule.priority, 0);
+        assert_eq!(rule.actions, [RuleAction::SetUrgency(2)]);
+    }
+}
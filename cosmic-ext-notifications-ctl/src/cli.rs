@@ -0,0 +1,250 @@
+//! Command-line parsing
+
+use chrono::NaiveTime;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Usage: cosmic-ext-notifications-ctl <command> [args]
+
+Commands:
+  status                        Print the daemon state as JSON
+  dnd [status]                  Print \"on\" or \"off\"
+  dnd on [--for <duration> | --until <HH:MM>]
+                                Turn do not disturb on, e.g. --for 1h30m
+  dnd off                       Turn do not disturb off
+  dnd toggle                    Toggle do not disturb
+  list [--json]                 List notifications on screen as id, app and
+                                summary separated by tabs
+  history [--app <app>] [--limit <n>]
+                                Print notification history as JSON
+  dismiss <id> | --app <app> | --all
+                                Dismiss notifications on screen
+  invoke <id> [<action>]        Invoke an action (default: \"default\")
+  show <id>                     Show a notification from history again
+  focus                         Navigate notifications on screen with the
+                                keyboard, for binding to a shortcut
+  reload                        Read the configuration again
+
+Exit codes:
+  0  success
+  1  the daemon reported an error
+  2  invalid arguments
+  3  no such notification or action
+  4  the daemon is not running";
+
+/// When do-not-disturb turned on by the command ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DndEnd {
+    /// After a while
+    For(Duration),
+    /// At the next occurrence of a local time
+    Until(NaiveTime),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DndCommand {
+    Status,
+    On(Option<DndEnd>),
+    Off,
+    Toggle,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dismiss {
+    Id(u32),
+    App(String),
+    All,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    Status,
+    Dnd(DndCommand),
+    List { json: bool },
+    History { app: Option<String>, limit: u32 },
+    Dismiss(Dismiss),
+    Invoke { id: u32, action: String },
+    Show(u32),
+    Focus,
+    Reload,
+}
+
+/// Parse the arguments after the program name
+pub fn parse<I, S>(args: I) -> Result<Command, String>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let args: Vec<String> = args.into_iter().map(Into::into).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let command = match args.as_slice() {
+        [] | ["help" | "--help" | "-h"] => Command::Help,
+        ["status"] => Command::Status,
+        ["dnd"] | ["dnd", "status"] => Command::Dnd(DndCommand::Status),
+        ["dnd", "on"] => Command::Dnd(DndCommand::On(None)),
+        ["dnd", "on", "--for", duration] => {
+            Command::Dnd(DndCommand::On(Some(DndEnd::For(parse_duration(duration)?))))
+        }
+        ["dnd", "on", "--until", time] => {
+            let time = NaiveTime::parse_from_str(time, "%H:%M")
+                .map_err(|_| format!("invalid time `{time}`, expected HH:MM"))?;
+            Command::Dnd(DndCommand::On(Some(DndEnd::Until(time))))
+        }
+        ["dnd", "off"] => Command::Dnd(DndCommand::Off),
+        ["dnd", "toggle"] => Command::Dnd(DndCommand::Toggle),
+        ["list"] => Command::List { json: false },
+        ["list", "--json"] => Command::List { json: true },
+        ["history", rest @ ..] => parse_history(rest)?,
+        ["dismiss", "--all"] => Command::Dismiss(Dismiss::All),
+        ["dismiss", "--app", app] => Command::Dismiss(Dismiss::App(app.to_string())),
+        ["dismiss", id] => Command::Dismiss(Dismiss::Id(parse_id(id)?)),
+        ["invoke", id] => Command::Invoke {
+            id: parse_id(id)?,
+            action: "default".to_string(),
+        },
+        ["invoke", id, action] => Command::Invoke {
+            id: parse_id(id)?,
+            action: action.to_string(),
+        },
+        ["show", id] => Command::Show(parse_id(id)?),
+        ["focus"] => Command::Focus,
+        ["reload"] => Command::Reload,
+        _ => return Err(format!("invalid arguments `{}`", args.join(" "))),
+    };
+    Ok(command)
+}
+
+fn parse_history(mut args: &[&str]) -> Result<Command, String> {
+    let mut app = None;
+    let mut limit = 0;
+    loop {
+        match args {
+            [] => break,
+            ["--app", value, rest @ ..] => {
+                app = Some(value.to_string());
+                args = rest;
+            }
+            ["--limit", value, rest @ ..] => {
+                limit = value
+                    .parse()
+                    .map_err(|_| format!("invalid limit `{value}`"))?;
+                args = rest;
+            }
+            [other, ..] => return Err(format!("invalid history argument `{other}`")),
+        }
+    }
+    Ok(Command::History { app, limit })
+}
+
+fn parse_id(id: &str) -> Result<u32, String> {
+    id.parse()
+        .ok()
+        .filter(|id| *id != 0)
+        .ok_or_else(|| format!("invalid notification ID `{id}`"))
+}
+
+/// Parse a duration such as `90s`, `45m`, `2h` or `1h30m`
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration `{s}`, expected e.g. 45m or 1h30m");
+    let mut secs: u64 = 0;
+    let mut digits = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            'd' => 86_400,
+            'h' => 3_600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        secs = value
+            .checked_mul(unit)
+            .and_then(|part| secs.checked_add(part))
+            .ok_or_else(invalid)?;
+        digits.clear();
+    }
+    if !digits.is_empty() || secs == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("45m"), Ok(Duration::from_secs(45 * 60)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86_400)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("0m").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("5w").is_err());
+    }
+
+    #[test]
+    fn test_parse_dnd() {
+        assert_eq!(parse(["dnd"]), Ok(Command::Dnd(DndCommand::Status)));
+        assert_eq!(
+            parse(["dnd", "toggle"]),
+            Ok(Command::Dnd(DndCommand::Toggle))
+        );
+        assert_eq!(
+            parse(["dnd", "on", "--for", "30m"]),
+            Ok(Command::Dnd(DndCommand::On(Some(DndEnd::For(
+                Duration::from_secs(1800)
+            )))))
+        );
+        assert_eq!(
+            parse(["dnd", "on", "--until", "14:00"]),
+            Ok(Command::Dnd(DndCommand::On(Some(DndEnd::Until(
+                NaiveTime::from_hms_opt(14, 0, 0).unwrap()
+            )))))
+        );
+        assert!(parse(["dnd", "on", "--until", "2pm"]).is_err());
+        assert!(parse(["dnd", "maybe"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse(Vec::<String>::new()), Ok(Command::Help));
+        assert_eq!(parse(["list", "--json"]), Ok(Command::List { json: true }));
+        assert_eq!(
+            parse(["history", "--limit", "5", "--app", "Slack"]),
+            Ok(Command::History {
+                app: Some("Slack".to_string()),
+                limit: 5
+            })
+        );
+        assert_eq!(
+            parse(["dismiss", "42"]),
+            Ok(Command::Dismiss(Dismiss::Id(42)))
+        );
+        assert_eq!(
+            parse(["dismiss", "--all"]),
+            Ok(Command::Dismiss(Dismiss::All))
+        );
+        assert_eq!(
+            parse(["invoke", "7"]),
+            Ok(Command::Invoke {
+                id: 7,
+                action: "default".to_string()
+            })
+        );
+        assert_eq!(parse(["show", "7"]), Ok(Command::Show(7)));
+        assert_eq!(parse(["focus"]), Ok(Command::Focus));
+        assert!(parse(["dismiss", "0"]).is_err());
+        assert!(parse(["history", "--limit", "many"]).is_err());
+        assert!(parse(["frobnicate"]).is_err());
+    }
+}
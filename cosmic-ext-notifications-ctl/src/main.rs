@@ -0,0 +1,287 @@
+//! Command-line control of a running cosmic-ext-notifications daemon
+
+mod cli;
+
+use chrono::{Local, TimeDelta, Utc};
+use cli::{Command, Dismiss, DndCommand, DndEnd, USAGE};
+use cosmic_ext_notifications_config::dnd::next_local_time;
+use cosmic_ext_notifications_util::Notification;
+use std::{collections::HashMap, fmt, process::ExitCode};
+use zbus::{Connection, DBusError, proxy, proxy::CacheProperties, zvariant::Value};
+
+#[proxy(
+    interface = "io.github.olafkfreund.CosmicExtNotifications.Control",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/io/github/olafkfreund/CosmicExtNotifications"
+)]
+trait Control {
+    fn set_do_not_disturb_until(&self, until: i64) -> zbus::Result<()>;
+    fn list_active(&self) -> zbus::Result<Vec<String>>;
+    fn dismiss(&self, id: u32) -> zbus::Result<()>;
+    fn dismiss_all(&self) -> zbus::Result<u32>;
+    fn dismiss_app(&self, app: &str) -> zbus::Result<u32>;
+    fn show_history_item(&self, id: u32) -> zbus::Result<()>;
+    fn invoke_action(&self, id: u32, key: &str) -> zbus::Result<()>;
+    fn focus_popups(&self) -> zbus::Result<()>;
+    fn reload_config(&self) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn do_not_disturb(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn set_do_not_disturb(&self, value: bool) -> zbus::Result<()>;
+    #[zbus(property)]
+    fn active_count(&self) -> zbus::Result<u32>;
+    #[zbus(property)]
+    fn history_count(&self) -> zbus::Result<u32>;
+    #[zbus(property)]
+    fn unread_count(&self) -> zbus::Result<u32>;
+    #[zbus(property)]
+    fn paused_until(&self) -> zbus::Result<i64>;
+}
+
+#[proxy(
+    interface = "com.system76.NotificationsApplet",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/com/system76/NotificationsApplet"
+)]
+trait Applet {
+    fn query_history(
+        &self,
+        filters: HashMap<&str, Value<'_>>,
+        offset: u32,
+        limit: u32,
+    ) -> zbus::Result<(Vec<String>, u32)>;
+}
+
+/// Exit code of a failed command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    /// The daemon reported an error
+    Failure = 1,
+    /// Invalid arguments
+    Usage = 2,
+    /// No such notification or action
+    NotFound = 3,
+    /// The daemon is not running
+    NotRunning = 4,
+    /// Another notification server, or an incompatible version, is running
+    Incompatible = 5,
+}
+
+#[derive(Debug)]
+struct Error {
+    status: Status,
+    message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<zbus::Error> for Error {
+    fn from(err: zbus::Error) -> Self {
+        let name = match &err {
+            zbus::Error::MethodError(name, ..) => Some(name.to_string()),
+            zbus::Error::FDO(err) => Some(err.name().to_string()),
+            _ => None,
+        };
+        let status = match name.as_deref() {
+            Some(
+                "org.freedesktop.DBus.Error.ServiceUnknown"
+                | "org.freedesktop.DBus.Error.NameHasNoOwner",
+            ) => Status::NotRunning,
+            // Something owns the name, but not with this interface
+            Some(
+                "org.freedesktop.DBus.Error.UnknownObject"
+                | "org.freedesktop.DBus.Error.UnknownInterface"
+                | "org.freedesktop.DBus.Error.UnknownMethod",
+            ) => Status::Incompatible,
+            Some("org.freedesktop.DBus.Error.FileNotFound") => Status::NotFound,
+            Some("org.freedesktop.DBus.Error.InvalidArgs") => Status::Usage,
+            Some(_) => Status::Failure,
+            None => match err {
+                zbus::Error::Address(_)
+                | zbus::Error::InputOutput(_)
+                | zbus::Error::Handshake(_) => Status::NotRunning,
+                _ => Status::Failure,
+            },
+        };
+        let message = match (status, err) {
+            (Status::NotRunning, err) => format!("cosmic-ext-notifications is not running: {err}"),
+            (Status::Incompatible, err) => format!(
+                "the notification server is not a compatible version of cosmic-ext-notifications: {err}"
+            ),
+            (_, zbus::Error::MethodError(_, Some(message), _)) => message,
+            (_, err) => err.to_string(),
+        };
+        Self { status, message }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self {
+            status: Status::Failure,
+            message: format!("invalid notification from the daemon: {err}"),
+        }
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let command = match cli::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::from(Status::Usage as u8);
+        }
+    };
+
+    match run(command).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::from(err.status as u8)
+        }
+    }
+}
+
+async fn run(command: Command) -> Result<(), Error> {
+    if command == Command::Help {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let conn = Connection::session().await?;
+    let control = ControlProxy::builder(&conn)
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await?;
+
+    match command {
+        Command::Help => {}
+        Command::Status => {
+            let status = serde_json::json!({
+                "do_not_disturb": control.do_not_disturb().await?,
+                "active": control.active_count().await?,
+                "history": control.history_count().await?,
+                "unread": control.unread_count().await?,
+                "paused_until": control.paused_until().await?,
+            });
+            println!("{status}");
+        }
+        Command::Dnd(DndCommand::Status) => {
+            println!("{}", on_off(control.do_not_disturb().await?));
+        }
+        Command::Dnd(DndCommand::On(None)) => control.set_do_not_disturb(true).await?,
+        Command::Dnd(DndCommand::On(Some(end))) => {
+            let until = match end {
+                DndEnd::For(duration) => TimeDelta::from_std(duration)
+                    .ok()
+                    .and_then(|duration| Utc::now().checked_add_signed(duration))
+                    .map(|until| until.timestamp()),
+                DndEnd::Until(time) => {
+                    next_local_time(&Local::now(), time).map(|until| until.timestamp())
+                }
+            };
+            let Some(until) = until else {
+                return Err(Error {
+                    status: Status::Usage,
+                    message: "do not disturb would end too far in the future".to_string(),
+                });
+            };
+            control.set_do_not_disturb_until(until).await?;
+        }
+        Command::Dnd(DndCommand::Off) => control.set_do_not_disturb(false).await?,
+        Command::Dnd(DndCommand::Toggle) => {
+            let on = !control.do_not_disturb().await?;
+            control.set_do_not_disturb(on).await?;
+            println!("{}", on_off(on));
+        }
+        Command::List { json } => {
+            let active = parse_notifications(control.list_active().await?)?;
+            if json {
+                println!("{}", serde_json::to_string(&active)?);
+            } else {
+                for n in active {
+                    println!(
+                        "{}\t{}\t{}",
+                        n.id,
+                        one_line(&n.app_name),
+                        one_line(&n.summary)
+                    );
+                }
+            }
+        }
+        Command::History { app, limit } => {
+            let applet = AppletProxy::builder(&conn)
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await?;
+            let mut filters = HashMap::new();
+            if let Some(app) = &app {
+                filters.insert("app", Value::from(app.as_str()));
+            }
+            let (history, _total) = applet.query_history(filters, 0, limit).await?;
+            println!("{}", serde_json::to_string(&parse_notifications(history)?)?);
+        }
+        Command::Dismiss(Dismiss::Id(id)) => control.dismiss(id).await?,
+        Command::Dismiss(Dismiss::App(app)) => println!("{}", control.dismiss_app(&app).await?),
+        Command::Dismiss(Dismiss::All) => println!("{}", control.dismiss_all().await?),
+        Command::Invoke { id, action } => control.invoke_action(id, &action).await?,
+        Command::Show(id) => control.show_history_item(id).await?,
+        Command::Focus => control.focus_popups().await?,
+        Command::Reload => control.reload_config().await?,
+    }
+    Ok(())
+}
+
+/// Notifications serialized as JSON by the daemon
+fn parse_notifications(json: Vec<String>) -> Result<Vec<Notification>, Error> {
+    json.iter()
+        .map(|n| serde_json::from_str(n).map_err(Error::from))
+        .collect()
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on { "on" } else { "off" }
+}
+
+/// Text with tabs and line breaks replaced, for tab-separated output
+fn one_line(text: &str) -> String {
+    text.replace(['\t', '\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::fdo::Error as FdoError;
+
+    fn status(err: FdoError) -> Status {
+        Error::from(zbus::Error::from(err)).status
+    }
+
+    #[test]
+    fn test_status_of_daemon_errors() {
+        let cases = [
+            (FdoError::ServiceUnknown(String::new()), Status::NotRunning),
+            (FdoError::NameHasNoOwner(String::new()), Status::NotRunning),
+            // Another server owns the name
+            (FdoError::UnknownMethod(String::new()), Status::Incompatible),
+            (
+                FdoError::UnknownInterface(String::new()),
+                Status::Incompatible,
+            ),
+            (FdoError::UnknownObject(String::new()), Status::Incompatible),
+            // Bad arguments are told apart from missing notifications
+            (FdoError::InvalidArgs(String::new()), Status::Usage),
+            (FdoError::FileNotFound(String::new()), Status::NotFound),
+            (FdoError::Failed(String::new()), Status::Failure),
+        ];
+        for (err, expected) in cases {
+            assert_eq!(status(err), expected);
+        }
+    }
+}
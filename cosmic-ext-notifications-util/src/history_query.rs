@@ -0,0 +1,257 @@
+use crate::{Notification, strip_html};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+/// Filters and paging for a history query
+///
+/// Every filter that is set must match. Results keep the order of the
+/// history they are taken from, newest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryQuery {
+    /// App name, desktop entry or sender app ID, compared case-insensitively
+    pub app: Option<String>,
+    /// Category, or a category class such as `email` for `email.arrived`
+    pub category: Option<String>,
+    /// Urgency level (0 = low, 1 = normal, 2 = critical)
+    pub urgency: Option<u8>,
+    /// Only notifications received at or after this time
+    pub since: Option<SystemTime>,
+    /// Only notifications received before this time
+    pub until: Option<SystemTime>,
+    /// Text searched case-insensitively in summary and body
+    pub text: Option<String>,
+    /// Number of matching notifications to skip
+    pub offset: usize,
+    /// Maximum number of notifications returned, `None` for all
+    pub limit: Option<usize>,
+}
+
+/// One page of history query results
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryPage {
+    /// Matching notifications within the requested page
+    pub notifications: Vec<Notification>,
+    /// Number of matching notifications across all pages
+    pub total: usize,
+}
+
+impl HistoryQuery {
+    /// Check if a notification passes every filter
+    pub fn matches(&self, n: &Notification) -> bool {
+        self.matches_app(n)
+            && self.matches_category(n)
+            && self.urgency.is_none_or(|urgency| n.urgency() == urgency)
+            && self.since.is_none_or(|since| n.time >= since)
+            && self.until.is_none_or(|until| n.time < until)
+            && self.matches_text(n)
+    }
+
+    /// Run the query over history, newest first
+    pub fn run<'a>(&self, history: impl IntoIterator<Item = &'a Notification>) -> HistoryPage {
+        let mut page = HistoryPage::default();
+        for n in history.into_iter().filter(|n| self.matches(n)) {
+            let in_page = page.total >= self.offset
+                && self
+                    .limit
+                    .is_none_or(|limit| page.notifications.len() < limit);
+            if in_page {
+                page.notifications.push(n.clone());
+            }
+            page.total += 1;
+        }
+        page
+    }
+
+    fn matches_app(&self, n: &Notification) -> bool {
+        let Some(app) = &self.app else {
+            return true;
+        };
+
+        let app_id = app.strip_suffix(".desktop").unwrap_or(app);
+        n.app_name.eq_ignore_ascii_case(app)
+            || n.app_id().is_some_and(|id| id.eq_ignore_ascii_case(app_id))
+            || n.desktop_entry().is_some_and(|entry| {
+                let entry = entry.strip_suffix(".desktop").unwrap_or(entry);
+                entry.eq_ignore_ascii_case(app_id)
+            })
+    }
+
+    fn matches_category(&self, n: &Notification) -> bool {
+        let Some(category) = &self.category else {
+            return true;
+        };
+
+        n.category().is_some_and(|c| {
+            c == category
+                || c.strip_prefix(category.as_str())
+                    .is_some_and(|rest| rest.starts_with('.'))
+        })
+    }
+
+    fn matches_text(&self, n: &Notification) -> bool {
+        let Some(text) = &self.text else {
+            return true;
+        };
+
+        let needle = text.to_lowercase();
+        n.summary.to_lowercase().contains(&needle)
+            || strip_html(&n.body).to_lowercase().contains(&needle)
+    }
+}
+
+#[cfg(all(test, feature = "zbus_notifications"))]
+mod tests {
+    use super::*;
+    use crate::Hint;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn notification(id: u32, app_name: &str, hints: Vec<Hint>, age_secs: u64) -> Notification {
+        let summary = format!("Summary {id}");
+        let mut n = Notification::new(app_name, id, "", &summary, "", vec![], HashMap::new(), 5000);
+        n.hints = hints;
+        n.time -= Duration::from_secs(age_secs);
+        n
+    }
+
+    fn ids(page: &HistoryPage) -> Vec<u32> {
+        page.notifications.iter().map(|n| n.id).collect()
+    }
+
+    #[test]
+    fn test_empty_query_matches_all() {
+        let history = [
+            notification(2, "Firefox", vec![], 0),
+            notification(1, "Slack", vec![], 10),
+        ];
+        let page = HistoryQuery::default().run(&history);
+        assert_eq!(ids(&page), [2, 1]);
+        assert_eq!(page.total, 2);
+    }
+
+    #[test]
+    fn test_app_matches_name_or_desktop_entry() {
+        let history = [
+            notification(3, "Firefox", vec![], 0),
+            notification(
+                2,
+                "Web",
+                vec![Hint::DesktopEntry("org.mozilla.firefox".into())],
+                0,
+            ),
+            notification(1, "Slack", vec![], 0),
+        ];
+
+        let query = HistoryQuery {
+            app: Some("firefox".into()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.run(&history)), [3]);
+
+        let query = HistoryQuery {
+            app: Some("org.mozilla.firefox.desktop".into()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.run(&history)), [2]);
+
+        let mut sandboxed = notification(4, "Spoofed", vec![], 0);
+        sandboxed.sender = Some(crate::SenderIdentity {
+            bus_name: ":1.42".into(),
+            app_id: Some("com.slack.Slack".into()),
+            ..Default::default()
+        });
+        let query = HistoryQuery {
+            app: Some("com.slack.Slack".into()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.run(&[sandboxed])), [4]);
+    }
+
+    #[test]
+    fn test_category_class() {
+        let history = [
+            notification(3, "Mail", vec![Hint::Category("email.arrived".into())], 0),
+            notification(2, "Mail", vec![Hint::Category("email".into())], 0),
+            notification(1, "Chat", vec![Hint::Category("emailish".into())], 0),
+        ];
+
+        let query = HistoryQuery {
+            category: Some("email".into()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.run(&history)), [3, 2]);
+
+        let query = HistoryQuery {
+            category: Some("email.arrived".into()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.run(&history)), [3]);
+    }
+
+    #[test]
+    fn test_urgency_and_time_range() {
+        let history = [
+            notification(3, "A", vec![Hint::Urgency(2)], 10),
+            notification(2, "A", vec![], 100),
+            notification(1, "A", vec![Hint::Urgency(2)], 1000),
+        ];
+
+        let query = HistoryQuery {
+            urgency: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.run(&history)), [3, 1]);
+
+        let now = SystemTime::now();
+        let query = HistoryQuery {
+            since: Some(now - Duration::from_secs(500)),
+            until: Some(now - Duration::from_secs(50)),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.run(&history)), [2]);
+    }
+
+    #[test]
+    fn test_text_search() {
+        let mut with_body = notification(2, "Mail", vec![], 0);
+        with_body.body = "Meeting moved to <b>Friday</b>".into();
+        let history = [with_body, notification(1, "Mail", vec![], 0)];
+
+        let query = HistoryQuery {
+            text: Some("FRIDAY".into()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.run(&history)), [2]);
+
+        let query = HistoryQuery {
+            text: Some("summary 1".into()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.run(&history)), [1]);
+    }
+
+    #[test]
+    fn test_paging_reports_total() {
+        let history: Vec<_> = (1..=5)
+            .rev()
+            .map(|id| notification(id, "A", vec![], 0))
+            .collect();
+
+        let query = HistoryQuery {
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = query.run(&history);
+        assert_eq!(ids(&page), [4, 3]);
+        assert_eq!(page.total, 5);
+
+        let query = HistoryQuery {
+            offset: 10,
+            ..Default::default()
+        };
+        let page = query.run(&history);
+        assert!(page.notifications.is_empty());
+        assert_eq!(page.total, 5);
+    }
+}
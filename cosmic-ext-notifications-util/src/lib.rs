@@ -20,6 +20,7 @@ pub use audio::{play_sound_file, play_sound_name, AudioError};
 
 pub mod action;
 pub mod action_parser;
+pub mod history_query;
 pub mod link;
 pub mod link_detector;
 pub mod markup_parser;
@@ -33,6 +34,7 @@ pub use action_parser::{
     get_button_actions, get_default_action, has_action_icons, limit_actions, parse_actions,
     parse_actions_from_strs,
 };
+pub use history_query::{HistoryPage, HistoryQuery};
 pub use link::NotificationLink;
 pub use link_detector::{detect_links, is_safe_url, open_link};
 pub use markup_parser::{parse_markup, segments_to_plain_text, StyledSegment, TextStyle};
@@ -51,7 +53,7 @@ use std::{
 };
 
 #[cfg(feature = "zbus_notifications")]
-use cosmic_ext_notifications_config::GroupingMode;
+use cosmic_ext_notifications_config::{AppRule, GroupingMode, RuleOutcome};
 
 /// A group of related notifications
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -109,6 +111,79 @@ pub struct Notification {
     pub hints: Vec<Hint>,
     pub expire_timeout: i32,
     pub time: SystemTime,
+    /// Identity of the sending client, resolved by the daemon
+    ///
+    /// Never serialized: its bus name, PID and executable stay inside the
+    /// daemon.
+    #[serde(default, skip_serializing)]
+    pub sender: Option<SenderIdentity>,
+    /// How the daemon's rules want the notification shown and kept
+    #[serde(default)]
+    pub routing: Routing,
+}
+
+/// How the daemon's rules want a notification shown and kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Routing {
+    /// Put it in history without showing a card
+    pub history_only: bool,
+    /// Do not keep it in history once its card closes
+    pub skip_history: bool,
+    /// Display timeout in milliseconds from a content rule or a per-app
+    /// `timeout_override`, overriding the urgency maximums
+    pub timeout: Option<u32>,
+}
+
+/// Identity of the client that sent a notification
+///
+/// Resolved by the daemon from the sender's bus connection, so unlike
+/// `app_name` and the `desktop-entry` hint it cannot be chosen by the client.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SenderIdentity {
+    /// Unique bus name of the sending connection, e.g. `:1.42`
+    pub bus_name: String,
+    /// Process ID of the sending connection
+    pub pid: Option<u32>,
+    /// Application ID from the sandbox (Flatpak, Snap) or the systemd unit
+    /// the process runs in
+    pub app_id: Option<String>,
+    /// Whether `app_id` comes from a sandbox
+    ///
+    /// A systemd unit is not specific to the client: processes started from
+    /// a terminal run in the terminal's unit.
+    #[serde(default)]
+    pub sandboxed: bool,
+    /// Executable of the sending process
+    pub executable: Option<PathBuf>,
+}
+
+impl SenderIdentity {
+    /// Key identifying the sending client, for rate limiting
+    ///
+    /// Uses the most specific part of the identity that could be resolved:
+    /// the sandbox app ID, then the executable, then the app ID of the
+    /// systemd unit, then the bus name.
+    pub fn key(&self) -> String {
+        match (&self.app_id, &self.executable) {
+            (Some(app_id), _) if self.sandboxed => app_id.clone(),
+            (_, Some(executable)) => executable.display().to_string(),
+            (Some(app_id), None) => app_id.clone(),
+            (None, None) => self.bus_name.clone(),
+        }
+    }
+
+    /// App IDs that app and content rules match the client on, given the
+    /// `desktop-entry` hint it sent, in the order they are tried
+    ///
+    /// A sandbox app ID is authoritative. The app ID of a systemd unit may
+    /// be that of the terminal or launcher the client was started from, so
+    /// the hint is tried first.
+    pub fn desktop_entries<'a>(&'a self, hint: Option<&'a str>) -> Vec<&'a str> {
+        match self.app_id.as_deref() {
+            Some(app_id) if self.sandboxed => vec![app_id],
+            app_id => hint.into_iter().chain(app_id).collect(),
+        }
+    }
 }
 
 impl Notification {
@@ -146,6 +221,9 @@ impl Notification {
                 "urgency" => u8::try_from(v).map(Hint::Urgency).ok(),
                 "value" => i32::try_from(v).map(Hint::Value).ok(),
                 "x" => i32::try_from(v).map(Hint::X).ok(),
+                "x-kde-reply-placeholder-text" => {
+                    String::try_from(v).map(Hint::ReplyPlaceholder).ok()
+                }
                 "y" => i32::try_from(v).map(Hint::Y).ok(),
                 "image-path" | "image_path" => String::try_from(v).ok().map(|s| {
                     Hint::Image(
@@ -203,6 +281,8 @@ impl Notification {
             hints,
             expire_timeout,
             time: SystemTime::now(),
+            sender: None,
+            routing: Routing::default(),
         }
     }
 
@@ -210,6 +290,30 @@ impl Notification {
         self.hints.iter().any(|h| *h == Hint::Transient(true))
     }
 
+    /// Check if the notification stays open after an action is invoked
+    ///
+    /// Resident notifications (e.g. media player controls) are only removed
+    /// when the user dismisses them or the sender closes them.
+    pub fn resident(&self) -> bool {
+        self.hints.contains(&Hint::Resident(true))
+    }
+
+    /// Label of the `inline-reply` action, if the sender accepts a typed reply
+    pub fn inline_reply(&self) -> Option<&str> {
+        self.actions.iter().find_map(|(id, label)| match id {
+            ActionId::Custom(id) if id == INLINE_REPLY_ACTION => Some(label.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Placeholder text of the inline reply field
+    pub fn reply_placeholder(&self) -> Option<&str> {
+        self.hints.iter().find_map(|h| match h {
+            Hint::ReplyPlaceholder(text) => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
     /// Check if action buttons should display icons instead of text labels
     pub fn action_icons(&self) -> bool {
         self.hints.iter().any(|h| *h == Hint::ActionIcons(true))
@@ -272,6 +376,11 @@ impl Notification {
         })
     }
 
+    /// Application ID of the sender, if the daemon could resolve it
+    pub fn app_id(&self) -> Option<&str> {
+        self.sender.as_ref()?.app_id.as_deref()
+    }
+
     pub fn desktop_entry(&self) -> Option<&str> {
         self.hints.iter().find_map(|h| match h {
             Hint::DesktopEntry(s) => Some(s.as_str()),
@@ -279,6 +388,15 @@ impl Notification {
         })
     }
 
+    /// App IDs that app and content rules match the notification on, see
+    /// [`SenderIdentity::desktop_entries`]
+    pub fn desktop_entries(&self) -> Vec<&str> {
+        match &self.sender {
+            Some(sender) => sender.desktop_entries(self.desktop_entry()),
+            None => self.desktop_entry().into_iter().collect(),
+        }
+    }
+
     pub fn urgency(&self) -> u8 {
         self.hints
             .iter()
@@ -289,6 +407,69 @@ impl Notification {
             .unwrap_or(1)
     }
 
+    /// Apply the overrides of a per-app rule to this notification
+    ///
+    /// Rewrites the urgency hint, replaces the expire timeout and marks the
+    /// notification as sound-suppressed when the rule disables sounds, so that
+    /// `play_sound` stays silent. A timeout override is also recorded in
+    /// `routing`, so it wins over the urgency maximums. Dropping disabled apps
+    /// is up to the caller.
+    #[cfg(feature = "zbus_notifications")]
+    pub fn apply_app_rule(&mut self, rule: &AppRule) {
+        if let Some(urgency) = rule.urgency_override {
+            self.hints.retain(|h| !matches!(h, Hint::Urgency(_)));
+            self.hints.push(Hint::Urgency(urgency.min(2)));
+        }
+
+        if let Some(timeout) = rule.timeout_override {
+            self.expire_timeout = i32::try_from(timeout).unwrap_or(i32::MAX);
+            self.routing.timeout = Some(timeout);
+        }
+
+        if !rule.sound_enabled {
+            self.hints.retain(|h| !matches!(h, Hint::SuppressSound(_)));
+            self.hints.push(Hint::SuppressSound(true));
+        }
+    }
+
+    /// Apply what the matching content rules decided to this notification
+    ///
+    /// Rewrites the urgency, transient and suppress-sound hints and the body,
+    /// and records the rest in `routing` for the app. Dropping is up to the
+    /// caller.
+    #[cfg(feature = "zbus_notifications")]
+    pub fn apply_rule_outcome(&mut self, outcome: &RuleOutcome) {
+        if let Some(urgency) = outcome.urgency {
+            self.hints.retain(|h| !matches!(h, Hint::Urgency(_)));
+            self.hints.push(Hint::Urgency(urgency.min(2)));
+        }
+
+        if outcome.mute {
+            self.hints.retain(|h| !matches!(h, Hint::SuppressSound(_)));
+            self.hints.push(Hint::SuppressSound(true));
+        }
+
+        if outcome.transient {
+            self.hints.retain(|h| !matches!(h, Hint::Transient(_)));
+            self.hints.push(Hint::Transient(true));
+        }
+
+        if outcome.redact_body {
+            self.body.clear();
+        }
+
+        if let Some(timeout) = outcome.timeout {
+            self.expire_timeout = i32::try_from(timeout).unwrap_or(i32::MAX);
+        }
+
+        self.routing = Routing {
+            history_only: outcome.history_only,
+            skip_history: outcome.skip_history,
+            // A content rule's timeout wins over the per-app one
+            timeout: outcome.timeout.or(self.routing.timeout),
+        };
+    }
+
     pub fn image(&self) -> Option<&Image> {
         self.hints.iter().find_map(|h| match h {
             Hint::Image(i) => Some(i),
@@ -340,6 +521,14 @@ impl Notification {
         size += self.app_icon.len();
         size += self.summary.len();
         size += self.body.len();
+        if let Some(sender) = &self.sender {
+            size += sender.bus_name.len();
+            size += sender.app_id.as_ref().map_or(0, String::len);
+            size += sender
+                .executable
+                .as_ref()
+                .map_or(0, |p| p.as_os_str().len());
+        }
 
         // Actions
         for (action_id, label) in &self.actions {
@@ -365,6 +554,10 @@ pub enum ActionId {
     Custom(String),
 }
 
+/// Action key of the inline reply, answered with `NotificationReplied`
+/// instead of `ActionInvoked`
+pub const INLINE_REPLY_ACTION: &str = "inline-reply";
+
 impl fmt::Display for ActionId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -392,6 +585,8 @@ pub enum Hint {
     DesktopEntry(String),
     Image(Image),
     IconData(Vec<u8>),
+    /// Placeholder of the inline reply field (`x-kde-reply-placeholder-text`)
+    ReplyPlaceholder(String),
     Resident(bool),
     SenderPid(u32),
     SoundFile(PathBuf),
@@ -417,6 +612,7 @@ impl Hint {
                 Image::Data { data, .. } => data.len() + 32, // Arc overhead is minimal
             },
             Hint::IconData(data) => data.len() + 8,
+            Hint::ReplyPlaceholder(s) => s.len() + 8,
             Hint::Resident(_) => 8,
             Hint::SenderPid(_) => 8,
             Hint::SoundFile(p) => p.as_os_str().len() + 8,
@@ -648,6 +844,8 @@ mod integration_tests {
             hints: vec![],
             expire_timeout: 5000,
             time: SystemTime::now(),
+            sender: None,
+            routing: Routing::default(),
         };
 
         // Should work with basic methods
@@ -655,6 +853,123 @@ mod integration_tests {
         assert!(notification.image().is_none());
         assert!(notification.category().is_none());
         assert!(!notification.transient());
+        assert!(!notification.resident());
+    }
+
+    #[test]
+    fn test_sender_identity_key() {
+        let mut sender = SenderIdentity {
+            bus_name: ":1.42".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(sender.key(), ":1.42");
+
+        sender.executable = Some(PathBuf::from("/usr/bin/notify-send"));
+        assert_eq!(sender.key(), "/usr/bin/notify-send");
+
+        // The unit of a terminal is shared by everything started from it
+        sender.app_id = Some("com.system76.CosmicTerm".to_string());
+        assert_eq!(sender.key(), "/usr/bin/notify-send");
+        sender.executable = None;
+        assert_eq!(sender.key(), "com.system76.CosmicTerm");
+
+        sender.executable = Some(PathBuf::from("/app/lib/firefox/firefox"));
+        sender.app_id = Some("org.mozilla.firefox".to_string());
+        sender.sandboxed = true;
+        assert_eq!(sender.key(), "org.mozilla.firefox");
+    }
+
+    #[test]
+    fn test_sender_desktop_entries() {
+        let mut sender = SenderIdentity {
+            bus_name: ":1.42".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(sender.desktop_entries(Some("firefox")), ["firefox"]);
+        assert!(sender.desktop_entries(None).is_empty());
+
+        // A systemd unit's app ID is tried after the hint
+        sender.app_id = Some("com.system76.CosmicTerm".to_string());
+        assert_eq!(
+            sender.desktop_entries(Some("firefox")),
+            ["firefox", "com.system76.CosmicTerm"]
+        );
+        assert_eq!(sender.desktop_entries(None), ["com.system76.CosmicTerm"]);
+
+        // A sandbox app ID cannot be overridden by the hint
+        sender.app_id = Some("org.mozilla.firefox".to_string());
+        sender.sandboxed = true;
+        assert_eq!(
+            sender.desktop_entries(Some("com.slack.Slack")),
+            ["org.mozilla.firefox"]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "zbus_notifications")]
+    fn test_sender_not_serialized() {
+        let mut notification =
+            Notification::new("Chat", 1, "", "Summary", "Body", vec![], HashMap::new(), -1);
+        notification.sender = Some(SenderIdentity {
+            bus_name: ":1.42".to_string(),
+            pid: Some(4242),
+            executable: Some(PathBuf::from("/usr/bin/chat")),
+            ..Default::default()
+        });
+
+        let json = serde_json::to_string(&notification).unwrap();
+        assert!(!json.contains(":1.42"));
+        assert!(!json.contains("/usr/bin/chat"));
+        let parsed: Notification = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.sender, None);
+    }
+
+    #[test]
+    fn test_resident_hint() {
+        let mut notification = Notification {
+            id: 1,
+            app_name: "Music".to_string(),
+            app_icon: String::new(),
+            summary: "Now playing".to_string(),
+            body: String::new(),
+            actions: vec![],
+            hints: vec![Hint::Resident(true)],
+            expire_timeout: 0,
+            time: SystemTime::now(),
+            sender: None,
+            routing: Routing::default(),
+        };
+        assert!(notification.resident());
+
+        notification.hints = vec![Hint::Resident(false)];
+        assert!(!notification.resident());
+    }
+
+    #[test]
+    fn test_inline_reply() {
+        let mut notification = Notification {
+            id: 1,
+            app_name: "KDE Connect".to_string(),
+            app_icon: String::new(),
+            summary: "Alice".to_string(),
+            body: "Lunch?".to_string(),
+            actions: vec![(ActionId::Default, String::new())],
+            hints: vec![],
+            expire_timeout: 0,
+            time: SystemTime::now(),
+            sender: None,
+            routing: Routing::default(),
+        };
+        assert_eq!(notification.inline_reply(), None);
+
+        notification
+            .actions
+            .push((INLINE_REPLY_ACTION.parse().unwrap(), "Reply".to_string()));
+        notification
+            .hints
+            .push(Hint::ReplyPlaceholder("Reply to Alice".to_string()));
+        assert_eq!(notification.inline_reply(), Some("Reply"));
+        assert_eq!(notification.reply_placeholder(), Some("Reply to Alice"));
     }
 }
 
@@ -678,6 +993,8 @@ mod grouping_tests {
             hints,
             expire_timeout: 5000,
             time: SystemTime::now(),
+            sender: None,
+            routing: Routing::default(),
         }
     }
 
@@ -868,6 +1185,80 @@ mod grouping_tests {
         assert_eq!(groups_cat.len(), 0);
     }
 
+    #[test]
+    fn test_apply_app_rule_overrides() {
+        let mut notification = create_test_notification(1, "Slack", None);
+        notification.hints.push(Hint::Urgency(0));
+
+        notification.apply_app_rule(&AppRule {
+            app_name: "Slack".to_string(),
+            urgency_override: Some(2),
+            sound_enabled: false,
+            timeout_override: Some(12000),
+            ..Default::default()
+        });
+
+        assert_eq!(notification.urgency(), 2);
+        assert_eq!(
+            notification
+                .hints
+                .iter()
+                .filter(|h| matches!(h, Hint::Urgency(_)))
+                .count(),
+            1
+        );
+        assert_eq!(notification.expire_timeout, 12000);
+        assert_eq!(notification.routing.timeout, Some(12000));
+        assert!(notification.suppress_sound());
+
+        // A content rule without a timeout keeps the app rule's
+        notification.apply_rule_outcome(&RuleOutcome::default());
+        assert_eq!(notification.routing.timeout, Some(12000));
+    }
+
+    #[test]
+    fn test_apply_app_rule_without_overrides() {
+        let mut notification = create_test_notification(1, "Firefox", None);
+        let original = notification.clone();
+
+        notification.apply_app_rule(&AppRule {
+            app_name: "Firefox".to_string(),
+            ..Default::default()
+        });
+
+        assert_eq!(notification, original);
+    }
+
+    #[test]
+    fn test_apply_rule_outcome() {
+        let mut notification = create_test_notification(1, "CI", None);
+        notification.body = "Secret token abc".to_string();
+
+        notification.apply_rule_outcome(&RuleOutcome {
+            mute: true,
+            urgency: Some(2),
+            transient: true,
+            timeout: Some(0),
+            redact_body: true,
+            history_only: true,
+            ..Default::default()
+        });
+
+        assert_eq!(notification.urgency(), 2);
+        assert!(notification.suppress_sound());
+        assert!(notification.transient());
+        assert_eq!(notification.body, "");
+        assert_eq!(notification.expire_timeout, 0);
+        assert_eq!(
+            notification.routing,
+            Routing {
+                history_only: true,
+                skip_history: false,
+                timeout: Some(0),
+            }
+        );
+    }
+
     #[test]
     fn test_single_notification() {
         let notifications = vec![create_test_notification(1, "Firefox", Some("email"))];
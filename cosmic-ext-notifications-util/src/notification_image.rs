@@ -16,6 +16,9 @@ pub const MAX_IMAGE_WIDTH: u32 = 128;
 /// Maximum height for notification images in pixels
 pub const MAX_IMAGE_HEIGHT: u32 = 128;
 
+/// Maximum memory a sender-supplied encoded image may take to decode (64MB)
+pub const MAX_DECODE_ALLOC: u64 = 64 * 1024 * 1024;
+
 /// Processed notification image ready for display
 #[derive(Debug, Clone)]
 pub struct ProcessedImage {
@@ -140,6 +143,41 @@ impl NotificationImage {
     })
   }
 
+  /// Decode and process an encoded image, e.g. PNG bytes sent over D-Bus.
+  ///
+  /// # Arguments
+  ///
+  /// * `bytes` - Encoded image in any supported format
+  ///
+  /// # Returns
+  ///
+  /// A `ProcessedImage` with RGBA data, resized if necessary to fit within max dimensions.
+  ///
+  /// # Errors
+  ///
+  /// Returns `ImageError` if the bytes are not a valid image, or decoding would need
+  /// more than [`MAX_DECODE_ALLOC`] bytes.
+  pub fn from_bytes(bytes: &[u8]) -> Result<ProcessedImage, ImageError> {
+    let mut limits = image::Limits::default();
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+
+    let mut reader = image::ImageReader::new(std::io::Cursor::new(bytes)).with_guessed_format()?;
+    reader.limits(limits);
+    let rgba_img = reader.decode()?.to_rgba8();
+    let width = rgba_img.width();
+    let height = rgba_img.height();
+    let data = rgba_img.into_raw();
+
+    // Resize if necessary
+    let (final_width, final_height, final_data) = Self::resize_if_needed(width, height, data)?;
+
+    Ok(ProcessedImage {
+      data: final_data,
+      width: final_width,
+      height: final_height,
+    })
+  }
+
   /// Resize image if it exceeds maximum dimensions, preserving aspect ratio.
   ///
   /// Uses Lanczos3 algorithm for high-quality downscaling.
@@ -418,4 +456,20 @@ mod tests {
     let result = NotificationImage::from_path("/nonexistent/path/to/image.png");
     assert!(result.is_err(), "Should fail for non-existent file");
   }
+
+  /// Test decoding encoded image bytes
+  #[test]
+  fn test_from_bytes() {
+    let img = RgbaImage::from_pixel(4, 2, image::Rgba([1, 2, 3, 255]));
+    let mut png = Vec::new();
+    img
+      .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+      .unwrap();
+
+    let processed = NotificationImage::from_bytes(&png).unwrap();
+    assert_eq!((processed.width, processed.height), (4, 2));
+    assert_eq!(&processed.data[..4], &[1, 2, 3, 255]);
+
+    assert!(NotificationImage::from_bytes(b"not an image").is_err());
+  }
 }
@@ -3,10 +3,10 @@
 //! These tests verify the D-Bus org.freedesktop.Notifications interface
 //! implementation without requiring an actual D-Bus connection.
 
-use cosmic_ext_notifications_util::{ActionId, Notification, Hint, Image, CloseReason};
+use cosmic_ext_notifications_util::{ActionId, Notification, Hint, Image, CloseReason, Routing};
 use std::collections::HashMap;
 use std::path::PathBuf;
-use std::time::{Duration, Instant, SystemTime};
+use std::time::{Duration, SystemTime};
 
 // Test capabilities as documented in the FreeDesktop spec
 const EXPECTED_CAPABILITIES: &[&str] = &[
@@ -18,6 +18,7 @@ const EXPECTED_CAPABILITIES: &[&str] = &[
     "body-markup",    // Renders bold/italic styling in body
     "body-hyperlinks",// Supports clickable links in body
     "sound",          // Plays sound-file and sound-name hints
+    "inline-reply",   // Text field for the inline-reply action
 ];
 
 // Server information constants from src/config.rs
@@ -32,8 +33,8 @@ fn test_get_capabilities() {
 
     let capabilities = EXPECTED_CAPABILITIES;
 
-    // Verify we have all 8 expected capabilities
-    assert_eq!(capabilities.len(), 8, "Should have 8 capabilities");
+    // Verify we have all 9 expected capabilities
+    assert_eq!(capabilities.len(), 9, "Should have 9 capabilities");
 
     // Verify specific capabilities are present
     assert!(capabilities.contains(&"body"), "Should support body text");
@@ -44,6 +45,7 @@ fn test_get_capabilities() {
     assert!(capabilities.contains(&"persistence"), "Should support persistence");
     assert!(capabilities.contains(&"sound"), "Should support sound");
     assert!(capabilities.contains(&"action-icons"), "Should support action icons");
+    assert!(capabilities.contains(&"inline-reply"), "Should support inline reply");
 
     // Verify we don't claim unsupported capabilities
     assert!(!capabilities.contains(&"icon-multi"), "Should not support animated icons");
@@ -383,6 +385,8 @@ fn test_estimated_size_basic() {
         hints: vec![],
         expire_timeout: 5000,
         time: SystemTime::now(),
+        sender: None,
+        routing: Routing::default(),
     };
 
     let size = notification.estimated_size();
@@ -411,6 +415,8 @@ fn test_estimated_size_with_actions() {
         hints: vec![],
         expire_timeout: 0,
         time: SystemTime::now(),
+        sender: None,
+        routing: Routing::default(),
     };
 
     let size = notification.estimated_size();
@@ -437,6 +443,8 @@ fn test_estimated_size_with_hints() {
         ],
         expire_timeout: 0,
         time: SystemTime::now(),
+        sender: None,
+        routing: Routing::default(),
     };
 
     let size = notification.estimated_size();
@@ -467,6 +475,8 @@ fn test_estimated_size_with_image_data() {
         ],
         expire_timeout: 0,
         time: SystemTime::now(),
+        sender: None,
+        routing: Routing::default(),
     };
 
     let size = notification.estimated_size();
@@ -491,6 +501,8 @@ fn test_estimated_size_with_large_body() {
         hints: vec![],
         expire_timeout: 0,
         time: SystemTime::now(),
+        sender: None,
+        routing: Routing::default(),
     };
 
     let size = notification.estimated_size();
@@ -563,6 +575,8 @@ fn test_notification_duration_since() {
         hints: vec![],
         expire_timeout: 0,
         time: SystemTime::now() - Duration::from_secs(5),
+        sender: None,
+        routing: Routing::default(),
     };
 
     let duration = notification.duration_since().unwrap();
@@ -570,234 +584,3 @@ fn test_notification_duration_since() {
     // Should be approximately 5 seconds (with some tolerance)
     assert!(duration.as_secs() >= 4 && duration.as_secs() <= 6);
 }
-
-// Rate limiter tests (these test the logic from src/subscriptions/notifications.rs:329-399)
-
-/// Mock rate limiter for testing (mirrors the actual implementation)
-struct TestRateLimiter {
-    limits: HashMap<String, (Instant, u32)>,
-}
-
-impl TestRateLimiter {
-    const MAX_APPS: usize = 1000;
-    const MAX_PER_MINUTE: u32 = 60;
-    const WINDOW: Duration = Duration::from_secs(60);
-
-    fn new() -> Self {
-        Self {
-            limits: HashMap::new(),
-        }
-    }
-
-    fn check_and_update(&mut self, app_name: &str) -> bool {
-        if self.limits.len() >= Self::MAX_APPS {
-            self.cleanup();
-        }
-
-        if self.limits.len() >= Self::MAX_APPS {
-            return false;
-        }
-
-        let now = Instant::now();
-        let entry = self.limits.entry(app_name.to_string()).or_insert((now, 0));
-
-        if now.duration_since(entry.0) > Self::WINDOW {
-            *entry = (now, 1);
-            return true;
-        }
-
-        if entry.1 >= Self::MAX_PER_MINUTE {
-            return false;
-        }
-
-        entry.1 += 1;
-        true
-    }
-
-    fn cleanup(&mut self) {
-        let now = Instant::now();
-        self.limits.retain(|_, (start, _)| now.duration_since(*start) <= Self::WINDOW);
-    }
-}
-
-#[test]
-fn test_rate_limiter_allows_under_limit() {
-    // Test: Verify rate limiter allows notifications under the limit
-
-    let mut limiter = TestRateLimiter::new();
-
-    // Should allow first 60 notifications
-    for i in 1..=60 {
-        assert!(
-            limiter.check_and_update("test_app"),
-            "Notification {} should be allowed",
-            i
-        );
-    }
-}
-
-#[test]
-fn test_rate_limiter_blocks_over_limit() {
-    // Test: Verify rate limiter blocks notifications over the limit
-
-    let mut limiter = TestRateLimiter::new();
-
-    // Fill up to the limit
-    for _ in 1..=60 {
-        limiter.check_and_update("test_app");
-    }
-
-    // 61st should be blocked
-    assert!(
-        !limiter.check_and_update("test_app"),
-        "Notification over limit should be blocked"
-    );
-}
-
-#[test]
-fn test_rate_limiter_resets_after_window() {
-    // Test: Verify rate limiter resets after the time window expires
-
-    let mut limiter = TestRateLimiter::new();
-
-    // Fill up to the limit
-    for _ in 1..=60 {
-        limiter.check_and_update("test_app");
-    }
-
-    // Manually advance time by modifying the entry
-    if let Some(entry) = limiter.limits.get_mut("test_app") {
-        entry.0 = Instant::now() - Duration::from_secs(61);
-    }
-
-    // Should allow again after window expires
-    assert!(
-        limiter.check_and_update("test_app"),
-        "Should allow after time window expires"
-    );
-}
-
-#[test]
-fn test_rate_limiter_per_app_isolation() {
-    // Test: Verify rate limiting is per-app (one app doesn't affect another)
-
-    let mut limiter = TestRateLimiter::new();
-
-    // Fill up limit for app1
-    for _ in 1..=60 {
-        limiter.check_and_update("app1");
-    }
-
-    // app1 should be blocked
-    assert!(
-        !limiter.check_and_update("app1"),
-        "app1 should be rate limited"
-    );
-
-    // app2 should still be allowed
-    assert!(
-        limiter.check_and_update("app2"),
-        "app2 should not be affected by app1's rate limit"
-    );
-}
-
-#[test]
-fn test_rate_limiter_cleanup() {
-    // Test: Verify cleanup removes old entries
-
-    let mut limiter = TestRateLimiter::new();
-
-    // Add entries for multiple apps
-    limiter.check_and_update("app1");
-    limiter.check_and_update("app2");
-    limiter.check_and_update("app3");
-
-    assert_eq!(limiter.limits.len(), 3, "Should have 3 apps tracked");
-
-    // Manually age the entries
-    for (_, entry) in limiter.limits.iter_mut() {
-        entry.0 = Instant::now() - Duration::from_secs(61);
-    }
-
-    // Cleanup should remove old entries
-    limiter.cleanup();
-
-    assert_eq!(
-        limiter.limits.len(),
-        0,
-        "Cleanup should remove expired entries"
-    );
-}
-
-#[test]
-fn test_rate_limiter_empty_app_name() {
-    // Test: Verify empty app names are still rate limited
-
-    let mut limiter = TestRateLimiter::new();
-
-    // Empty app names should still be rate limited
-    for i in 1..=60 {
-        assert!(
-            limiter.check_and_update(""),
-            "Empty app name notification {} should be allowed",
-            i
-        );
-    }
-
-    assert!(
-        !limiter.check_and_update(""),
-        "Empty app name should be rate limited after 60"
-    );
-}
-
-#[test]
-fn test_rate_limiter_max_apps_limit() {
-    // Test: Verify rate limiter respects MAX_APPS limit
-
-    let mut limiter = TestRateLimiter::new();
-
-    // Add notifications from many different apps
-    for i in 0..1000 {
-        assert!(
-            limiter.check_and_update(&format!("app{}", i)),
-            "Should allow notifications from first 1000 apps"
-        );
-    }
-
-    // Should have 1000 apps tracked
-    assert_eq!(limiter.limits.len(), 1000);
-
-    // Next new app should trigger cleanup first
-    limiter.check_and_update("app1000");
-
-    // After cleanup (if no entries expired), should reject to prevent DoS
-    // This test verifies the max tracking limit is enforced
-}
-
-#[test]
-fn test_rate_limiter_concurrent_apps() {
-    // Test: Verify rate limiter handles multiple apps concurrently
-
-    let mut limiter = TestRateLimiter::new();
-
-    // Simulate multiple apps sending notifications concurrently
-    for i in 0..5 {
-        for j in 0..10 {
-            assert!(
-                limiter.check_and_update(&format!("app{}", i)),
-                "App {} notification {} should be allowed",
-                i,
-                j
-            );
-        }
-    }
-
-    // Verify all apps are tracked independently
-    assert_eq!(limiter.limits.len(), 5, "Should track 5 different apps");
-
-    // Each app should have sent 10 notifications
-    for i in 0..5 {
-        let entry = limiter.limits.get(&format!("app{}", i)).unwrap();
-        assert_eq!(entry.1, 10, "App {} should have count of 10", i);
-    }
-}
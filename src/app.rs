@@ -29,8 +29,9 @@
 // - Card list animations are handled efficiently by cosmic_time::anim! macro
 
 use crate::rendering::build_element_row;
+use crate::subscriptions::control::{self, ControlRequest};
 use crate::subscriptions::notifications;
-use crate::widgets::{notification_progress, RichCardConfig};
+use crate::widgets::{notification_progress, styled_progress, RichCardConfig};
 use cosmic::app::{Core, Settings};
 use cosmic::cosmic_config::{Config, CosmicConfigEntry};
 use cosmic::iced::platform_specific::runtime::wayland::layer_surface::{
@@ -38,28 +39,39 @@ use cosmic::iced::platform_specific::runtime::wayland::layer_surface::{
 };
 use cosmic::iced::platform_specific::shell::wayland::commands::{
     activation,
-    layer_surface::{Anchor, KeyboardInteractivity, destroy_layer_surface, get_layer_surface},
+    layer_surface::{
+        Anchor, KeyboardInteractivity, destroy_layer_surface, get_layer_surface,
+        set_keyboard_interactivity,
+    },
 };
-use cosmic::iced::{self, Length, Limits, Subscription};
+use cosmic::iced::futures::SinkExt;
+use cosmic::iced::{self, Length, Limits, Subscription, stream};
 use cosmic::iced_runtime::core::window::Id as SurfaceId;
-use cosmic::iced_widget::{column, row, vertical_space};
-use cosmic::widget::{autosize, button, container, icon, text};
+use cosmic::iced_widget::{column, mouse_area, row, vertical_space};
+use cosmic::widget::{autosize, button, container, icon, text, text_input};
 use cosmic::{Application, Element, app::Task};
-use cosmic_ext_notifications_config::NotificationsConfig;
+use cosmic_ext_notifications_config::{DndOutcome, GroupingMode, NotificationsConfig};
 use cosmic_ext_notifications_util::{
-    ActionId, CloseReason, Notification, NotificationLink,
+    ActionId, CloseReason, INLINE_REPLY_ACTION, Notification, NotificationGroup, NotificationLink,
     clean_bare_schemes, detect_links, extract_hrefs, sanitize_html, strip_html,
 };
 
-use crate::state::NotificationState;
-use crate::handlers::Message;
-use crate::rendering::{render_notification_image, render_markup_body, render_body_with_links, get_progress_from_hints};
+use crate::state::{MissedDigest, NotificationState};
+use crate::handlers::{Message, Navigation};
+use crate::rendering::{
+    get_progress_from_hints, render_body_with_links, render_group_header, render_markup_body,
+    render_notification_image,
+};
 use crate::constants::*;
 use cosmic_panel_config::{CosmicPanelConfig, CosmicPanelOuput, PanelAnchor};
+use chrono::{DateTime, Local, Utc};
 use cosmic_time::{Timeline, anim, id};
 use iced::Alignment;
+use iced::keyboard::{Key, Modifiers, key::Named};
 use std::borrow::Cow;
-use std::time::Duration;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
 static NOTIFICATIONS_APPLET: &str = "com.system76.CosmicAppletNotifications";
@@ -86,12 +98,33 @@ struct CosmicNotifications {
     window_id: SurfaceId,
     state: NotificationState,
     notifications_id: id::Cards,
-    notifications_tx: Option<mpsc::Sender<notifications::Input>>,
+    /// Queue forwarded in order to the D-Bus subscription
+    notifications_tx: Option<mpsc::UnboundedSender<notifications::Input>>,
     config: NotificationsConfig,
+    /// Handle used to write config changes, such as manual DND running out
+    config_helper: Option<Config>,
     dock_config: CosmicPanelConfig,
     panel_config: CosmicPanelConfig,
     anchor: Option<(Anchor, Option<String>)>,
     timeline: Timeline,
+    /// First ID handed out to new notifications, above any restored from history
+    first_id: u32,
+    /// Whether do-not-disturb was on when last checked
+    dnd_active: bool,
+    /// Whether a client holds popups back through `Inhibit`
+    inhibited: bool,
+    /// "While you were away" card shown after do-not-disturb ended
+    digest: Option<MissedDigest>,
+    /// History entries shown again on request, whose close was already reported
+    reshown: HashSet<u32>,
+    /// Status last published on the control interface
+    status: control::Status,
+    /// Card whose inline reply field is open, with the text typed so far
+    reply: Option<(u32, String)>,
+    reply_input_id: iced::id::Id,
+    /// Card focused in keyboard navigation mode, while the popups hold the
+    /// keyboard
+    focused: Option<u32>,
 }
 
 
@@ -265,14 +298,16 @@ impl CosmicNotifications {
             card_content = card_content.push(progress_bar);
         }
 
+        // Optional countdown until the card expires
+        if config.show_countdown {
+            if let Some(remaining) = self.state.timers().progress(n.id) {
+                card_content = card_content.push(styled_progress(remaining, COUNTDOWN_BAR_HEIGHT));
+            }
+        }
+
         // Optional action buttons - inline creation for 'static lifetime
         if config.show_actions && !n.actions.is_empty() {
-            // Filter to non-default actions and take up to MAX_VISIBLE_ACTIONS
-            let visible_actions: Vec<_> = n.actions
-                .iter()
-                .filter(|(id, _)| !matches!(id, ActionId::Default))
-                .take(MAX_VISIBLE_ACTIONS)
-                .collect();
+            let visible_actions: Vec<_> = visible_actions(n).collect();
 
             if !visible_actions.is_empty() {
                 let notification_id = n.id;
@@ -318,46 +353,294 @@ impl CosmicNotifications {
             }
         }
 
+        if config.show_actions {
+            if let Some(label) = n.inline_reply() {
+                card_content = card_content.push(self.render_reply(n, label));
+            }
+        }
+
         // Wrap in container with padding
-        container(card_content)
+        let card = container(card_content)
             .padding(CARD_PADDING)
-            .width(Length::Fill)
+            .width(Length::Fill);
+
+        if self.focused != Some(n.id) {
+            return card.into();
+        }
+        // Ring in the accent color around the card focused by keyboard
+        card.class(cosmic::theme::Container::custom(|theme| {
+            let cosmic = theme.cosmic();
+            cosmic::iced::widget::container::Style {
+                border: iced::Border {
+                    color: cosmic.accent_color().into(),
+                    width: FOCUS_RING_WIDTH,
+                    radius: cosmic.corner_radii.radius_s.into(),
+                },
+                ..Default::default()
+            }
+        }))
+        .into()
+    }
+
+    /// Render the inline reply of a card: a button until it is opened, then
+    /// a text field with send and cancel buttons
+    fn render_reply(&self, n: &Notification, label: &str) -> Element<'static, Message> {
+        let Some((_, draft)) = self.reply.as_ref().filter(|(id, _)| *id == n.id) else {
+            let label = if label.is_empty() { "Reply" } else { label };
+            return button::text(label.to_string())
+                .on_press(Message::OpenReply(n.id))
+                .padding([6, 12])
+                .into();
+        };
+
+        let placeholder = n.reply_placeholder().unwrap_or("Write a reply").to_string();
+        let input = text_input(placeholder, draft.clone())
+            .id(self.reply_input_id.clone())
+            .on_input(Message::ReplyInput)
+            .on_submit(|_| Message::SendReply)
+            .width(Length::Fill);
+        let send = button::icon(
+            icon::from_name("mail-send-symbolic")
+                .size(16)
+                .symbolic(true),
+        )
+        .on_press(Message::SendReply);
+        let cancel = button::icon(
+            icon::from_name("window-close-symbolic")
+                .size(16)
+                .symbolic(true),
+        )
+        .on_press(Message::CancelReply);
+        row![input, send, cancel]
+            .spacing(8)
+            .align_y(Alignment::Center)
             .into()
     }
 
+    /// Render a group of notifications as one stacked card
+    ///
+    /// Collapsed groups show their newest notification below the group
+    /// header. Expanded groups list up to `max_per_group` notifications,
+    /// each of which can be activated on its own.
+    fn render_group(
+        &self,
+        group: &NotificationGroup,
+        config: &RichCardConfig,
+    ) -> Element<'static, Message> {
+        let max_per_group = (self.config.max_per_group as usize).max(1);
+        let mut content = column![render_group_header(group, self.config.show_group_count)];
+
+        if group.expanded {
+            for n in group.notifications.iter().take(max_per_group) {
+                content = content.push(
+                    button::custom(self.render_rich_notification(n, config))
+                        .on_press(Message::ActivateNotification(n.id))
+                        .class(cosmic::theme::Button::Text)
+                        .padding(0),
+                );
+            }
+
+            let remaining = group.count().saturating_sub(max_per_group);
+            if remaining > 0 {
+                content = content.push(
+                    container(text::caption(format!("+{remaining} more")))
+                        .padding([0, CARD_PADDING]),
+                );
+            }
+        } else if let Some(newest) = group.newest() {
+            content = content.push(self.render_rich_notification(newest, config));
+        }
+
+        content.width(Length::Fill).into()
+    }
+
 
     fn expire(&mut self, i: u32) {
-        self.state.hide_notification(i);
+        if !self.state.hide_notification(i) {
+            return;
+        }
         self.sort_notifications();
         self.group_notifications();
+
+        self.report_closed(i, CloseReason::Expired);
+    }
+
+    /// Emit `NotificationClosed` for a card, unless it was shown again from
+    /// history and its sender was told long ago
+    fn report_closed(&mut self, id: u32, reason: CloseReason) {
+        if !self.reshown.remove(&id) {
+            self.send_input(notifications::Input::Closed(id, reason));
+        }
     }
 
+    /// Remove a notification and report it closed
+    ///
+    /// `NotificationClosed` is only sent for open cards; notifications that
+    /// already expired into history were reported closed back then.
     fn close(&mut self, i: u32, reason: CloseReason) -> Option<Task<Message>> {
+        if self
+            .digest
+            .as_ref()
+            .is_some_and(|digest| digest.id == Some(i))
+        {
+            self.digest = None;
+        }
+        // Keyboard focus moves on to the card below, or the one above
+        let was_focused = self.focused == Some(i);
+        let next_focus = if was_focused {
+            let ids = self.card_ids();
+            let position = ids.iter().position(|id| *id == i).unwrap_or(0);
+            ids.get(position + 1)
+                .or_else(|| position.checked_sub(1).and_then(|p| ids.get(p)))
+                .copied()
+        } else {
+            None
+        };
+
+        let was_visible = self.state.is_visible(i);
         let notification = self.state.remove_notification(i)?;
 
+        // Closing the card being replied to or focused may give the
+        // keyboard back
+        let was_replied = self.reply.as_ref().is_some_and(|(id, _)| *id == i);
+        if was_replied {
+            self.reply = None;
+        }
+        if was_focused {
+            self.focus(next_focus);
+        }
+        let keyboard = if was_replied || was_focused {
+            self.update_keyboard()
+        } else {
+            Task::none()
+        };
+
         self.state.shrink_visible();
         self.sort_notifications();
         self.group_notifications();
 
-        if let Some(sender) = &self.notifications_tx {
-            let id = notification.id;
-            let sender = sender.clone();
-            tokio::spawn(async move {
-                _ = sender.send(notifications::Input::Closed(id, reason));
-            });
-        }
-
-        if let Some(sender) = &self.notifications_tx {
-            let sender = sender.clone();
-            let id = notification.id;
-            tokio::spawn(async move { sender.send(notifications::Input::Dismissed(id)).await });
+        if was_visible {
+            self.report_closed(notification.id, reason);
         }
 
         if self.state.is_empty() && self.active_surface {
             self.active_surface = false;
-            Some(destroy_layer_surface(self.window_id))
+            Some(Task::batch([
+                keyboard,
+                destroy_layer_surface(self.window_id),
+            ]))
         } else {
-            Some(Task::none())
+            Some(keyboard)
+        }
+    }
+
+    /// Hold the keyboard while navigating by keyboard or typing a reply,
+    /// and give it back otherwise
+    fn update_keyboard(&self) -> Task<Message> {
+        let interactivity = if self.focused.is_some() {
+            KeyboardInteractivity::Exclusive
+        } else if self.reply.is_some() {
+            KeyboardInteractivity::OnDemand
+        } else {
+            KeyboardInteractivity::None
+        };
+        set_keyboard_interactivity(self.window_id, interactivity)
+    }
+
+    /// Check if a card is kept from expiring because it is replied to or
+    /// focused by keyboard
+    fn holds_card(&self, id: u32) -> bool {
+        self.focused == Some(id) || self.reply.as_ref().is_some_and(|(reply, _)| *reply == id)
+    }
+
+    /// Move keyboard focus to a card, or end keyboard navigation with `None`
+    ///
+    /// The focused card does not expire.
+    fn focus(&mut self, id: Option<u32>) {
+        if let Some(previous) = std::mem::replace(&mut self.focused, id) {
+            if !self.holds_card(previous) {
+                self.state.timers_mut().resume(previous);
+            }
+        }
+        if let Some(id) = id {
+            self.state.timers_mut().pause(id);
+        }
+    }
+
+    /// IDs of the cards on screen from top to bottom, as keyboard navigation
+    /// moves through them
+    fn card_ids(&self) -> Vec<u32> {
+        let max = self.effective_max_notifications();
+        if self.config.grouping_mode == GroupingMode::None {
+            return self
+                .state
+                .visible()
+                .iter()
+                .rev()
+                .take(max)
+                .map(|n| n.id)
+                .collect();
+        }
+
+        let max_per_group = (self.config.max_per_group as usize).max(1);
+        self.state
+            .groups(self.config.grouping_mode)
+            .iter()
+            .filter(|group| !group.notifications.is_empty())
+            .take(max)
+            .flat_map(|group| {
+                // Collapsed stacks show only their newest notification
+                if group.expanded && group.notifications.len() > 1 {
+                    group
+                        .notifications
+                        .iter()
+                        .take(max_per_group)
+                        .map(|n| n.id)
+                        .collect()
+                } else {
+                    group.newest().map(|n| n.id).into_iter().collect::<Vec<_>>()
+                }
+            })
+            .collect()
+    }
+
+    /// Act on a key pressed in keyboard navigation mode
+    fn navigate(&mut self, navigation: Navigation) -> Task<Message> {
+        let Some(focused) = self.focused else {
+            return Task::none();
+        };
+        match navigation {
+            Navigation::Previous | Navigation::Next => {
+                let ids = self.card_ids();
+                let next = match ids.iter().position(|id| *id == focused) {
+                    Some(position) if navigation == Navigation::Previous => {
+                        position.saturating_sub(1)
+                    }
+                    Some(position) => (position + 1).min(ids.len().saturating_sub(1)),
+                    None => 0,
+                };
+                self.focus(ids.get(next).copied());
+                self.update_keyboard()
+            }
+            Navigation::Activate => self.request_activation(focused, None),
+            Navigation::Action(index) => {
+                let action = self
+                    .state
+                    .get(focused)
+                    .and_then(|n| visible_actions(n).nth(index))
+                    .map(|(action, _)| action.clone());
+                match action {
+                    Some(action) => self.request_activation(focused, Some(action)),
+                    None => Task::none(),
+                }
+            }
+            Navigation::Dismiss => self
+                .close(focused, CloseReason::Dismissed)
+                .unwrap_or(Task::none()),
+            Navigation::Release => {
+                self.focus(None);
+                self.update_keyboard()
+            }
         }
     }
 
@@ -484,33 +767,59 @@ impl CosmicNotifications {
         &mut self,
         notification: Notification,
     ) -> Task<<CosmicNotifications as cosmic::app::Application>::Message> {
-        // Play notification sound if not in do-not-disturb mode
-        #[cfg(feature = "audio")]
-        if !self.config.do_not_disturb {
-            notification.play_sound();
+        // Content rules can send a notification straight to history
+        if notification.routing.history_only {
+            let id = notification.id;
+            self.state.add_hidden(notification);
+            self.state.mark_unread(id);
+            self.send_input(notifications::Input::Closed(id, CloseReason::Undefined));
+            return Task::none();
         }
 
-        let mut timeout = u32::try_from(notification.expire_timeout).unwrap_or(3000);
-        let max_timeout = if notification.urgency() == 2 {
-            self.config.max_timeout_urgent
-        } else if notification.urgency() == 1 {
-            self.config.max_timeout_normal
-        } else {
-            self.config.max_timeout_low
+        // In do-not-disturb mode notifications that do not break through
+        // skip the screen, going to history unless their rule says otherwise
+        if self.is_dnd_active() {
+            let rule = self
+                .config
+                .find_app_rule_by_entries(&notification.app_name, &notification.desktop_entries());
+            if let DndOutcome::Suppress { sound, history } =
+                self.config
+                    .dnd_outcome(rule, notification.urgency(), notification.category())
+            {
+                #[cfg(feature = "audio")]
+                if sound {
+                    notification.play_sound();
+                }
+                #[cfg(not(feature = "audio"))]
+                let _ = sound;
+
+                self.state.add_missed(&notification);
+                let id = notification.id;
+                if history {
+                    self.state.add_hidden(notification);
+                    self.state.mark_unread(id);
+                }
+                self.send_input(notifications::Input::Closed(id, CloseReason::Undefined));
+                return Task::none();
+            }
         }
-        .unwrap_or(u32::try_from(notification.expire_timeout).unwrap_or(3000));
-        timeout = timeout.min(max_timeout);
 
-        let mut tasks = vec![if timeout > 0 {
-            iced::Task::perform(
-                tokio::time::sleep(Duration::from_millis(timeout as u64)),
-                move |_| cosmic::action::app(Message::Timeout(notification.id)),
-            )
-        } else {
-            iced::Task::none()
-        }];
+        self.show_notification(notification)
+    }
 
-        if self.state.is_empty() && !self.config.do_not_disturb {
+    /// Show a notification as a card, whatever do-not-disturb says
+    fn show_notification(&mut self, notification: Notification) -> Task<Message> {
+        // Apps whose rule disables sound arrive with suppress-sound set
+        #[cfg(feature = "audio")]
+        notification.play_sound();
+
+        let timeout = self.timeout_for(&notification);
+
+        self.arm_timeout(notification.id, timeout);
+
+        let mut tasks = Vec::new();
+
+        if self.state.is_empty() {
             let (anchor, _output) = self.anchor.clone().unwrap_or((Anchor::TOP, None));
             self.active_surface = true;
             tasks.push(get_layer_surface(SctkLayerSurfaceSettings {
@@ -544,17 +853,247 @@ impl CosmicNotifications {
         iced::Task::batch(tasks)
     }
 
+    /// Effective display timeout in milliseconds for a notification
+    ///
+    /// A content rule's timeout, then a per-app `timeout_override`, win over
+    /// the urgency-based maximums. Both were recorded in `routing` when the
+    /// notification arrived.
+    fn timeout_for(&self, notification: &Notification) -> u32 {
+        if let Some(timeout) = notification.routing.timeout {
+            return timeout;
+        }
+
+        let timeout = u32::try_from(notification.expire_timeout).unwrap_or(3000);
+        let max_timeout = if notification.urgency() == 2 {
+            self.config.max_timeout_urgent
+        } else if notification.urgency() == 1 {
+            self.config.max_timeout_normal
+        } else {
+            self.config.max_timeout_low
+        }
+        .unwrap_or(timeout);
+        timeout.min(max_timeout)
+    }
+
+    /// Start or restart the expiry timer of a card
+    ///
+    /// Replaced cards are re-armed so they live for their new timeout.
+    fn arm_timeout(&mut self, id: u32, timeout: u32) {
+        self.state
+            .timers_mut()
+            .arm(id, Duration::from_millis(u64::from(timeout)));
+    }
+
+    /// Pause the expiry of the given cards while the pointer is over them
+    fn hover_pauses(
+        &self,
+        ids: Vec<u32>,
+        card: Element<'static, Message>,
+    ) -> Element<'static, Message> {
+        if !self.config.pause_on_hover {
+            return card;
+        }
+        mouse_area(card)
+            .on_enter(Message::PauseExpiry(ids.clone()))
+            .on_exit(Message::ResumeExpiry(ids))
+            .into()
+    }
+
+    /// Forward an input to the D-Bus subscription without blocking the UI
+    ///
+    /// Inputs arrive in the order they were sent, so signals such as
+    /// `NotificationClosed` go out in the order cards closed.
+    fn send_input(&self, input: notifications::Input) {
+        if let Some(sender) = &self.notifications_tx {
+            if sender.send(input).is_err() {
+                tracing::error!("Failed to send input to notifications subscription: queue closed");
+            }
+        }
+    }
+
+    /// Open, update or close the on-disk history for the configuration
+    ///
+    /// Opening reads the disk, so it happens in the background and ends in
+    /// `Message::HistoryOpened`.
+    fn open_history(&mut self, config: &NotificationsConfig) -> Task<Message> {
+        let Some(opener) = self.state.configure_history(config) else {
+            return Task::none();
+        };
+        cosmic::task::future(async move {
+            let opened = tokio::task::spawn_blocking(move || opener.open())
+                .await
+                .ok()
+                .flatten();
+            cosmic::Action::App(Message::HistoryOpened(Arc::new(opened)))
+        })
+    }
+
+    /// Start forwarding queued inputs to a (re)connected subscription
+    ///
+    /// The forwarding task ends when the queue is replaced on reconnect.
+    fn connect_inputs(&mut self, tx: mpsc::Sender<notifications::Input>) {
+        let (queue, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(input) = rx.recv().await {
+                if let Err(err) = tx.send(input).await {
+                    tracing::error!("Failed to send input to notifications subscription: {err}");
+                    break;
+                }
+            }
+        });
+        self.notifications_tx = Some(queue);
+    }
+
     fn group_notifications(&mut self) {
         self.state.group_by_app(
             self.config.max_per_app as usize,
             self.effective_max_notifications()
         );
+        self.state.retain_groups(self.config.grouping_mode);
     }
 
     fn sort_notifications(&mut self) {
         self.state.sort_visible();
     }
 
+    /// Tick the card expiry timers while any of them is counting down
+    fn expiry_subscription(&self) -> Subscription<Message> {
+        if !self.state.timers().has_running() {
+            return Subscription::none();
+        }
+        let tick = if self.config.show_countdown {
+            COUNTDOWN_TICK_MS
+        } else {
+            EXPIRY_TICK_MS
+        };
+        iced::time::every(Duration::from_millis(tick)).map(|_| Message::ExpiryTick)
+    }
+
+    /// Keys of keyboard navigation, while the popups hold the keyboard
+    fn keyboard_subscription(&self) -> Subscription<Message> {
+        if self.focused.is_none() {
+            return Subscription::none();
+        }
+        iced::keyboard::on_key_press(navigation_key)
+    }
+
+    /// Check if do-not-disturb is on, by config, schedule or an inhibitor
+    fn is_dnd_active(&self) -> bool {
+        self.inhibited || self.config.dnd_active_at(&Local::now())
+    }
+
+    /// Track do-not-disturb turning on or off, showing what was missed once
+    /// it ends
+    fn update_dnd(&mut self) -> Task<Message> {
+        let active = self.is_dnd_active();
+        let was_active = std::mem::replace(&mut self.dnd_active, active);
+        if was_active && !active {
+            self.show_missed_digest()
+        } else {
+            Task::none()
+        }
+    }
+
+    /// Turn manual do-not-disturb on until `until`, indefinitely if `None`,
+    /// or off, saving it to the config
+    fn set_manual_dnd(&mut self, on: bool, until: Option<DateTime<Utc>>) {
+        match &self.config_helper {
+            Some(helper) => {
+                // The end goes first, so a watcher never sees a stale one
+                if let Err(err) = self.config.set_do_not_disturb_until(helper, until) {
+                    tracing::error!("Failed to set do not disturb end: {:?}", err);
+                }
+                if let Err(err) = self.config.set_do_not_disturb(helper, on) {
+                    tracing::error!(
+                        "Failed to turn do not disturb {}: {:?}",
+                        if on { "on" } else { "off" },
+                        err
+                    );
+                }
+            }
+            None => {
+                self.config.do_not_disturb = on;
+                self.config.do_not_disturb_until = until;
+            }
+        }
+    }
+
+    /// Show the "while you were away" card for notifications held back by
+    /// do-not-disturb, or count them on the one already shown
+    fn show_missed_digest(&mut self) -> Task<Message> {
+        let missed = self.state.take_missed();
+        if missed.is_empty() {
+            return Task::none();
+        }
+
+        match &mut self.digest {
+            Some(digest) => {
+                digest.extend(missed);
+                // A digest still waiting for its ID is shown complete later
+                if digest.id.is_none() {
+                    return Task::none();
+                }
+                let card = digest.notification();
+                self.replace_notification(card)
+            }
+            None => {
+                let Some(digest) = MissedDigest::new(missed) else {
+                    return Task::none();
+                };
+                // The daemon hands out the ID, so it never clashes with a client's
+                self.send_input(notifications::Input::MissedDigest(Box::new(
+                    digest.notification(),
+                )));
+                self.digest = Some(digest);
+                Task::none()
+            }
+        }
+    }
+
+    /// Expand the digest card into a list of what was missed, or dismiss it
+    /// if it already is
+    ///
+    /// Returns `None` if `id` is not the digest card.
+    fn activate_digest(&mut self, id: u32) -> Option<Task<Message>> {
+        let digest = self
+            .digest
+            .as_mut()
+            .filter(|digest| digest.id == Some(id))?;
+        if digest.expanded {
+            return self.close(id, CloseReason::Dismissed);
+        }
+        digest.expanded = true;
+        let card = digest.notification();
+        // The list shows what was missed, so it counts as seen
+        self.state.mark_all_read();
+        Some(self.replace_notification(card))
+    }
+
+    /// Wake up when do-not-disturb turns on or off
+    ///
+    /// Keyed by the transition time, so config changes that move it restart
+    /// the wait.
+    fn dnd_subscription(&self) -> Subscription<Message> {
+        let Some(at) = self.config.next_dnd_transition(&Local::now()) else {
+            return Subscription::none();
+        };
+        Subscription::run_with_id(
+            ("dnd-transition", at.timestamp()),
+            stream::channel(1, move |mut output| async move {
+                // Sleep in steps against the wall clock, which keeps going
+                // while the system is suspended
+                while let Ok(left) = (at - Utc::now()).to_std() {
+                    if left.is_zero() {
+                        break;
+                    }
+                    tokio::time::sleep(left.min(Duration::from_secs(DND_CHECK_INTERVAL_SECS)))
+                        .await;
+                }
+                _ = output.send(Message::DndTransition).await;
+            }),
+        )
+    }
+
     /// Calculate effective max notifications based on available screen space
     fn effective_max_notifications(&self) -> usize {
         // Estimated height per notification card (including spacing)
@@ -573,8 +1112,11 @@ impl CosmicNotifications {
     }
 
     fn replace_notification(&mut self, notification: Notification) -> Task<Message> {
-        if let Some(notif) = self.state.visible_mut().iter_mut().find(|n| n.id == notification.id) {
+        let id = notification.id;
+        let timeout = self.timeout_for(&notification);
+        if let Some(notif) = self.state.visible_mut().iter_mut().find(|n| n.id == id) {
             *notif = notification;
+            self.arm_timeout(id, timeout);
             Task::none()
         } else {
             tracing::error!("Notification not found... pushing instead");
@@ -594,14 +1136,8 @@ impl CosmicNotifications {
         id: u32,
         action: Option<ActionId>,
     ) -> Option<Task<Message>> {
-        if let Some(tx) = self.notifications_tx.as_ref() {
-            let c_pos = self.state.visible().iter().position(|n| n.id == id);
-            let notification = c_pos.map(|c_pos| &self.state.visible()[c_pos]).or_else(|| {
-                self.state.hidden()
-                    .iter()
-                    .position(|n| n.id == id)
-                    .map(|pos| &self.state.hidden()[pos])
-            })?;
+        if self.notifications_tx.is_some() {
+            let notification = self.state.get(id)?;
 
             let maybe_action = if action
                 .as_ref()
@@ -621,83 +1157,166 @@ impl CosmicNotifications {
             let Some(action) = maybe_action else {
                 return self.close(id, CloseReason::Dismissed);
             };
-            let tx = tx.clone();
+            // Resident notifications stay open until the user or the sender
+            // closes them, whether invoked from a card or the applet
+            let resident = notification.resident();
             tracing::info!("action for {id} {action}");
-            return Some(Task::future(async move {
-                _ = tx
-                    .send(notifications::Input::Activated { token, id, action })
-                    .await;
-                tracing::trace!("sent action to sub");
-                cosmic::Action::App(Message::Dismissed(id))
-            }));
+            // Queued ahead of the close, so `ActionInvoked` precedes
+            // `NotificationClosed`
+            self.send_input(notifications::Input::Activated { token, id, action });
+            if resident {
+                return Some(Task::none());
+            }
+            return self.close(id, CloseReason::Dismissed);
         } else {
             tracing::error!("Failed to activate notification. No channel.");
             None
         }
     }
-}
-
-impl cosmic::Application for CosmicNotifications {
-    type Message = Message;
-    type Executor = cosmic::executor::single::Executor;
-    type Flags = ();
-    const APP_ID: &'static str = "io.github.olafkfreund.CosmicExtNotifications";
-
-    fn init(core: Core, _flags: ()) -> (Self, Task<Message>) {
-        let helper = Config::new(
-            cosmic_ext_notifications_config::ID,
-            NotificationsConfig::VERSION,
-        )
-        .ok();
 
-        let config: NotificationsConfig = helper
-            .as_ref()
-            .map(|helper| {
-                NotificationsConfig::get_entry(helper).unwrap_or_else(|(errors, config)| {
-                    for err in errors {
-                        if err.is_err() {
-                            tracing::error!("{:?}", err);
+    /// Carry out a request from the control interface
+    fn handle_control(&mut self, request: ControlRequest) -> Task<Message> {
+        match request {
+            ControlRequest::SetDoNotDisturb(on) => {
+                tracing::info!(
+                    "Do not disturb turned {} over D-Bus",
+                    if on { "on" } else { "off" }
+                );
+                self.set_manual_dnd(on, None);
+                self.update_dnd()
+            }
+            ControlRequest::SetDoNotDisturbUntil(until) => {
+                tracing::info!("Do not disturb turned on until {until} over D-Bus");
+                self.set_manual_dnd(true, Some(until));
+                self.update_dnd()
+            }
+            ControlRequest::ListActive { tx } => {
+                let mut active = self.state.visible().to_vec();
+                active.sort_by_key(|n| std::cmp::Reverse(n.time));
+                _ = tx.send(active);
+                Task::none()
+            }
+            ControlRequest::Dismiss { id, tx } => {
+                let visible = self.state.is_visible(id);
+                _ = tx.send(visible);
+                if !visible {
+                    return Task::none();
+                }
+                self.close(id, CloseReason::Dismissed)
+                    .unwrap_or(Task::none())
+            }
+            ControlRequest::ReloadConfig { tx } => {
+                let Some(helper) = &self.config_helper else {
+                    _ = tx.send(false);
+                    return Task::none();
+                };
+                let config =
+                    NotificationsConfig::get_entry(helper).unwrap_or_else(|(errors, config)| {
+                        for err in errors {
+                            if err.is_err() {
+                                tracing::error!("{:?}", err);
+                            }
                         }
-                    }
-                    config
-                })
-            })
-            .unwrap_or_default();
-        (
-            CosmicNotifications {
-                core,
-                active_surface: false,
-                autosize_id: iced::id::Id::new("autosize"),
-                window_id: SurfaceId::unique(),
-                anchor: None,
-                config,
-                dock_config: CosmicPanelConfig::default(),
-                panel_config: CosmicPanelConfig::default(),
-                notifications_id: id::Cards::new("Notifications"),
-                notifications_tx: None,
-                timeline: Timeline::new(),
-                state: NotificationState::new(),
-            },
-            Task::none(),
-        )
+                        config
+                    });
+                _ = tx.send(true);
+                tracing::info!("Reloaded configuration over D-Bus");
+                self.handle_message(Message::Config(config))
+            }
+            ControlRequest::DismissAll { tx } => {
+                let ids: Vec<_> = self.state.visible().iter().map(|n| n.id).collect();
+                self.dismiss(ids, tx)
+            }
+            ControlRequest::DismissApp { app, tx } => {
+                let ids: Vec<_> = self
+                    .state
+                    .visible()
+                    .iter()
+                    .filter(|n| n.app_name == app || n.app_id() == Some(app.as_str()))
+                    .map(|n| n.id)
+                    .collect();
+                self.dismiss(ids, tx)
+            }
+            ControlRequest::ShowHistoryItem { id, tx } => {
+                let notification = self.state.reshow(id);
+                _ = tx.send(notification.is_some());
+                let Some(notification) = notification else {
+                    return Task::none();
+                };
+                self.reshown.insert(id);
+                self.show_notification(notification)
+            }
+            ControlRequest::FocusPopups { tx } => {
+                let first = self.card_ids().first().copied();
+                _ = tx.send(first.is_some());
+                if first.is_none() {
+                    return Task::none();
+                }
+                tracing::info!("Keyboard navigation started over D-Bus");
+                self.focus(first);
+                self.update_keyboard()
+            }
+            ControlRequest::InvokeAction { id, action, tx } => {
+                let known = self
+                    .state
+                    .get(id)
+                    .is_some_and(|n| n.actions.iter().any(|(a, _)| a.to_string() == action));
+                _ = tx.send(known);
+                if !known {
+                    return Task::none();
+                }
+                self.request_activation(id, Some(action.parse().unwrap_or(ActionId::Default)))
+            }
+        }
     }
 
-    fn core(&self) -> &Core {
-        &self.core
+    /// Dismiss cards, replying how many there were
+    fn dismiss(&mut self, ids: Vec<u32>, tx: tokio::sync::oneshot::Sender<u32>) -> Task<Message> {
+        _ = tx.send(u32::try_from(ids.len()).unwrap_or(u32::MAX));
+        let tasks: Vec<_> = ids
+            .into_iter()
+            .filter_map(|id| self.close(id, CloseReason::Dismissed))
+            .collect();
+        Task::batch(tasks)
     }
 
-    fn core_mut(&mut self) -> &mut Core {
-        &mut self.core
+    /// Daemon state as published on the control interface
+    fn control_status(&self) -> control::Status {
+        let now = Local::now();
+        let paused_until = if !self.is_dnd_active() {
+            0
+        } else if self.inhibited {
+            -1
+        } else {
+            self.config
+                .dnd_ends_at(&now)
+                .map_or(-1, |at| at.timestamp())
+        };
+        control::Status {
+            do_not_disturb: self.config.manual_dnd_active_at(&now),
+            active_count: u32::try_from(self.state.visible_count()).unwrap_or(u32::MAX),
+            history_count: u32::try_from(self.state.hidden().len()).unwrap_or(u32::MAX),
+            unread_count: u32::try_from(self.state.unread_count()).unwrap_or(u32::MAX),
+            paused_until,
+        }
     }
 
-    fn view(&self) -> Element<'_, Self::Message> {
-        unimplemented!();
+    /// Publish the daemon state on the control interface if it changed
+    fn sync_status(&mut self) {
+        let status = self.control_status();
+        if status != self.status {
+            self.status = status;
+            self.send_input(notifications::Input::Status(status));
+        }
     }
 
     #[allow(clippy::too_many_lines)]
-    fn update(&mut self, message: Message) -> Task<Self::Message> {
+    fn handle_message(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::ActivateNotification(id) => {
+                if let Some(task) = self.activate_digest(id) {
+                    return task;
+                }
                 tracing::trace!("requesting token for {id}");
                 return self.request_activation(id, None);
             }
@@ -724,7 +1343,14 @@ impl cosmic::Application for CosmicNotifications {
                     }
                 }
                 notifications::Event::Ready(tx) => {
-                    self.notifications_tx = Some(tx);
+                    self.connect_inputs(tx);
+                    self.send_input(notifications::Input::Config(Box::new(self.config.clone())));
+                    self.send_input(notifications::Input::Status(self.status));
+                    self.send_input(notifications::Input::ReserveIds(self.state.next_free_id()));
+                    // Inhibitors do not survive a reconnect
+                    if std::mem::take(&mut self.inhibited) {
+                        return self.update_dnd();
+                    }
                 }
                 notifications::Event::AppletActivated { id, action } => {
                     tracing::trace!("requesting token for {id}");
@@ -737,21 +1363,92 @@ impl cosmic::Application for CosmicNotifications {
                         tracing::error!("Failed to send history response: {:?}", err);
                     }
                 }
+                notifications::Event::ClearHistory => {
+                    self.state.clear_history();
+                    self.send_input(notifications::Input::HistoryCleared);
+                }
+                notifications::Event::MissedDigest(n) => {
+                    if let Some(digest) = self.digest.as_mut().filter(|digest| digest.id.is_none())
+                    {
+                        digest.id = Some(n.id);
+                        let card = digest.notification();
+                        return self.push_notification(card);
+                    }
+                }
+                notifications::Event::Control(request) => {
+                    return self.handle_control(request);
+                }
+                notifications::Event::Inhibited(inhibited) => {
+                    self.inhibited = inhibited;
+                    return self.update_dnd();
+                }
+                notifications::Event::QueryHistory { query, tx } => {
+                    let page = query.run(self.state.hidden());
+                    if let Err(err) = tx.send(page) {
+                        tracing::error!("Failed to send history query response: {:?}", err);
+                    }
+                }
             },
             Message::Dismissed(id) => {
                 if let Some(c) = self.close(id, CloseReason::Dismissed) {
                     return c;
                 }
             }
-            Message::Timeout(id) => {
-                self.expire(id);
+            Message::ExpiryTick => {
+                let expired = self.state.timers_mut().take_expired(Instant::now());
+                for id in expired {
+                    self.expire(id);
+                }
                 if self.state.is_empty() && self.active_surface {
                     self.active_surface = false;
                     return destroy_layer_surface(self.window_id);
                 }
             }
+            Message::PauseExpiry(ids) => {
+                for id in ids {
+                    self.state.timers_mut().pause(id);
+                }
+            }
+            Message::ResumeExpiry(ids) => {
+                for id in ids {
+                    // A card being replied to or focused stays
+                    if self.holds_card(id) {
+                        continue;
+                    }
+                    self.state.timers_mut().resume(id);
+                }
+            }
+            Message::DndTransition => {
+                let now = Local::now();
+                if self.config.do_not_disturb && !self.config.manual_dnd_active_at(&now) {
+                    // Manual do-not-disturb ran out, so turn the toggle off
+                    self.set_manual_dnd(false, None);
+                }
+                tracing::info!(
+                    "Do not disturb is {}",
+                    if self.config.dnd_active_at(&now) {
+                        "on"
+                    } else {
+                        "off"
+                    }
+                );
+                return self.update_dnd();
+            }
             Message::Config(config) => {
+                self.send_input(notifications::Input::Config(Box::new(config.clone())));
+                let open_history = self.open_history(&config);
                 self.config = config;
+                // Regroup the cards already on screen for the new settings
+                self.sort_notifications();
+                self.group_notifications();
+                // Turning do-not-disturb off by hand shows what was missed
+                return Task::batch([open_history, self.update_dnd()]);
+            }
+            Message::HistoryOpened(opened) => {
+                self.state
+                    .history_opened(Arc::into_inner(opened).flatten(), &self.config);
+                // The history may hold IDs above the ones in use
+                self.send_input(notifications::Input::ReserveIds(self.state.next_free_id()));
             }
             Message::PanelConfig(c) => {
                 self.panel_config = c;
@@ -785,9 +1482,185 @@ impl cosmic::Application for CosmicNotifications {
                 tracing::trace!("action clicked for {id}: {action_id}");
                 return self.request_activation(id, Some(action_id.parse().unwrap_or(ActionId::Default)));
             }
+            Message::ToggleGroup(key) => {
+                self.state.toggle_group(&key);
+            }
+            Message::DismissGroup(key) => {
+                let ids = self.state.group_ids(self.config.grouping_mode, &key);
+                let tasks: Vec<_> = ids
+                    .into_iter()
+                    .filter_map(|id| self.close(id, CloseReason::Dismissed))
+                    .collect();
+                return Task::batch(tasks);
+            }
+            Message::OpenReply(id) => {
+                if !self.state.is_visible(id) {
+                    return Task::none();
+                }
+                if let Some((previous, _)) = self.reply.replace((id, String::new())) {
+                    if !self.holds_card(previous) {
+                        self.state.timers_mut().resume(previous);
+                    }
+                }
+                self.state.timers_mut().pause(id);
+                // The popup takes no keyboard focus unless a reply is typed
+                return Task::batch([
+                    self.update_keyboard(),
+                    text_input::focus(self.reply_input_id.clone()),
+                ]);
+            }
+            Message::ReplyInput(text) => {
+                if let Some((_, draft)) = &mut self.reply {
+                    *draft = text;
+                }
+            }
+            Message::SendReply => {
+                let Some((id, text)) = self.reply.take() else {
+                    return Task::none();
+                };
+                if text.trim().is_empty() {
+                    self.reply = Some((id, text));
+                    return Task::none();
+                }
+                self.send_input(notifications::Input::Replied { id, text });
+                let keyboard = self.update_keyboard();
+                // Like an invoked action, replying closes all but resident cards
+                if self.state.get(id).is_some_and(Notification::resident) {
+                    if !self.holds_card(id) {
+                        self.state.timers_mut().resume(id);
+                    }
+                    return keyboard;
+                }
+                let close = self
+                    .close(id, CloseReason::Dismissed)
+                    .unwrap_or(Task::none());
+                return Task::batch([keyboard, close]);
+            }
+            Message::CancelReply => {
+                if let Some((id, _)) = self.reply.take() {
+                    if !self.holds_card(id) {
+                        self.state.timers_mut().resume(id);
+                    }
+                    return self.update_keyboard();
+                }
+            }
+            Message::Navigate(navigation) => {
+                return self.navigate(navigation);
+            }
         }
         Task::none()
     }
+}
+
+/// Action buttons shown on a card: all but the default action and the
+/// inline reply, which gets its own row, up to `MAX_VISIBLE_ACTIONS`
+fn visible_actions(n: &Notification) -> impl Iterator<Item = &(ActionId, String)> {
+    n.actions
+        .iter()
+        .filter(|(id, _)| !matches!(id, ActionId::Default))
+        .filter(|(id, _)| !matches!(id, ActionId::Custom(id) if id == INLINE_REPLY_ACTION))
+        .take(MAX_VISIBLE_ACTIONS)
+}
+
+/// What a key does in keyboard navigation mode
+///
+/// Number keys invoke the action buttons from the left.
+fn navigation_key(key: Key, _modifiers: Modifiers) -> Option<Message> {
+    let navigation = match key.as_ref() {
+        Key::Named(Named::ArrowUp) => Navigation::Previous,
+        Key::Named(Named::ArrowDown) => Navigation::Next,
+        Key::Named(Named::Enter) => Navigation::Activate,
+        Key::Named(Named::Delete) => Navigation::Dismiss,
+        Key::Named(Named::Escape) => Navigation::Release,
+        Key::Character(c) => match c.parse::<usize>() {
+            Ok(n @ 1..=9) => Navigation::Action(n - 1),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(Message::Navigate(navigation))
+}
+
+impl cosmic::Application for CosmicNotifications {
+    type Message = Message;
+    type Executor = cosmic::executor::single::Executor;
+    type Flags = ();
+    const APP_ID: &'static str = "io.github.olafkfreund.CosmicExtNotifications";
+
+    fn init(core: Core, _flags: ()) -> (Self, Task<Message>) {
+        let helper = Config::new(
+            cosmic_ext_notifications_config::ID,
+            NotificationsConfig::VERSION,
+        )
+        .ok();
+
+        let config: NotificationsConfig = helper
+            .as_ref()
+            .map(|helper| {
+                NotificationsConfig::get_entry(helper).unwrap_or_else(|(errors, config)| {
+                    for err in errors {
+                        if err.is_err() {
+                            tracing::error!("{:?}", err);
+                        }
+                    }
+                    config
+                })
+            })
+            .unwrap_or_default();
+
+        let mut state = NotificationState::new();
+        // Opened right away, so that numbering starts above its IDs
+        if let Some(opener) = state.configure_history(&config) {
+            state.history_opened(opener.open(), &config);
+        }
+        let first_id = state.next_free_id();
+        let dnd_active = config.dnd_active_at(&Local::now());
+        (
+            CosmicNotifications {
+                core,
+                active_surface: false,
+                autosize_id: iced::id::Id::new("autosize"),
+                window_id: SurfaceId::unique(),
+                anchor: None,
+                config,
+                config_helper: helper,
+                dock_config: CosmicPanelConfig::default(),
+                panel_config: CosmicPanelConfig::default(),
+                notifications_id: id::Cards::new("Notifications"),
+                notifications_tx: None,
+                timeline: Timeline::new(),
+                state,
+                first_id,
+                dnd_active,
+                inhibited: false,
+                digest: None,
+                reshown: HashSet::new(),
+                status: control::Status::default(),
+                reply: None,
+                reply_input_id: iced::id::Id::new("inline-reply"),
+                focused: None,
+            },
+            Task::none(),
+        )
+    }
+
+    fn core(&self) -> &Core {
+        &self.core
+    }
+
+    fn core_mut(&mut self) -> &mut Core {
+        &mut self.core
+    }
+
+    fn view(&self) -> Element<'_, Self::Message> {
+        unimplemented!();
+    }
+
+    fn update(&mut self, message: Message) -> Task<Self::Message> {
+        let task = self.handle_message(message);
+        self.sync_status();
+        task
+    }
 
     #[allow(clippy::too_many_lines)]
     fn view_window(&self, _: SurfaceId) -> Element<'_, Message> {
@@ -801,17 +1674,45 @@ impl cosmic::Application for CosmicNotifications {
         // Get rich card config from settings
         let card_config = RichCardConfig::from_notifications_config(&self.config);
 
-        let (ids, notif_elems): (Vec<_>, Vec<_>) = self
-            .state
-            .visible()
-            .iter()
-            .rev()
-            .map(|n| {
-                let e = self.render_rich_notification(n, &card_config);
-                (n.id, e)
-            })
-            .take(self.effective_max_notifications())
-            .unzip();
+        // Each card comes with the message sent when it is clicked:
+        // single notifications are activated, stacked groups toggle.
+        let (on_click, notif_elems): (Vec<_>, Vec<_>) =
+            if self.config.grouping_mode == GroupingMode::None {
+                self.state
+                    .visible()
+                    .iter()
+                    .rev()
+                    .map(|n| {
+                        let e = self.render_rich_notification(n, &card_config);
+                        (
+                            Message::ActivateNotification(n.id),
+                            self.hover_pauses(vec![n.id], e),
+                        )
+                    })
+                    .take(self.effective_max_notifications())
+                    .unzip()
+            } else {
+                self.state
+                    .groups(self.config.grouping_mode)
+                    .iter()
+                    .filter_map(|group| {
+                        let (on_click, card) = match group.notifications.as_slice() {
+                            [n] => (
+                                Message::ActivateNotification(n.id),
+                                self.render_rich_notification(n, &card_config),
+                            ),
+                            [] => return None,
+                            _ => (
+                                Message::ToggleGroup(group.key.clone()),
+                                self.render_group(group, &card_config),
+                            ),
+                        };
+                        let ids = group.notifications.iter().map(|n| n.id).collect();
+                        Some((on_click, self.hover_pauses(ids, card)))
+                    })
+                    .take(self.effective_max_notifications())
+                    .unzip()
+            };
 
         // Card list with animations - width increased from 300px to 380px
         // for rich notifications with images and progress bars.
@@ -824,7 +1725,7 @@ impl cosmic::Application for CosmicNotifications {
             notif_elems,
             Message::Ignore,
             None::<fn(cosmic_time::chain::Cards, bool) -> Message>,
-            Some(move |id| Message::ActivateNotification(ids[id])),
+            Some(move |i: usize| on_click[i].clone()),
             "",
             "",
             "",
@@ -883,7 +1784,10 @@ impl cosmic::Application for CosmicNotifications {
             self.timeline
                 .as_subscription()
                 .map(|(_, now)| Message::Frame(now)),
-            notifications::notifications().map(Message::Notification),
+            notifications::notifications(self.first_id).map(Message::Notification),
+            self.expiry_subscription(),
+            self.dnd_subscription(),
+            self.keyboard_subscription(),
         ])
     }
 }
@@ -26,6 +26,12 @@ pub(crate) const AUTOSIZE_MIN_HEIGHT: f32 = 100.0;
 /// Padding inside notification cards
 pub(crate) const CARD_PADDING: u16 = 12;
 
+/// Height of the countdown bar at the bottom of cards (pixels)
+pub(crate) const COUNTDOWN_BAR_HEIGHT: f32 = 2.0;
+
+/// Width of the border around the card focused by keyboard navigation (pixels)
+pub(crate) const FOCUS_RING_WIDTH: f32 = 2.0;
+
 // ============================================================================
 // Icon Size Constants
 // ============================================================================
@@ -56,19 +62,32 @@ pub(crate) const MAX_HIDDEN_MEMORY: usize = 50 * 1024 * 1024;
 /// Initial capacity for notification cards vector
 pub(crate) const INITIAL_CARDS_CAPACITY: usize = 50;
 
+/// Interval for checking card expiry timers (milliseconds)
+pub(crate) const EXPIRY_TICK_MS: u64 = 250;
+
+/// Interval for checking card expiry timers while countdown bars are shown (milliseconds)
+pub(crate) const COUNTDOWN_TICK_MS: u64 = 50;
+
+/// Longest sleep while waiting for a do-not-disturb transition (seconds),
+/// so the wall clock is checked again soon after a suspend
+pub(crate) const DND_CHECK_INTERVAL_SECS: u64 = 60;
+
 // ============================================================================
 // Rate Limiting Constants
 // ============================================================================
 
-/// Maximum notifications per minute per app
-pub(crate) const RATE_LIMIT_PER_MINUTE: u32 = 60;
-
 /// Maximum number of apps tracked by rate limiter
 pub(crate) const RATE_LIMIT_MAX_APPS: usize = 1000;
 
 /// Interval for rate limiter cleanup (in notification count)
 pub(crate) const RATE_LIMIT_CLEANUP_INTERVAL: u64 = 100;
 
+/// Maximum inhibitors a single client may hold at once
+pub(crate) const MAX_INHIBITORS_PER_CLIENT: usize = 16;
+
+/// Maximum hook scripts running at once, further ones are skipped
+pub(crate) const MAX_CONCURRENT_HOOKS: usize = 4;
+
 // ============================================================================
 // Channel and Buffer Constants
 // ============================================================================
@@ -76,6 +95,16 @@ pub(crate) const RATE_LIMIT_CLEANUP_INTERVAL: u64 = 100;
 /// Buffer size for notification channel
 pub(crate) const CHANNEL_BUFFER_SIZE: usize = 100;
 
+/// Events queued for each event socket subscriber before older ones are
+/// skipped
+pub(crate) const EVENT_SOCKET_BUFFER_SIZE: usize = 256;
+
+/// Maximum clients subscribed to the event socket at once
+pub(crate) const MAX_EVENT_SUBSCRIBERS: usize = 16;
+
+/// Time a subscriber has to accept an event before it is disconnected (seconds)
+pub(crate) const EVENT_SOCKET_WRITE_TIMEOUT: u64 = 5;
+
 // ============================================================================
 // URL Display Constants
 // ============================================================================
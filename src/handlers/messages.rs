@@ -1,7 +1,9 @@
+use crate::state::LoadedHistory;
 use crate::subscriptions::notifications;
 use cosmic::surface;
 use cosmic_ext_notifications_util::ActionId;
 use cosmic_time::Instant;
+use std::sync::Arc;
 
 /// Application message types
 #[derive(Debug, Clone)]
@@ -14,10 +16,18 @@ pub enum Message {
     Dismissed(u32),
     /// Notification event from subscription
     Notification(notifications::Event),
-    /// Notification timeout expired
-    Timeout(u32),
+    /// Periodic check of card expiry timers
+    ExpiryTick,
+    /// Pointer entered a card: pause the expiry of its notifications
+    PauseExpiry(Vec<u32>),
+    /// Pointer left a card: resume the expiry of its notifications
+    ResumeExpiry(Vec<u32>),
+    /// Do-not-disturb may have turned on or off
+    DndTransition,
     /// Configuration updated
     Config(cosmic_ext_notifications_config::NotificationsConfig),
+    /// On-disk history opened in the background, `None` if it failed
+    HistoryOpened(Arc<Option<LoadedHistory>>),
     /// Panel configuration updated
     PanelConfig(cosmic_panel_config::CosmicPanelConfig),
     /// Dock configuration updated
@@ -33,4 +43,35 @@ pub enum Message {
     LinkClicked(String),
     /// Action button clicked (notification_id, action_id)
     ActionClicked(u32, String),
+    /// Expand or collapse a notification group (group key)
+    ToggleGroup(String),
+    /// Dismiss every notification in a group (group key)
+    DismissGroup(String),
+    /// Open the inline reply field of a notification
+    OpenReply(u32),
+    /// Text typed into the inline reply field
+    ReplyInput(String),
+    /// Send the inline reply
+    SendReply,
+    /// Close the inline reply field without sending
+    CancelReply,
+    /// Key pressed in keyboard navigation mode
+    Navigate(Navigation),
+}
+
+/// What a key does in keyboard navigation mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Navigation {
+    /// Focus the card above
+    Previous,
+    /// Focus the card below
+    Next,
+    /// Activate the default action of the focused card
+    Activate,
+    /// Invoke the nth action button of the focused card, from 0
+    Action(usize),
+    /// Dismiss the focused card
+    Dismiss,
+    /// Give the keyboard back
+    Release,
 }
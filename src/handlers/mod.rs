@@ -1,3 +1,3 @@
 pub mod messages;
 
-pub use messages::Message;
+pub use messages::{Message, Navigation};
@@ -1,12 +1,12 @@
 use crate::handlers::Message;
 use crate::widgets::{notification_image, ImageSize};
 use crate::constants::*;
-use cosmic::iced::Length;
-use cosmic::iced_widget::{column, container};
+use cosmic::iced::{Alignment, Length};
+use cosmic::iced_widget::{column, container, row};
 use cosmic::widget::{icon, text};
 use cosmic::Element;
 use cosmic_ext_notifications_util::{
-    parse_markup, sanitize_html, Image, Notification, NotificationImage,
+    parse_markup, sanitize_html, Image, Notification, NotificationGroup, NotificationImage,
     NotificationLink, ProcessedImage,
 };
 
@@ -146,6 +146,58 @@ pub fn render_body_with_links(
         .into()
 }
 
+/// Render the header of a stacked notification group
+///
+/// Shows the group name with an optional count badge (e.g. "Firefox (3)"),
+/// a toggle to expand or collapse the group and a button dismissing it.
+pub fn render_group_header(
+    group: &NotificationGroup,
+    show_count: bool,
+) -> Element<'static, Message> {
+    use cosmic::widget::button;
+
+    let label = if show_count {
+        group.label()
+    } else {
+        group.display_name.clone()
+    };
+
+    let toggle_icon = if group.expanded {
+        "go-up-symbolic"
+    } else {
+        "go-down-symbolic"
+    };
+
+    let toggle_button = button::custom(
+        icon::from_name(toggle_icon)
+            .size(ICON_SIZE_SMALL)
+            .symbolic(true),
+    )
+    .on_press(Message::ToggleGroup(group.key.clone()))
+    .class(cosmic::theme::Button::Text);
+
+    let dismiss_button = button::custom(
+        icon::from_name("edit-clear-all-symbolic")
+            .size(ICON_SIZE_SMALL)
+            .symbolic(true),
+    )
+    .on_press(Message::DismissGroup(group.key.clone()))
+    .class(cosmic::theme::Button::Text);
+
+    container(
+        row![
+            text::caption(label).width(Length::Fill),
+            toggle_button,
+            dismiss_button
+        ]
+        .spacing(8)
+        .align_y(Alignment::Center),
+    )
+    .padding([4, CARD_PADDING])
+    .width(Length::Fill)
+    .into()
+}
+
 /// Extract progress value from notification hints
 pub fn get_progress_from_hints(n: &Notification) -> Option<f32> {
     use cosmic_ext_notifications_util::Hint;
@@ -0,0 +1,176 @@
+use cosmic_ext_notifications_util::{Hint, Notification, Routing};
+use std::time::SystemTime;
+
+/// Missed notifications listed on an expanded digest card
+const MAX_DIGEST_LINES: usize = 10;
+
+/// A notification held back by do-not-disturb
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Missed {
+    pub id: u32,
+    pub app_name: String,
+    pub summary: String,
+}
+
+impl Missed {
+    pub fn new(n: &Notification) -> Self {
+        let app_name = if n.app_name.is_empty() {
+            n.app_id().unwrap_or("Unknown app").to_string()
+        } else {
+            n.app_name.clone()
+        };
+        Self {
+            id: n.id,
+            app_name,
+            summary: n.summary.clone(),
+        }
+    }
+}
+
+/// "While you were away" card summarizing what do-not-disturb held back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissedDigest {
+    /// ID of the card, once the daemon handed one out
+    pub id: Option<u32>,
+    /// Whether the card lists the missed notifications instead of counts
+    pub expanded: bool,
+    /// Missed notifications, oldest first
+    missed: Vec<Missed>,
+}
+
+impl MissedDigest {
+    /// Digest of the given missed notifications, `None` if there are none
+    pub fn new(missed: Vec<Missed>) -> Option<Self> {
+        (!missed.is_empty()).then_some(Self {
+            id: None,
+            expanded: false,
+            missed,
+        })
+    }
+
+    /// Add notifications missed since the card was shown
+    pub fn extend(&mut self, missed: Vec<Missed>) {
+        self.missed.extend(missed);
+    }
+
+    /// Missed notifications per app, most first
+    pub fn counts(&self) -> Vec<(&str, usize)> {
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for missed in &self.missed {
+            match counts.iter_mut().find(|(app, _)| *app == missed.app_name) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((&missed.app_name, 1)),
+            }
+        }
+        // Stable, so apps with equal counts keep the order they first came in
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        counts
+    }
+
+    /// Counts per app on one line, e.g. "Slack 5, Mail 2"
+    pub fn counts_line(&self) -> String {
+        self.counts()
+            .iter()
+            .map(|(app, count)| format!("{app} {count}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// One line per missed notification, newest first
+    pub fn details(&self) -> String {
+        let mut lines: Vec<_> = self
+            .missed
+            .iter()
+            .rev()
+            .take(MAX_DIGEST_LINES)
+            .map(|missed| format!("{}: {}", missed.app_name, missed.summary))
+            .collect();
+        if self.missed.len() > MAX_DIGEST_LINES {
+            lines.push(format!(
+                "and {} more in history",
+                self.missed.len() - MAX_DIGEST_LINES
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// The digest card, staying on screen until clicked or dismissed
+    pub fn notification(&self) -> Notification {
+        let count = self.missed.len();
+        Notification {
+            id: self.id.unwrap_or_default(),
+            app_name: "Notifications".to_string(),
+            app_icon: "preferences-system-notifications-symbolic".to_string(),
+            summary: format!(
+                "While you were away: {count} notification{}",
+                if count == 1 { "" } else { "s" }
+            ),
+            body: if self.expanded {
+                self.details()
+            } else {
+                self.counts_line()
+            },
+            actions: Vec::new(),
+            hints: vec![Hint::Transient(true)],
+            expire_timeout: 0,
+            time: SystemTime::now(),
+            sender: None,
+            routing: Routing::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn missed(id: u32, app_name: &str) -> Missed {
+        Missed {
+            id,
+            app_name: app_name.to_string(),
+            summary: format!("Message {id}"),
+        }
+    }
+
+    #[test]
+    fn test_empty_digest() {
+        assert_eq!(MissedDigest::new(Vec::new()), None);
+    }
+
+    #[test]
+    fn test_counts_by_app() {
+        let digest = MissedDigest::new(vec![
+            missed(1, "Mail"),
+            missed(2, "Slack"),
+            missed(3, "Slack"),
+            missed(4, "Mail"),
+            missed(5, "Slack"),
+            missed(6, "Calendar"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            digest.counts(),
+            [("Slack", 3), ("Mail", 2), ("Calendar", 1)]
+        );
+        assert_eq!(digest.counts_line(), "Slack 3, Mail 2, Calendar 1");
+
+        let card = digest.notification();
+        assert_eq!(card.summary, "While you were away: 6 notifications");
+        assert_eq!(card.body, "Slack 3, Mail 2, Calendar 1");
+        assert!(card.transient());
+    }
+
+    #[test]
+    fn test_expanded_lists_newest_first() {
+        let mut digest = MissedDigest::new(vec![missed(1, "Mail")]).unwrap();
+        digest.extend((2..=12).map(|id| missed(id, "Slack")).collect());
+        digest.expanded = true;
+
+        let body = digest.notification().body;
+        let lines: Vec<_> = body.lines().collect();
+        assert_eq!(lines.len(), MAX_DIGEST_LINES + 1);
+        assert_eq!(lines[0], "Slack: Message 12");
+        assert_eq!(lines[MAX_DIGEST_LINES], "and 2 more in history");
+    }
+}
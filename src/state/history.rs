@@ -0,0 +1,1049 @@
+use cosmic_ext_notifications_config::NotificationsConfig;
+use cosmic_ext_notifications_util::{Hint, Image, Notification};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write as _;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+/// Append-only log of history records inside the history directory
+const LOG_FILE: &str = "history.jsonl";
+
+/// Directory of content-addressed image files inside the history directory
+const IMAGES_DIR: &str = "images";
+
+/// Log bytes allowed on top of the live records before the log is compacted
+const COMPACT_SLACK: u64 = 64 * 1024;
+
+/// Retention limits for the on-disk history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryRetention {
+    /// Maximum number of notifications kept
+    pub max_count: usize,
+    /// Maximum age of a notification, `None` keeps them forever
+    pub max_age: Option<Duration>,
+    /// Maximum bytes used by records and images together
+    pub max_bytes: u64,
+}
+
+impl HistoryRetention {
+    /// Create retention limits from the history settings
+    pub fn from_config(config: &NotificationsConfig) -> Self {
+        Self {
+            max_count: config.history_max_count as usize,
+            max_age: (config.history_max_age_days > 0).then(|| {
+                Duration::from_secs(u64::from(config.history_max_age_days) * 24 * 60 * 60)
+            }),
+            max_bytes: u64::from(config.history_max_size_mb) * 1024 * 1024,
+        }
+    }
+}
+
+/// A line of the history log
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum LogEntry {
+    /// A notification entered history, with its images stored separately
+    Add {
+        notification: Box<Notification>,
+        images: Vec<StoredImage>,
+    },
+    /// A notification left history
+    Remove { id: u32 },
+}
+
+/// Reference to image data kept in a content-addressed file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum StoredImage {
+    /// RGBA `image-data` hint
+    Data {
+        width: u32,
+        height: u32,
+        hash: String,
+    },
+    /// `icon_data` hint
+    IconData { hash: String },
+}
+
+impl StoredImage {
+    fn hash(&self) -> &str {
+        match self {
+            StoredImage::Data { hash, .. } | StoredImage::IconData { hash } => hash,
+        }
+    }
+}
+
+/// A live `Add` record read back from the log
+struct Record {
+    notification: Notification,
+    images: Vec<StoredImage>,
+    /// The raw log line, without its newline
+    line: Vec<u8>,
+}
+
+/// Bookkeeping for a notification kept in the store
+#[derive(Debug)]
+struct Entry {
+    id: u32,
+    time: SystemTime,
+    /// Size of its log record
+    bytes: u64,
+    /// Hashes of the images it references
+    images: Vec<String>,
+}
+
+/// Disk work queued for the writer thread, carried out in order
+#[derive(Debug)]
+enum Op {
+    /// Append a line to the log
+    Append(Vec<u8>),
+    /// Write image data to its content-addressed file, unless it exists
+    StoreImage { hash: String, data: Vec<u8> },
+    /// Delete an image file no entry references anymore
+    RemoveImage(String),
+    /// Rewrite the log with only the records of `live` and delete image
+    /// files not in `images`
+    Compact {
+        live: HashSet<u32>,
+        images: HashSet<String>,
+    },
+    /// Answered once everything queued before is on disk
+    Flush(mpsc::Sender<()>),
+}
+
+/// Notification history persisted under `$XDG_STATE_HOME`
+///
+/// Bookkeeping happens in memory, while a writer thread appends records to
+/// a JSON-lines log and syncs once per batch of queued writes, so callers
+/// never wait for the disk. A crash loses at most the last batch; damaged
+/// lines are skipped on load and the log is compacted. Image data is written
+/// once per content hash instead of being embedded in every record.
+/// Dropping the store waits for queued writes to finish, `close` does not.
+#[derive(Debug)]
+pub struct HistoryStore {
+    dir: PathBuf,
+    /// Queue of the writer thread, and its handle to join on drop
+    writer: Option<(mpsc::Sender<Op>, JoinHandle<()>)>,
+    retention: HistoryRetention,
+    /// Live notifications, oldest first
+    entries: VecDeque<Entry>,
+    /// Image hash -> (file size, number of entries referencing it)
+    images: HashMap<String, (u64, usize)>,
+    /// Bytes used by the records of live notifications
+    record_bytes: u64,
+    /// Bytes used by referenced image files
+    image_bytes: u64,
+    /// Bytes in the log file, including superseded records
+    log_bytes: u64,
+}
+
+/// Store and notifications loaded by `HistoryStore::open`
+pub type LoadedHistory = (HistoryStore, Vec<Notification>);
+
+/// How to open the history, which reads the disk and is best done off the
+/// UI thread
+#[derive(Debug)]
+pub struct HistoryOpener {
+    dir: PathBuf,
+    retention: HistoryRetention,
+    /// Writers of stores closed before, which must finish first
+    closing: Vec<JoinHandle<()>>,
+}
+
+impl HistoryOpener {
+    /// Open the history in `dir` once the `closing` writers are done
+    pub fn new(dir: PathBuf, retention: HistoryRetention, closing: Vec<JoinHandle<()>>) -> Self {
+        Self {
+            dir,
+            retention,
+            closing,
+        }
+    }
+
+    /// Open the history, blocking on the disk
+    ///
+    /// Returns `None` if it failed, which is logged.
+    pub fn open(self) -> Option<LoadedHistory> {
+        for handle in self.closing {
+            if handle.join().is_err() {
+                tracing::error!("History writer panicked");
+            }
+        }
+
+        match HistoryStore::open(&self.dir, self.retention) {
+            Ok((store, loaded)) => {
+                tracing::info!(
+                    "Loaded {} notifications from {}",
+                    loaded.len(),
+                    self.dir.display()
+                );
+                Some((store, loaded))
+            }
+            Err(err) => {
+                tracing::error!("Failed to open history in {}: {err}", self.dir.display());
+                None
+            }
+        }
+    }
+}
+
+impl HistoryStore {
+    /// Default history directory, `$XDG_STATE_HOME/cosmic-ext-notifications`
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::state_dir().map(|dir| dir.join("cosmic-ext-notifications"))
+    }
+
+    /// Open the history in `dir`, creating it if needed
+    ///
+    /// Returns the store along with the notifications kept after applying
+    /// the retention limits, newest first.
+    pub fn open(
+        dir: impl Into<PathBuf>,
+        retention: HistoryRetention,
+    ) -> io::Result<(Self, Vec<Notification>)> {
+        let dir = dir.into();
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir.join(IMAGES_DIR))?;
+
+        let log_path = dir.join(LOG_FILE);
+        let records = read_log(&log_path)?;
+        let writer = Writer {
+            log: open_log(&log_path)?,
+            dir: dir.clone(),
+        };
+        let (ops, rx) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("history-writer".to_string())
+            .spawn(move || writer.run(rx))?;
+        let mut store = Self {
+            dir,
+            writer: Some((ops, handle)),
+            retention,
+            entries: VecDeque::new(),
+            images: HashMap::new(),
+            record_bytes: 0,
+            image_bytes: 0,
+            log_bytes: 0,
+        };
+
+        let mut loaded = Vec::with_capacity(records.len());
+        for record in records {
+            let images = record
+                .images
+                .iter()
+                .filter(|image| store.reference_existing_image(image.hash()))
+                .map(|image| image.hash().to_string())
+                .collect();
+            let bytes = record.line.len() as u64 + 1;
+            store.record_bytes += bytes;
+            store.entries.push_back(Entry {
+                id: record.notification.id,
+                time: record.notification.time,
+                bytes,
+                images,
+            });
+            loaded.push((record.notification, record.images));
+        }
+
+        // Drops damaged lines and superseded records as well
+        store.prune();
+        store.compact();
+
+        let live: HashSet<u32> = store.entries.iter().map(|e| e.id).collect();
+        let notifications = loaded
+            .into_iter()
+            .rev()
+            .filter(|(n, _)| live.contains(&n.id))
+            .map(|(n, images)| store.restore_images(n, &images))
+            .collect();
+
+        Ok((store, notifications))
+    }
+
+    /// Add a notification to history
+    ///
+    /// A notification with the same ID is replaced. Transient notifications
+    /// are never persisted, and neither is the identity of the sender.
+    /// Returns the IDs dropped to stay within the retention limits.
+    pub fn append(&mut self, notification: &Notification) -> io::Result<Vec<u32>> {
+        if notification.transient() {
+            return Ok(Vec::new());
+        }
+
+        // The sender's identity is never serialized
+        let (stripped, images) = self.store_images(notification);
+        let hashes = images
+            .iter()
+            .map(|image| image.hash().to_string())
+            .collect();
+        let bytes = self.write_entry(&LogEntry::Add {
+            notification: Box::new(stripped),
+            images,
+        })?;
+
+        if let Some(pos) = self.entries.iter().position(|e| e.id == notification.id) {
+            if let Some(old) = self.entries.remove(pos) {
+                self.forget(&old);
+            }
+        }
+        self.record_bytes += bytes;
+        self.entries.push_back(Entry {
+            id: notification.id,
+            time: notification.time,
+            bytes,
+            images: hashes,
+        });
+
+        let dropped = self.prune_logged();
+        self.maybe_compact();
+        Ok(dropped)
+    }
+
+    /// Remove a notification from history
+    ///
+    /// Returns `false` if it was not in history.
+    pub fn remove(&mut self, id: u32) -> bool {
+        let Some(pos) = self.entries.iter().position(|e| e.id == id) else {
+            return false;
+        };
+
+        self.write_remove(id);
+        if let Some(entry) = self.entries.remove(pos) {
+            self.forget(&entry);
+        }
+        self.maybe_compact();
+        true
+    }
+
+    /// Remove every notification from history
+    pub fn clear(&mut self) {
+        while let Some(entry) = self.entries.pop_front() {
+            self.forget(&entry);
+        }
+        self.compact();
+    }
+
+    /// Close the store without waiting for queued writes
+    ///
+    /// Returns the writer thread, which ends once they are on disk.
+    pub fn close(mut self) -> Option<JoinHandle<()>> {
+        let (ops, handle) = self.writer.take()?;
+        // The writer finishes the queue once its sender is gone
+        drop(ops);
+        Some(handle)
+    }
+
+    /// Change the retention limits
+    ///
+    /// Returns the IDs dropped to stay within the new limits.
+    pub fn set_retention(&mut self, retention: HistoryRetention) -> Vec<u32> {
+        self.retention = retention;
+        let dropped = self.prune_logged();
+        self.maybe_compact();
+        dropped
+    }
+
+    /// Number of notifications in history
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if history is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Highest notification ID in history
+    pub fn max_id(&self) -> Option<u32> {
+        self.entries.iter().map(|e| e.id).max()
+    }
+
+    /// Bytes used by records and images of the notifications in history
+    pub fn size_bytes(&self) -> u64 {
+        self.record_bytes + self.image_bytes
+    }
+
+    /// Drop the oldest entries exceeding the retention limits
+    fn prune(&mut self) -> Vec<u32> {
+        let now = SystemTime::now();
+        let mut dropped = Vec::new();
+
+        while let Some(oldest) = self.entries.front() {
+            let too_old = self.retention.max_age.is_some_and(|max_age| {
+                now.duration_since(oldest.time).unwrap_or_default() > max_age
+            });
+            let too_many = self.entries.len() > self.retention.max_count;
+            let too_big = self.size_bytes() > self.retention.max_bytes;
+            if !(too_old || too_many || too_big) {
+                break;
+            }
+
+            if let Some(entry) = self.entries.pop_front() {
+                self.forget(&entry);
+                dropped.push(entry.id);
+            }
+        }
+
+        dropped
+    }
+
+    /// Drop the entries exceeding the retention limits, logging their removal
+    fn prune_logged(&mut self) -> Vec<u32> {
+        let dropped = self.prune();
+        for id in &dropped {
+            self.write_remove(*id);
+        }
+        dropped
+    }
+
+    /// Release the record and images of an entry that left history
+    fn forget(&mut self, entry: &Entry) {
+        self.record_bytes = self.record_bytes.saturating_sub(entry.bytes);
+        for hash in &entry.images {
+            let Some((size, refs)) = self.images.get_mut(hash) else {
+                continue;
+            };
+            *refs -= 1;
+            if *refs == 0 {
+                self.image_bytes = self.image_bytes.saturating_sub(*size);
+                self.images.remove(hash);
+                self.send(Op::RemoveImage(hash.clone()));
+            }
+        }
+    }
+
+    /// Queue disk work for the writer thread
+    fn send(&self, op: Op) {
+        let Some((ops, _)) = &self.writer else {
+            return;
+        };
+        if ops.send(op).is_err() {
+            tracing::error!("History writer stopped, changes are no longer saved");
+        }
+    }
+
+    /// Queue a record for the log
+    ///
+    /// Returns the number of bytes it takes up.
+    fn write_entry(&mut self, entry: &LogEntry) -> io::Result<u64> {
+        let mut line = serde_json::to_vec(entry).map_err(io::Error::other)?;
+        line.push(b'\n');
+        let bytes = line.len() as u64;
+        self.send(Op::Append(line));
+        self.log_bytes += bytes;
+        Ok(bytes)
+    }
+
+    /// Queue the removal of a notification for the log
+    fn write_remove(&mut self, id: u32) {
+        if let Err(err) = self.write_entry(&LogEntry::Remove { id }) {
+            tracing::error!("Failed to remove notification {id} from history: {err}");
+        }
+    }
+
+    /// Compact the log once superseded records take up too much space
+    fn maybe_compact(&mut self) {
+        if self.log_bytes > self.record_bytes.saturating_mul(2) + COMPACT_SLACK {
+            self.compact();
+        }
+    }
+
+    /// Rewrite the log with only the live records and delete unused images
+    fn compact(&mut self) {
+        self.send(Op::Compact {
+            live: self.entries.iter().map(|e| e.id).collect(),
+            images: self.images.keys().cloned().collect(),
+        });
+        // Once compacted the log holds exactly the live records
+        self.log_bytes = self.record_bytes;
+    }
+
+    /// Wait until everything queued so far is on disk
+    #[cfg(test)]
+    fn flush(&self) {
+        let (tx, rx) = mpsc::channel();
+        self.send(Op::Flush(tx));
+        _ = rx.recv();
+    }
+
+    /// Queue the image data of a notification for content-addressed files
+    ///
+    /// Returns the notification without its image data, and references to
+    /// the stored images.
+    fn store_images(&mut self, notification: &Notification) -> (Notification, Vec<StoredImage>) {
+        let mut stripped = notification.clone();
+        let mut images = Vec::new();
+
+        stripped.hints.clear();
+        for hint in &notification.hints {
+            match hint {
+                Hint::Image(Image::Data {
+                    width,
+                    height,
+                    data,
+                }) => images.push(StoredImage::Data {
+                    width: *width,
+                    height: *height,
+                    hash: self.store_image(data),
+                }),
+                Hint::IconData(data) => images.push(StoredImage::IconData {
+                    hash: self.store_image(data),
+                }),
+                hint => stripped.hints.push(hint.clone()),
+            }
+        }
+
+        (stripped, images)
+    }
+
+    /// Store image data under its content hash, referencing it once more
+    fn store_image(&mut self, data: &[u8]) -> String {
+        let hash = content_hash(data);
+        if let Some((_, refs)) = self.images.get_mut(&hash) {
+            *refs += 1;
+            return hash;
+        }
+
+        let size = data.len() as u64;
+        self.send(Op::StoreImage {
+            hash: hash.clone(),
+            data: data.to_vec(),
+        });
+        self.images.insert(hash.clone(), (size, 1));
+        self.image_bytes += size;
+        hash
+    }
+
+    /// Reference an image file found while loading the log
+    ///
+    /// Returns `false` if the file is missing.
+    fn reference_existing_image(&mut self, hash: &str) -> bool {
+        if let Some((_, refs)) = self.images.get_mut(hash) {
+            *refs += 1;
+            return true;
+        }
+
+        match fs::metadata(self.image_path(hash)) {
+            Ok(meta) => {
+                self.images.insert(hash.to_string(), (meta.len(), 1));
+                self.image_bytes += meta.len();
+                true
+            }
+            Err(err) => {
+                tracing::warn!("History image {hash} is unavailable: {err}");
+                false
+            }
+        }
+    }
+
+    /// Put stored image data back into the hints of a loaded notification
+    fn restore_images(
+        &self,
+        mut notification: Notification,
+        images: &[StoredImage],
+    ) -> Notification {
+        for image in images {
+            let data = match fs::read(self.image_path(image.hash())) {
+                Ok(data) => data,
+                Err(err) => {
+                    tracing::warn!("Failed to read history image {}: {err}", image.hash());
+                    continue;
+                }
+            };
+            notification.hints.push(match image {
+                StoredImage::Data { width, height, .. } => Hint::Image(Image::Data {
+                    width: *width,
+                    height: *height,
+                    data: Arc::new(data),
+                }),
+                StoredImage::IconData { .. } => Hint::IconData(data),
+            });
+        }
+        notification
+    }
+
+    fn image_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(IMAGES_DIR).join(hash)
+    }
+}
+
+impl Drop for HistoryStore {
+    fn drop(&mut self) {
+        if let Some((ops, handle)) = self.writer.take() {
+            // The writer finishes the queue once its sender is gone
+            drop(ops);
+            if handle.join().is_err() {
+                tracing::error!("History writer panicked");
+            }
+        }
+    }
+}
+
+/// Disk side of the history, run on its own thread
+struct Writer {
+    dir: PathBuf,
+    log: File,
+}
+
+impl Writer {
+    /// Carry out queued work until the store is dropped
+    ///
+    /// Work queued while the disk is busy is written in one batch and
+    /// synced once.
+    fn run(mut self, rx: mpsc::Receiver<Op>) {
+        while let Ok(op) = rx.recv() {
+            let mut waiting = Vec::new();
+            for op in std::iter::once(op).chain(rx.try_iter()) {
+                let result = match op {
+                    Op::Append(line) => self.log.write_all(&line),
+                    Op::StoreImage { hash, data } => self.store_image(&hash, &data),
+                    Op::RemoveImage(hash) => fs::remove_file(self.image_path(&hash)),
+                    Op::Compact { live, images } => self.compact(&live, &images),
+                    Op::Flush(tx) => {
+                        waiting.push(tx);
+                        Ok(())
+                    }
+                };
+                if let Err(err) = result {
+                    tracing::error!("Failed to write history: {err}");
+                }
+            }
+            if let Err(err) = self.log.sync_data() {
+                tracing::error!("Failed to sync history: {err}");
+            }
+            for tx in waiting {
+                _ = tx.send(());
+            }
+        }
+    }
+
+    /// Write image data to its content-addressed file
+    ///
+    /// The file is synced before it atomically appears under its hash.
+    fn store_image(&self, hash: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.image_path(hash);
+        if path.exists() {
+            return Ok(());
+        }
+        let tmp_path = path.with_extension("tmp");
+        let mut tmp = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)?;
+        tmp.write_all(data)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &path)
+    }
+
+    /// Rewrite the log with only the live records and delete unused images
+    ///
+    /// The new log is synced before it atomically replaces the old one.
+    fn compact(&mut self, live: &HashSet<u32>, images: &HashSet<String>) -> io::Result<()> {
+        let log_path = self.dir.join(LOG_FILE);
+        let tmp_path = self.dir.join(format!("{LOG_FILE}.tmp"));
+        let mut tmp = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)?;
+        for record in read_log(&log_path)? {
+            if live.contains(&record.notification.id) {
+                tmp.write_all(&record.line)?;
+                tmp.write_all(b"\n")?;
+            }
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &log_path)?;
+
+        self.log = open_log(&log_path)?;
+        self.remove_unused_images(images);
+        Ok(())
+    }
+
+    /// Delete image files no live entry references
+    fn remove_unused_images(&self, images: &HashSet<String>) {
+        let Ok(dir) = fs::read_dir(self.dir.join(IMAGES_DIR)) else {
+            return;
+        };
+        for file in dir.flatten() {
+            let name = file.file_name();
+            if !images.contains(&*name.to_string_lossy()) {
+                if let Err(err) = fs::remove_file(file.path()) {
+                    tracing::warn!("Failed to remove unused history image {:?}: {err}", name);
+                }
+            }
+        }
+    }
+
+    fn image_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(IMAGES_DIR).join(hash)
+    }
+}
+
+/// Open the log for appending, creating it if needed
+fn open_log(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)
+}
+
+/// Replay the log, returning the live `Add` records in order
+///
+/// Damaged lines, such as one cut short by a crash, are skipped.
+fn read_log(path: &Path) -> io::Result<Vec<Record>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut records: Vec<Option<Record>> = Vec::new();
+    let mut index: HashMap<u32, usize> = HashMap::new();
+    for (number, line) in BufReader::new(file).split(b'\n').enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+
+        match serde_json::from_slice(&line) {
+            Ok(LogEntry::Add {
+                notification,
+                images,
+            }) => {
+                if let Some(old) = index.insert(notification.id, records.len()) {
+                    records[old] = None;
+                }
+                records.push(Some(Record {
+                    notification: *notification,
+                    images,
+                    line,
+                }));
+            }
+            Ok(LogEntry::Remove { id }) => {
+                if let Some(old) = index.remove(&id) {
+                    records[old] = None;
+                }
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "Skipping damaged history record on line {}: {err}",
+                    number + 1
+                );
+            }
+        }
+    }
+
+    Ok(records.into_iter().flatten().collect())
+}
+
+/// Hex-encoded SHA-256 of image data
+pub(crate) fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmic_ext_notifications_util::SenderIdentity;
+
+    const RETENTION: HistoryRetention = HistoryRetention {
+        max_count: 100,
+        max_age: None,
+        max_bytes: 1024 * 1024,
+    };
+
+    fn notification(id: u32, hints: Vec<Hint>) -> Notification {
+        let summary = format!("Summary {id}");
+        let mut n = Notification::new(
+            "Test",
+            id,
+            "",
+            &summary,
+            "Body",
+            vec![],
+            HashMap::new(),
+            5000,
+        );
+        n.hints = hints;
+        n
+    }
+
+    fn image(byte: u8) -> Hint {
+        Hint::Image(Image::Data {
+            width: 2,
+            height: 2,
+            data: Arc::new(vec![byte; 16]),
+        })
+    }
+
+    fn ids(notifications: &[Notification]) -> Vec<u32> {
+        notifications.iter().map(|n| n.id).collect()
+    }
+
+    fn image_files(dir: &Path) -> usize {
+        fs::read_dir(dir.join(IMAGES_DIR)).unwrap().count()
+    }
+
+    #[test]
+    fn test_reload_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut store, loaded) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        assert!(loaded.is_empty());
+
+        store.append(&notification(1, vec![])).unwrap();
+        store
+            .append(&notification(2, vec![Hint::Urgency(2)]))
+            .unwrap();
+        drop(store);
+
+        let (store, loaded) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        assert_eq!(ids(&loaded), [2, 1]);
+        assert_eq!(loaded[0].urgency(), 2);
+        assert_eq!(store.max_id(), Some(2));
+    }
+
+    #[test]
+    fn test_close_leaves_writes_to_the_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut store, _) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        let original = notification(1, vec![image(1)]);
+        store.append(&original).unwrap();
+        let writer = store.close().unwrap();
+
+        // Opening again waits for the writer of the closed store
+        let opener = HistoryOpener::new(dir.path().to_path_buf(), RETENTION, vec![writer]);
+        let (_, loaded) = opener.open().unwrap();
+        assert_eq!(ids(&loaded), [1]);
+        assert_eq!(loaded[0].hints, original.hints);
+    }
+
+    #[test]
+    fn test_images_are_content_addressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut store, _) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+
+        store.append(&notification(1, vec![image(7)])).unwrap();
+        store.append(&notification(2, vec![image(7)])).unwrap();
+        store
+            .append(&notification(3, vec![Hint::IconData(vec![1, 2, 3])]))
+            .unwrap();
+        store.flush();
+        assert_eq!(image_files(dir.path()), 2);
+
+        // Records reference images instead of embedding them
+        let log = fs::read_to_string(dir.path().join(LOG_FILE)).unwrap();
+        assert!(!log.contains("[7,7,7"));
+
+        store.remove(1);
+        store.flush();
+        assert_eq!(image_files(dir.path()), 2);
+        store.remove(2);
+        store.flush();
+        assert_eq!(image_files(dir.path()), 1);
+        drop(store);
+
+        let (_, loaded) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        assert_eq!(loaded[0].hints, [Hint::IconData(vec![1, 2, 3])]);
+    }
+
+    #[test]
+    fn test_image_restored_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut store, _) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        let original = notification(1, vec![Hint::Urgency(1), image(9)]);
+        store.append(&original).unwrap();
+        drop(store);
+
+        let (_, loaded) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        assert_eq!(loaded[0].hints, original.hints);
+    }
+
+    #[test]
+    fn test_remove_and_replace_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut store, _) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        store.append(&notification(1, vec![])).unwrap();
+        store.append(&notification(2, vec![])).unwrap();
+        assert!(store.remove(1));
+        assert!(!store.remove(1));
+
+        let mut replaced = notification(2, vec![]);
+        replaced.summary = "Updated".to_string();
+        store.append(&replaced).unwrap();
+        assert_eq!(store.len(), 1);
+        drop(store);
+
+        let (_, loaded) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        assert_eq!(ids(&loaded), [2]);
+        assert_eq!(loaded[0].summary, "Updated");
+    }
+
+    #[test]
+    fn test_transient_not_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut store, _) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        store
+            .append(&notification(1, vec![Hint::Transient(true)]))
+            .unwrap();
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_sender_not_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut store, _) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        let mut sent = notification(1, vec![]);
+        sent.sender = Some(SenderIdentity {
+            bus_name: ":1.42".to_string(),
+            pid: Some(4242),
+            app_id: Some("org.example.Chat".to_string()),
+            sandboxed: false,
+            executable: Some(PathBuf::from("/usr/bin/chat")),
+        });
+        store.append(&sent).unwrap();
+        store.flush();
+
+        let log = fs::read_to_string(dir.path().join(LOG_FILE)).unwrap();
+        assert!(!log.contains("/usr/bin/chat"));
+        drop(store);
+
+        let (_, loaded) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        assert_eq!(loaded[0].sender, None);
+    }
+
+    #[test]
+    fn test_retention_by_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let retention = HistoryRetention {
+            max_count: 2,
+            ..RETENTION
+        };
+        let (mut store, _) = HistoryStore::open(dir.path(), retention).unwrap();
+
+        store.append(&notification(1, vec![])).unwrap();
+        store.append(&notification(2, vec![])).unwrap();
+        assert_eq!(store.append(&notification(3, vec![])).unwrap(), [1]);
+        drop(store);
+
+        let (_, loaded) = HistoryStore::open(dir.path(), retention).unwrap();
+        assert_eq!(ids(&loaded), [3, 2]);
+    }
+
+    #[test]
+    fn test_retention_by_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut store, _) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+
+        let mut old = notification(1, vec![]);
+        old.time = SystemTime::now() - Duration::from_secs(3 * 24 * 60 * 60);
+        store.append(&old).unwrap();
+        store.append(&notification(2, vec![])).unwrap();
+
+        let retention = HistoryRetention {
+            max_age: Some(Duration::from_secs(24 * 60 * 60)),
+            ..RETENTION
+        };
+        assert_eq!(store.set_retention(retention), [1]);
+        drop(store);
+
+        let (_, loaded) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        assert_eq!(ids(&loaded), [2]);
+    }
+
+    #[test]
+    fn test_retention_by_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let retention = HistoryRetention {
+            max_bytes: 1500,
+            ..RETENTION
+        };
+        let (mut store, _) = HistoryStore::open(dir.path(), retention).unwrap();
+
+        let big = |id, byte| {
+            notification(
+                id,
+                vec![Hint::Image(Image::Data {
+                    width: 16,
+                    height: 16,
+                    data: Arc::new(vec![byte; 1024]),
+                })],
+            )
+        };
+        store.append(&big(1, 1)).unwrap();
+        assert_eq!(store.append(&big(2, 2)).unwrap(), [1]);
+        assert!(store.size_bytes() <= 1500);
+        store.flush();
+        assert_eq!(image_files(dir.path()), 1);
+    }
+
+    #[test]
+    fn test_damaged_tail_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut store, _) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        store.append(&notification(1, vec![])).unwrap();
+        drop(store);
+
+        // Simulate a crash in the middle of writing a record
+        let mut log = open_log(&dir.path().join(LOG_FILE)).unwrap();
+        log.write_all(br#"{"op":"add","notification":{"id":2,"app_na"#)
+            .unwrap();
+        drop(log);
+
+        let (mut store, loaded) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        assert_eq!(ids(&loaded), [1]);
+
+        // Later records are not swallowed by the damaged line
+        store.append(&notification(3, vec![])).unwrap();
+        drop(store);
+        let (_, loaded) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        assert_eq!(ids(&loaded), [3, 1]);
+    }
+
+    #[test]
+    fn test_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut store, _) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        store.append(&notification(1, vec![image(1)])).unwrap();
+        store.clear();
+        assert!(store.is_empty());
+        store.flush();
+        assert_eq!(image_files(dir.path()), 0);
+        drop(store);
+
+        let (_, loaded) = HistoryStore::open(dir.path(), RETENTION).unwrap();
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn test_retention_from_config() {
+        let config = NotificationsConfig {
+            history_max_count: 10,
+            history_max_age_days: 0,
+            history_max_size_mb: 2,
+            ..Default::default()
+        };
+        let retention = HistoryRetention::from_config(&config);
+        assert_eq!(retention.max_count, 10);
+        assert_eq!(retention.max_age, None);
+        assert_eq!(retention.max_bytes, 2 * 1024 * 1024);
+    }
+}
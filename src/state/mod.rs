@@ -1,3 +1,9 @@
+pub mod digest;
+pub mod history;
 pub mod notifications;
+pub mod timers;
 
+pub use digest::{Missed, MissedDigest};
+pub use history::{HistoryOpener, HistoryRetention, HistoryStore, LoadedHistory};
 pub use notifications::NotificationState;
+pub use timers::ExpiryTimers;
@@ -1,8 +1,11 @@
 #![allow(dead_code)]
 
-use cosmic_ext_notifications_util::Notification;
-use std::collections::VecDeque;
+use cosmic_ext_notifications_config::{GroupingMode, NotificationsConfig};
+use cosmic_ext_notifications_util::{Notification, NotificationGroup, group_notifications};
+use std::collections::{HashSet, VecDeque};
+use std::thread::JoinHandle;
 use crate::constants::*;
+use super::{ExpiryTimers, HistoryOpener, HistoryRetention, HistoryStore, LoadedHistory, Missed};
 
 /// Manages the state of notification queues
 ///
@@ -14,6 +17,20 @@ pub struct NotificationState {
     cards: Vec<Notification>,
     /// Hidden notifications (dismissed or expired)
     hidden: VecDeque<Notification>,
+    /// Keys of notification groups the user has expanded
+    expanded_groups: HashSet<String>,
+    /// Expiry timers of visible cards
+    timers: ExpiryTimers,
+    /// On-disk history, when persistence is enabled
+    history: Option<HistoryStore>,
+    /// Whether the on-disk history is being opened
+    history_opening: bool,
+    /// Writers of closed histories, still finishing their queued writes
+    history_closing: Vec<JoinHandle<()>>,
+    /// Notifications held back by do-not-disturb since it was last turned off
+    missed: Vec<Missed>,
+    /// IDs of history entries that never made it to the screen
+    unread: HashSet<u32>,
 }
 
 impl NotificationState {
@@ -22,6 +39,13 @@ impl NotificationState {
         Self {
             cards: Vec::with_capacity(INITIAL_CARDS_CAPACITY),
             hidden: VecDeque::new(),
+            expanded_groups: HashSet::new(),
+            timers: ExpiryTimers::default(),
+            history: None,
+            history_opening: false,
+            history_closing: Vec::new(),
+            missed: Vec::new(),
+            unread: HashSet::new(),
         }
     }
 
@@ -45,6 +69,37 @@ impl NotificationState {
         &mut self.hidden
     }
 
+    /// Get the expiry timers of visible cards
+    pub fn timers(&self) -> &ExpiryTimers {
+        &self.timers
+    }
+
+    /// Get mutable reference to the expiry timers of visible cards
+    pub(crate) fn timers_mut(&mut self) -> &mut ExpiryTimers {
+        &mut self.timers
+    }
+
+    /// Find a notification by ID in visible cards or hidden history
+    pub fn get(&self, id: u32) -> Option<&Notification> {
+        self.cards
+            .iter()
+            .find(|n| n.id == id)
+            .or_else(|| self.hidden.iter().find(|n| n.id == id))
+    }
+
+    /// Copy a history entry to show it as a card again
+    ///
+    /// The entry stays in history, so dismissing the card leaves it there.
+    /// Returns `None` if the ID is not in history or already on screen.
+    pub fn reshow(&mut self, id: u32) -> Option<Notification> {
+        if self.is_visible(id) {
+            return None;
+        }
+        let notification = self.hidden.iter().find(|n| n.id == id).cloned()?;
+        self.unread.remove(&id);
+        Some(notification)
+    }
+
     /// Add a notification to the visible cards
     pub fn add_notification(&mut self, notification: Notification) {
         self.cards.push(notification);
@@ -54,9 +109,14 @@ impl NotificationState {
     ///
     /// Returns the removed notification if found
     pub fn remove_notification(&mut self, id: u32) -> Option<Notification> {
+        self.timers.cancel(id);
+        self.unread.remove(&id);
         if let Some(pos) = self.cards.iter().position(|n| n.id == id) {
             Some(self.cards.remove(pos))
         } else {
+            if let Some(store) = &mut self.history {
+                store.remove(id);
+            }
             self.hidden
                 .iter()
                 .position(|n| n.id == id)
@@ -66,19 +126,165 @@ impl NotificationState {
 
     /// Move a notification from visible cards to hidden history
     ///
-    /// Applies memory budget management to hidden queue
-    pub fn hide_notification(&mut self, id: u32) {
+    /// Applies memory budget management to hidden queue. Returns `false` if
+    /// no visible card has the given ID.
+    pub fn hide_notification(&mut self, id: u32) -> bool {
         let Some(pos) = self.cards.iter().position(|n| n.id == id) else {
-            return;
+            return false;
         };
 
         let notification = self.cards.remove(pos);
+        self.timers.cancel(id);
+        self.add_hidden(notification);
+        true
+    }
+
+    /// Add a notification straight to hidden history
+    ///
+    /// Used for notifications that are never shown as a card. An older
+    /// history entry with the same ID is replaced. Notifications a content
+    /// rule keeps out of history are forgotten.
+    pub fn add_hidden(&mut self, notification: Notification) {
+        if notification.routing.skip_history {
+            return;
+        }
+
+        let dropped = match &mut self.history {
+            Some(store) => store.append(&notification).unwrap_or_else(|err| {
+                tracing::error!(
+                    "Failed to save notification {} to history: {err}",
+                    notification.id
+                );
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+
+        self.hidden
+            .retain(|n| n.id != notification.id && !dropped.contains(&n.id));
         self.hidden.push_front(notification);
 
         // Apply memory budget: allows ~500 text or ~50 image notifications
         self.apply_memory_budget(MAX_HIDDEN_MEMORY);
     }
 
+    /// Remember a notification held back by do-not-disturb
+    pub fn add_missed(&mut self, notification: &Notification) {
+        self.missed.push(Missed::new(notification));
+    }
+
+    /// Take the notifications missed since the last call, oldest first
+    pub fn take_missed(&mut self) -> Vec<Missed> {
+        std::mem::take(&mut self.missed)
+    }
+
+    /// Mark a history entry as never seen on screen
+    pub fn mark_unread(&mut self, id: u32) {
+        self.unread.insert(id);
+        // Forget entries history dropped since
+        if self.unread.len() > self.hidden.len() {
+            let hidden = &self.hidden;
+            self.unread.retain(|id| hidden.iter().any(|n| n.id == *id));
+        }
+    }
+
+    /// Mark every history entry as seen
+    pub fn mark_all_read(&mut self) {
+        self.unread.clear();
+    }
+
+    /// Number of history entries never seen on screen
+    pub fn unread_count(&self) -> usize {
+        self.hidden
+            .iter()
+            .filter(|n| self.unread.contains(&n.id))
+            .count()
+    }
+
+    /// Remove every notification from hidden history, on disk as well
+    pub fn clear_history(&mut self) {
+        self.hidden.clear();
+        self.unread.clear();
+        if let Some(store) = &mut self.history {
+            store.clear();
+        }
+    }
+
+    /// Update or close the on-disk history to match the configuration
+    ///
+    /// Returns how to open the history if it should be open but is not; hand
+    /// what it opened to `history_opened`. Closing the history leaves the
+    /// files on disk and its queued writes to finish in the background.
+    pub fn configure_history(&mut self, config: &NotificationsConfig) -> Option<HistoryOpener> {
+        if !config.persist_history {
+            if let Some(store) = self.history.take() {
+                self.history_closing.extend(store.close());
+            }
+            return None;
+        }
+
+        let retention = HistoryRetention::from_config(config);
+        if let Some(store) = &mut self.history {
+            let dropped = store.set_retention(retention);
+            self.hidden.retain(|n| !dropped.contains(&n.id));
+            return None;
+        }
+        if self.history_opening {
+            return None;
+        }
+
+        let Some(dir) = HistoryStore::default_dir() else {
+            tracing::warn!("No state directory available, history will not be saved");
+            return None;
+        };
+        self.history_opening = true;
+        let closing = std::mem::take(&mut self.history_closing);
+        Some(HistoryOpener::new(dir, retention, closing))
+    }
+
+    /// Use the history opened for `configure_history`, loading the
+    /// notifications it kept into hidden history
+    ///
+    /// The history is closed again if `config` turned persistence off in
+    /// the meantime.
+    pub fn history_opened(&mut self, opened: Option<LoadedHistory>, config: &NotificationsConfig) {
+        self.history_opening = false;
+        let Some((store, loaded)) = opened else {
+            return;
+        };
+        self.attach_history(store, loaded);
+        // The settings may have changed while opening
+        _ = self.configure_history(config);
+    }
+
+    /// Use `store` for history, adding its `loaded` notifications (newest
+    /// first) behind the hidden ones already in memory
+    pub fn attach_history(&mut self, store: HistoryStore, loaded: Vec<Notification>) {
+        for notification in loaded {
+            if self.get(notification.id).is_none() {
+                self.hidden.push_back(notification);
+            }
+        }
+        self.apply_memory_budget(MAX_HIDDEN_MEMORY);
+        self.history = Some(store);
+    }
+
+    /// Lowest notification ID above every ID in use or in history
+    pub fn next_free_id(&self) -> u32 {
+        self.cards
+            .iter()
+            .chain(&self.hidden)
+            .map(|n| n.id)
+            .chain(self.history.as_ref().and_then(HistoryStore::max_id))
+            .max()
+            .map_or(1, |id| id.saturating_add(1))
+    }
+
+    /// Check if a notification is currently shown as a card
+    pub fn is_visible(&self, id: u32) -> bool {
+        self.cards.iter().any(|n| n.id == id)
+    }
+
     /// Apply memory budget to hidden notifications
     ///
     /// Keeps newest notifications that fit within the budget
@@ -190,6 +396,60 @@ impl NotificationState {
 
         extra_per_app
     }
+
+    /// Group visible notifications for display
+    ///
+    /// Groups and the notifications inside them are ordered most important
+    /// first (urgency, then recency), and carry the expanded state the user
+    /// chose for their key.
+    pub fn groups(&self, mode: GroupingMode) -> Vec<NotificationGroup> {
+        let rank = |group: &NotificationGroup| {
+            group
+                .notifications
+                .iter()
+                .map(|n| (n.urgency(), n.time))
+                .max()
+        };
+
+        let mut groups = group_notifications(&self.cards, mode);
+        groups.sort_by_cached_key(|g| std::cmp::Reverse(rank(g)));
+        for group in &mut groups {
+            group
+                .notifications
+                .sort_by_key(|n| std::cmp::Reverse((n.urgency(), n.time)));
+            group.expanded = self.expanded_groups.contains(&group.key);
+        }
+        groups
+    }
+
+    /// Get the IDs of visible notifications in the group with the given key
+    pub fn group_ids(&self, mode: GroupingMode, key: &str) -> Vec<u32> {
+        group_notifications(&self.cards, mode)
+            .into_iter()
+            .find(|g| g.key == key)
+            .map(|g| g.notifications.iter().map(|n| n.id).collect())
+            .unwrap_or_default()
+    }
+
+    /// Expand a collapsed group or collapse an expanded one
+    pub fn toggle_group(&mut self, key: &str) {
+        if !self.expanded_groups.remove(key) {
+            self.expanded_groups.insert(key.to_string());
+        }
+    }
+
+    /// Forget the expanded state of groups that no longer exist
+    pub fn retain_groups(&mut self, mode: GroupingMode) {
+        if self.expanded_groups.is_empty() {
+            return;
+        }
+        let keys: HashSet<String> = group_notifications(&self.cards, mode)
+            .into_iter()
+            .filter(|g| g.count() > 1)
+            .map(|g| g.key)
+            .collect();
+        self.expanded_groups.retain(|k| keys.contains(k));
+    }
 }
 
 impl Default for NotificationState {
@@ -197,3 +457,272 @@ impl Default for NotificationState {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmic_ext_notifications_util::{Hint, Routing};
+    use std::time::{Duration, SystemTime};
+
+    fn notification(id: u32, app_name: &str, urgency: u8, age_secs: u64) -> Notification {
+        Notification {
+            id,
+            app_name: app_name.to_string(),
+            app_icon: String::new(),
+            summary: format!("Test {id}"),
+            body: String::new(),
+            actions: vec![],
+            hints: vec![Hint::Urgency(urgency)],
+            expire_timeout: 5000,
+            time: SystemTime::now() - Duration::from_secs(age_secs),
+            sender: None,
+            routing: Routing::default(),
+        }
+    }
+
+    fn state_with(notifications: Vec<Notification>) -> NotificationState {
+        let mut state = NotificationState::new();
+        for n in notifications {
+            state.insert_sorted(n);
+        }
+        state
+    }
+
+    #[test]
+    fn test_groups_ordered_by_priority() {
+        let state = state_with(vec![
+            notification(1, "Firefox", 1, 30),
+            notification(2, "Slack", 1, 20),
+            notification(3, "Firefox", 1, 10),
+            notification(4, "Mail", 2, 40),
+        ]);
+
+        let groups = state.groups(GroupingMode::ByApp);
+        let keys: Vec<_> = groups.iter().map(|g| g.key.as_str()).collect();
+
+        // Critical first, then by newest member
+        assert_eq!(keys, ["Mail", "Firefox", "Slack"]);
+        assert_eq!(groups[1].label(), "Firefox (2)");
+        assert_eq!(groups[1].newest().unwrap().id, 3);
+    }
+
+    #[test]
+    fn test_toggle_group_survives_regrouping() {
+        let mut state = state_with(vec![
+            notification(1, "Firefox", 1, 20),
+            notification(2, "Firefox", 1, 10),
+        ]);
+
+        state.toggle_group("Firefox");
+        assert!(state.groups(GroupingMode::ByApp)[0].expanded);
+
+        state.insert_sorted(notification(3, "Firefox", 1, 0));
+        assert!(state.groups(GroupingMode::ByApp)[0].expanded);
+
+        state.toggle_group("Firefox");
+        assert!(!state.groups(GroupingMode::ByApp)[0].expanded);
+    }
+
+    #[test]
+    fn test_retain_groups_drops_stale_keys() {
+        let mut state = state_with(vec![
+            notification(1, "Firefox", 1, 20),
+            notification(2, "Firefox", 1, 10),
+        ]);
+        state.toggle_group("Firefox");
+
+        state.remove_notification(2);
+        state.retain_groups(GroupingMode::ByApp);
+
+        state.insert_sorted(notification(3, "Firefox", 1, 0));
+        assert!(!state.groups(GroupingMode::ByApp)[0].expanded);
+    }
+
+    #[test]
+    fn test_hide_notification_only_once() {
+        let mut state = state_with(vec![notification(1, "Firefox", 1, 10)]);
+
+        assert!(state.hide_notification(1));
+        assert!(!state.is_visible(1));
+        assert_eq!(state.hidden().len(), 1);
+
+        // Already hidden: nothing left to expire
+        assert!(!state.hide_notification(1));
+        assert!(!state.hide_notification(42));
+        assert_eq!(state.hidden().len(), 1);
+    }
+
+    #[test]
+    fn test_add_hidden_replaces_same_id() {
+        let mut state = NotificationState::new();
+        state.add_hidden(notification(1, "Firefox", 1, 10));
+        state.add_hidden(notification(2, "Slack", 1, 5));
+        state.add_hidden(notification(1, "Firefox", 1, 0));
+
+        let ids: Vec<_> = state.hidden().iter().map(|n| n.id).collect();
+        assert_eq!(ids, [1, 2]);
+        assert!(state.is_empty());
+    }
+
+    #[test]
+    fn test_skip_history_is_not_kept() {
+        let mut skipped = notification(1, "CI", 1, 10);
+        skipped.routing.skip_history = true;
+        let mut state = state_with(vec![skipped, notification(2, "Slack", 1, 5)]);
+
+        assert!(state.hide_notification(1));
+        assert!(state.hide_notification(2));
+        let ids: Vec<_> = state.hidden().iter().map(|n| n.id).collect();
+        assert_eq!(ids, [2]);
+    }
+
+    #[test]
+    fn test_get_finds_visible_and_hidden() {
+        let mut state = state_with(vec![
+            notification(1, "Music", 1, 10),
+            notification(2, "Firefox", 1, 5),
+        ]);
+        state.hide_notification(2);
+
+        assert_eq!(state.get(1).map(|n| n.app_name.as_str()), Some("Music"));
+        assert_eq!(state.get(2).map(|n| n.app_name.as_str()), Some("Firefox"));
+        assert!(state.get(3).is_none());
+    }
+
+    #[test]
+    fn test_group_ids() {
+        let state = state_with(vec![
+            notification(1, "Firefox", 1, 20),
+            notification(2, "Slack", 1, 15),
+            notification(3, "Firefox", 1, 10),
+        ]);
+
+        let mut ids = state.group_ids(GroupingMode::ByApp, "Firefox");
+        ids.sort();
+        assert_eq!(ids, [1, 3]);
+        assert!(state.group_ids(GroupingMode::ByApp, "Mail").is_empty());
+    }
+
+    #[test]
+    fn test_take_missed() {
+        let mut state = NotificationState::new();
+        state.add_missed(&notification(1, "Slack", 1, 0));
+        state.add_missed(&notification(2, "", 1, 0));
+
+        let missed = state.take_missed();
+        assert_eq!(missed.iter().map(|m| m.id).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(missed[0].app_name, "Slack");
+        assert_eq!(missed[1].app_name, "Unknown app");
+        assert!(state.take_missed().is_empty());
+    }
+
+    #[test]
+    fn test_unread_count() {
+        let mut state = NotificationState::new();
+        for id in 1..=3 {
+            state.add_hidden(notification(id, "Slack", 1, 0));
+        }
+        state.mark_unread(1);
+        state.mark_unread(2);
+        assert_eq!(state.unread_count(), 2);
+
+        // Showing an entry again marks it read, keeping it in history
+        assert!(state.reshow(2).is_some());
+        assert_eq!(state.unread_count(), 1);
+        assert!(state.get(2).is_some());
+
+        state.mark_all_read();
+        assert_eq!(state.unread_count(), 0);
+    }
+
+    #[test]
+    fn test_history_store_backs_hidden() {
+        let dir = tempfile::tempdir().unwrap();
+        let retention = HistoryRetention {
+            max_count: 2,
+            max_age: None,
+            max_bytes: 1024 * 1024,
+        };
+
+        let (store, _) = HistoryStore::open(dir.path(), retention).unwrap();
+        let mut state = NotificationState::new();
+        state.attach_history(store, Vec::new());
+        state.add_hidden(notification(1, "Firefox", 1, 30));
+        state.add_hidden(notification(2, "Slack", 1, 20));
+        // Retention drops the oldest entry from memory as well
+        state.add_hidden(notification(3, "Mail", 1, 10));
+        state.remove_notification(3);
+        let ids: Vec<_> = state.hidden().iter().map(|n| n.id).collect();
+        assert_eq!(ids, [2]);
+        drop(state);
+
+        let (store, loaded) = HistoryStore::open(dir.path(), retention).unwrap();
+        let mut state = state_with(vec![notification(7, "Music", 1, 0)]);
+        state.attach_history(store, loaded);
+        let ids: Vec<_> = state.hidden().iter().map(|n| n.id).collect();
+        assert_eq!(ids, [2]);
+        assert_eq!(state.next_free_id(), 8);
+    }
+
+    #[test]
+    fn test_history_opened_after_persistence_turned_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let retention = HistoryRetention {
+            max_count: 10,
+            max_age: None,
+            max_bytes: 1024 * 1024,
+        };
+        let (mut store, _) = HistoryStore::open(dir.path(), retention).unwrap();
+        store.append(&notification(1, "Firefox", 1, 10)).unwrap();
+        drop(store);
+
+        // Persistence was turned off while the history was opening
+        let opener = HistoryOpener::new(dir.path().to_path_buf(), retention, Vec::new());
+        let mut state = NotificationState::new();
+        state.history_opened(opener.open(), &NotificationsConfig::default());
+        assert!(state.history.is_none());
+        state.add_hidden(notification(2, "Slack", 1, 0));
+        let ids: Vec<_> = state.hidden().iter().map(|n| n.id).collect();
+        assert_eq!(ids, [2, 1]);
+
+        let closing = std::mem::take(&mut state.history_closing);
+        let opener = HistoryOpener::new(dir.path().to_path_buf(), retention, closing);
+        let (_, loaded) = opener.open().unwrap();
+        let ids: Vec<_> = loaded.iter().map(|n| n.id).collect();
+        assert_eq!(ids, [1]);
+    }
+
+    #[test]
+    fn test_reshown_history_survives_dismiss() {
+        let dir = tempfile::tempdir().unwrap();
+        let retention = HistoryRetention {
+            max_count: 10,
+            max_age: None,
+            max_bytes: 1024 * 1024,
+        };
+
+        let (store, _) = HistoryStore::open(dir.path(), retention).unwrap();
+        let mut state = NotificationState::new();
+        state.attach_history(store, Vec::new());
+        state.add_hidden(notification(1, "Firefox", 1, 20));
+        state.add_hidden(notification(2, "Slack", 1, 10));
+        state.mark_unread(1);
+
+        let card = state.reshow(1).unwrap();
+        assert_eq!(state.unread_count(), 0);
+        state.insert_sorted(card);
+        assert!(state.reshow(1).is_none(), "Already on screen");
+
+        // Dismissing the card removes the card only
+        assert!(state.remove_notification(1).is_some());
+        assert!(!state.is_visible(1));
+        let ids: Vec<_> = state.hidden().iter().map(|n| n.id).collect();
+        assert_eq!(ids, [2, 1]);
+        drop(state);
+
+        let (_, loaded) = HistoryStore::open(dir.path(), retention).unwrap();
+        let mut ids: Vec<_> = loaded.iter().map(|n| n.id).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [1, 2]);
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Expiry timer of a single notification card
+#[derive(Debug, Clone)]
+struct ExpiryTimer {
+    /// Full display timeout
+    total: Duration,
+    /// Time left when the timer was last started or paused
+    remaining: Duration,
+    /// When the timer was last started, `None` while paused
+    started: Option<Instant>,
+}
+
+impl ExpiryTimer {
+    fn remaining_at(&self, now: Instant) -> Duration {
+        match self.started {
+            Some(started) => self
+                .remaining
+                .saturating_sub(now.saturating_duration_since(started)),
+            None => self.remaining,
+        }
+    }
+}
+
+/// Expiry timers for notification cards
+///
+/// Timers are plain deadlines checked on each tick rather than detached
+/// sleeps, so replacing a card simply re-arms its timer and a paused timer
+/// keeps the time it has left.
+#[derive(Debug, Default)]
+pub struct ExpiryTimers {
+    timers: HashMap<u32, ExpiryTimer>,
+}
+
+impl ExpiryTimers {
+    /// Start or restart the timer of a card
+    ///
+    /// A zero `timeout` means the card never expires. A paused timer stays
+    /// paused with the new timeout until it is resumed.
+    pub fn arm(&mut self, id: u32, timeout: Duration) {
+        if timeout.is_zero() {
+            self.timers.remove(&id);
+            return;
+        }
+
+        let paused = self.is_paused(id);
+        let timer = ExpiryTimer {
+            total: timeout,
+            remaining: timeout,
+            started: (!paused).then(Instant::now),
+        };
+        self.timers.insert(id, timer);
+    }
+
+    /// Pause a running timer, keeping the time it has left
+    ///
+    /// Returns `false` if the card has no running timer.
+    pub fn pause(&mut self, id: u32) -> bool {
+        let Some(timer) = self.timers.get_mut(&id) else {
+            return false;
+        };
+        if timer.started.is_none() {
+            return false;
+        }
+
+        timer.remaining = timer.remaining_at(Instant::now());
+        timer.started = None;
+        true
+    }
+
+    /// Resume a paused timer
+    ///
+    /// Returns `false` if the card has no paused timer.
+    pub fn resume(&mut self, id: u32) -> bool {
+        let Some(timer) = self.timers.get_mut(&id) else {
+            return false;
+        };
+        if timer.started.is_some() {
+            return false;
+        }
+
+        timer.started = Some(Instant::now());
+        true
+    }
+
+    /// Remove and return the cards whose timer ran out by `now`
+    pub fn take_expired(&mut self, now: Instant) -> Vec<u32> {
+        let expired: Vec<u32> = self
+            .timers
+            .iter()
+            .filter(|(_, t)| t.started.is_some() && t.remaining_at(now).is_zero())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            self.timers.remove(id);
+        }
+        expired
+    }
+
+    /// Forget the timer of a card that was closed
+    pub fn cancel(&mut self, id: u32) {
+        self.timers.remove(&id);
+    }
+
+    /// Check if any timer is counting down
+    pub fn has_running(&self) -> bool {
+        self.timers.values().any(|t| t.started.is_some())
+    }
+
+    /// Check if the card's timer is paused
+    fn is_paused(&self, id: u32) -> bool {
+        self.timers.get(&id).is_some_and(|t| t.started.is_none())
+    }
+
+    /// Full display timeout of the card, if it expires at all
+    #[cfg(test)]
+    fn total(&self, id: u32) -> Option<Duration> {
+        self.timers.get(&id).map(|t| t.total)
+    }
+
+    /// Time left before the card expires, if it expires at all
+    #[cfg(test)]
+    fn remaining(&self, id: u32) -> Option<Duration> {
+        self.timers.get(&id).map(|t| t.remaining_at(Instant::now()))
+    }
+
+    /// Fraction of the display time left, from 1.0 down to 0.0
+    pub fn progress(&self, id: u32) -> Option<f32> {
+        let timer = self.timers.get(&id)?;
+        let remaining = timer.remaining_at(Instant::now());
+        Some(remaining.as_secs_f32() / timer.total.as_secs_f32())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_expires_after_timeout() {
+        let mut timers = ExpiryTimers::default();
+        timers.arm(1, TIMEOUT);
+        let now = Instant::now();
+
+        assert!(timers.take_expired(now).is_empty());
+        assert_eq!(timers.take_expired(now + TIMEOUT), [1]);
+        // Expired timers are forgotten
+        assert!(timers.take_expired(now + TIMEOUT * 2).is_empty());
+        assert!(timers.remaining(1).is_none());
+    }
+
+    #[test]
+    fn test_rearm_restarts_countdown() {
+        let mut timers = ExpiryTimers::default();
+        timers.arm(1, TIMEOUT);
+        timers.arm(1, Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert_eq!(timers.total(1), Some(Duration::from_secs(10)));
+        assert!(timers.take_expired(now + TIMEOUT).is_empty());
+        assert_eq!(timers.take_expired(now + Duration::from_secs(10)), [1]);
+    }
+
+    #[test]
+    fn test_zero_timeout_never_expires() {
+        let mut timers = ExpiryTimers::default();
+        timers.arm(1, TIMEOUT);
+        timers.arm(1, Duration::ZERO);
+
+        assert!(!timers.has_running());
+        assert!(timers.take_expired(Instant::now() + TIMEOUT).is_empty());
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let mut timers = ExpiryTimers::default();
+        timers.arm(1, TIMEOUT);
+
+        assert!(timers.pause(1));
+        assert!(timers.is_paused(1));
+        assert!(!timers.pause(1));
+        assert!(!timers.has_running());
+        // Paused timers never run out
+        assert!(timers.take_expired(Instant::now() + TIMEOUT * 2).is_empty());
+
+        assert!(timers.resume(1));
+        assert!(!timers.resume(1));
+        assert!(timers.remaining(1).unwrap() <= TIMEOUT);
+        assert_eq!(timers.take_expired(Instant::now() + TIMEOUT), [1]);
+    }
+
+    #[test]
+    fn test_rearm_while_paused_stays_paused() {
+        let mut timers = ExpiryTimers::default();
+        timers.arm(1, TIMEOUT);
+        timers.pause(1);
+
+        timers.arm(1, Duration::from_secs(10));
+        assert!(timers.is_paused(1));
+        assert_eq!(timers.remaining(1), Some(Duration::from_secs(10)));
+        assert_eq!(timers.progress(1), Some(1.0));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut timers = ExpiryTimers::default();
+        timers.arm(1, TIMEOUT);
+        timers.arm(2, TIMEOUT);
+
+        timers.cancel(1);
+        assert_eq!(timers.take_expired(Instant::now() + TIMEOUT), [2]);
+    }
+}
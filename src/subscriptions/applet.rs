@@ -6,14 +6,15 @@ use tokio::{net::UnixStream, sync::mpsc::Sender};
 use tracing::{error, info};
 use zbus::{
     Connection, Guid, connection::Builder, interface, object_server::SignalEmitter,
-    zvariant::OwnedFd,
+    zvariant::{OwnedFd, OwnedValue},
 };
 
 use super::notifications::Input;
 
 use anyhow::{Result, bail};
-use cosmic_ext_notifications_util::DAEMON_NOTIFICATIONS_FD;
+use cosmic_ext_notifications_util::{DAEMON_NOTIFICATIONS_FD, HistoryQuery};
 use std::os::unix::io::FromRawFd;
+use std::time::{Duration, SystemTime};
 
 use serde_json;
 
@@ -144,6 +145,33 @@ impl NotificationsApplet {
         expire_timeout: i32,
     ) -> zbus::Result<()>;
 
+    /// A notification was closed
+    ///
+    /// `reason` follows `NotificationClosed`, so applets can keep expired
+    /// notifications in their history. `seq` increases by one with every
+    /// state-change signal; a gap means a signal was missed.
+    #[zbus(signal)]
+    pub async fn notification_removed(
+        signal_ctxt: &SignalEmitter<'_>,
+        id: u32,
+        reason: u32,
+        seq: u64,
+    ) -> zbus::Result<()>;
+
+    /// A notification was replaced, sent after `notify` with the new content
+    /// serialized as JSON like `get_history_full`
+    #[zbus(signal)]
+    pub async fn notification_updated(
+        signal_ctxt: &SignalEmitter<'_>,
+        id: u32,
+        notification: &str,
+        seq: u64,
+    ) -> zbus::Result<()>;
+
+    /// History was cleared
+    #[zbus(signal)]
+    pub async fn history_cleared(signal_ctxt: &SignalEmitter<'_>, seq: u64) -> zbus::Result<()>;
+
     pub async fn invoke_action(&self, id: u32, action: &str) -> zbus::fdo::Result<()> {
         tracing::trace!("Received action from applet {id} {action}");
         let res = self
@@ -160,6 +188,16 @@ impl NotificationsApplet {
         Ok(())
     }
 
+    /// Clear notification history
+    pub async fn clear_history(&self) -> zbus::fdo::Result<()> {
+        tracing::trace!("Received clear_history request from applet");
+        if let Err(err) = self.tx.send(Input::ClearHistory).await {
+            tracing::error!("Failed to send clear_history message to channel");
+            return Err(zbus::fdo::Error::Failed(err.to_string()));
+        }
+        Ok(())
+    }
+
     pub async fn get_history(&self) -> zbus::fdo::Result<Vec<(u32, String, String, String, String, i64)>> {
         tracing::trace!("Received get_history request from applet");
 
@@ -241,4 +279,173 @@ impl NotificationsApplet {
 
         result
     }
+
+    /// Query history with filters and paging
+    ///
+    /// `filters` may contain `app` (s, app name or desktop entry),
+    /// `category` (s), `urgency` (y), `since` and `until` (x, Unix seconds)
+    /// and `text` (s). A `limit` of 0 returns every match. Returns the page
+    /// as JSON strings, like `get_history_full`, and the total match count.
+    #[zbus(out_args("notifications", "total"))]
+    pub async fn query_history(
+        &self,
+        filters: HashMap<String, OwnedValue>,
+        offset: u32,
+        limit: u32,
+    ) -> zbus::fdo::Result<(Vec<String>, u32)> {
+        tracing::trace!("Received query_history request from applet");
+
+        let query = history_query(filters, offset, limit)?;
+        let (tx, rx) = tokio::sync::oneshot::channel();
+
+        let res = self
+            .tx
+            .send(Input::QueryHistory {
+                query: Box::new(query),
+                tx,
+            })
+            .await;
+        if let Err(err) = res {
+            tracing::error!("Failed to send query_history message to channel");
+            return Err(zbus::fdo::Error::Failed(err.to_string()));
+        }
+
+        // Wait for response with timeout
+        let page = match tokio::time::timeout(tokio::time::Duration::from_secs(2), rx).await {
+            Ok(Ok(page)) => page,
+            Ok(Err(err)) => {
+                tracing::error!("Failed to receive history query: {}", err);
+                return Err(zbus::fdo::Error::Failed("Channel closed".to_string()));
+            }
+            Err(_) => {
+                tracing::error!("Timeout waiting for history query");
+                return Err(zbus::fdo::Error::Failed("Timeout".to_string()));
+            }
+        };
+
+        let notifications = page
+            .notifications
+            .iter()
+            .map(|n| {
+                serde_json::to_string(n).map_err(|e| {
+                    tracing::error!("Failed to serialize notification {}: {}", n.id, e);
+                    zbus::fdo::Error::Failed(format!("Serialization error: {}", e))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((notifications, u32::try_from(page.total).unwrap_or(u32::MAX)))
+    }
+}
+
+/// Build a history query from the arguments of `QueryHistory`
+fn history_query(
+    filters: HashMap<String, OwnedValue>,
+    offset: u32,
+    limit: u32,
+) -> zbus::fdo::Result<HistoryQuery> {
+    let mut query = HistoryQuery {
+        offset: offset as usize,
+        limit: (limit > 0).then_some(limit as usize),
+        ..Default::default()
+    };
+
+    for (key, value) in filters {
+        let invalid = || zbus::fdo::Error::InvalidArgs(format!("Invalid value for filter `{key}`"));
+        match key.as_str() {
+            "app" => query.app = Some(String::try_from(value).map_err(|_| invalid())?),
+            "category" => query.category = Some(String::try_from(value).map_err(|_| invalid())?),
+            "urgency" => query.urgency = Some(u8::try_from(value).map_err(|_| invalid())?),
+            "since" => query.since = Some(unix_time(i64::try_from(value).map_err(|_| invalid())?)),
+            "until" => query.until = Some(unix_time(i64::try_from(value).map_err(|_| invalid())?)),
+            "text" => query.text = Some(String::try_from(value).map_err(|_| invalid())?),
+            _ => {
+                return Err(zbus::fdo::Error::InvalidArgs(format!(
+                    "Unknown history filter `{key}`"
+                )));
+            }
+        }
+    }
+
+    Ok(query)
+}
+
+/// Convert Unix seconds to a time, clamping times before the epoch
+fn unix_time(secs: i64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::zvariant::Value;
+
+    fn filters(entries: Vec<(&str, Value<'static>)>) -> HashMap<String, OwnedValue> {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), OwnedValue::try_from(value).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_history_query_filters() {
+        let query = history_query(
+            filters(vec![
+                ("app", Value::from("Slack")),
+                ("category", Value::from("email")),
+                ("urgency", Value::from(2u8)),
+                ("since", Value::from(100i64)),
+                ("until", Value::from(200i64)),
+                ("text", Value::from("invoice")),
+            ]),
+            10,
+            5,
+        )
+        .unwrap();
+        assert_eq!(
+            query,
+            HistoryQuery {
+                app: Some("Slack".to_string()),
+                category: Some("email".to_string()),
+                urgency: Some(2),
+                since: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(100)),
+                until: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(200)),
+                text: Some("invoice".to_string()),
+                offset: 10,
+                limit: Some(5),
+            }
+        );
+    }
+
+    #[test]
+    fn test_history_query_limit_zero_is_unlimited() {
+        let query = history_query(HashMap::new(), 0, 0).unwrap();
+        assert_eq!(query, HistoryQuery::default());
+    }
+
+    #[test]
+    fn test_history_query_negative_since_clamps_to_epoch() {
+        let query = history_query(filters(vec![("since", Value::from(-5i64))]), 0, 0).unwrap();
+        assert_eq!(query.since, Some(SystemTime::UNIX_EPOCH));
+    }
+
+    #[test]
+    fn test_history_query_rejects_unknown_keys_and_wrong_types() {
+        let err = history_query(filters(vec![("sender", Value::from("Slack"))]), 0, 0).unwrap_err();
+        assert!(matches!(err, zbus::fdo::Error::InvalidArgs(msg) if msg.contains("sender")));
+
+        for (key, value) in [
+            ("app", Value::from(1u32)),
+            ("urgency", Value::from("critical")),
+            ("urgency", Value::from(2u32)),
+            ("since", Value::from("yesterday")),
+            ("text", Value::from(true)),
+        ] {
+            let err = history_query(filters(vec![(key, value)]), 0, 0).unwrap_err();
+            assert!(
+                matches!(&err, zbus::fdo::Error::InvalidArgs(msg) if msg.contains(key)),
+                "{key}: {err:?}"
+            );
+        }
+    }
 }
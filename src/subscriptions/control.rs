@@ -0,0 +1,484 @@
+use chrono::{DateTime, Utc};
+use cosmic_ext_notifications_util::Notification;
+use tokio::sync::{mpsc::Sender, oneshot};
+use zbus::{interface, object_server::SignalEmitter};
+
+use super::notifications::Input;
+
+/// Object path of the control interface
+pub const PATH: &str = "/io/github/olafkfreund/CosmicExtNotifications";
+
+/// Daemon state published as properties of the control interface
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Status {
+    /// Manual do-not-disturb toggle
+    pub do_not_disturb: bool,
+    /// Cards on screen
+    pub active_count: u32,
+    /// Notifications in history
+    pub history_count: u32,
+    /// Notifications in history that never made it to the screen
+    pub unread_count: u32,
+    /// Unix seconds when popups resume, 0 if they are not paused and -1 if
+    /// they are paused with no end in sight
+    pub paused_until: i64,
+}
+
+/// Request from the control interface, answered by the app
+#[derive(Debug)]
+pub enum ControlRequest {
+    /// Turn manual do-not-disturb on or off
+    SetDoNotDisturb(bool),
+    /// Turn manual do-not-disturb on until the given time
+    SetDoNotDisturbUntil(DateTime<Utc>),
+    /// Cards on screen, newest first
+    ListActive {
+        tx: oneshot::Sender<Vec<Notification>>,
+    },
+    /// Dismiss a card, replying if it was on screen
+    Dismiss { id: u32, tx: oneshot::Sender<bool> },
+    /// Dismiss every card on screen, replying how many there were
+    DismissAll { tx: oneshot::Sender<u32> },
+    /// Dismiss the cards of an app, replying how many there were
+    DismissApp {
+        app: String,
+        tx: oneshot::Sender<u32>,
+    },
+    /// Show a history entry as a card again, replying if it was found
+    ShowHistoryItem { id: u32, tx: oneshot::Sender<bool> },
+    /// Invoke an action of a notification, replying if it has the action
+    InvokeAction {
+        id: u32,
+        action: String,
+        tx: oneshot::Sender<bool>,
+    },
+    /// Read the configuration again, replying if it could be
+    ReloadConfig { tx: oneshot::Sender<bool> },
+    /// Take the keyboard to navigate the cards, replying if any are on screen
+    FocusPopups { tx: oneshot::Sender<bool> },
+}
+
+/// Interface for status bars and scripts to read and change daemon state
+///
+/// Methods fail with `FileNotFound` for a notification or action that does
+/// not exist, and with `InvalidArgs` for arguments that are never valid.
+pub struct Control {
+    tx: Sender<Input>,
+    status: Status,
+}
+
+impl Control {
+    pub fn new(tx: Sender<Input>) -> Self {
+        Self {
+            tx,
+            status: Status::default(),
+        }
+    }
+
+    /// Take the latest status from the app, announcing the properties that
+    /// changed
+    pub async fn update(
+        &mut self,
+        emitter: &SignalEmitter<'_>,
+        status: Status,
+    ) -> zbus::Result<()> {
+        let old = std::mem::replace(&mut self.status, status);
+        if old.do_not_disturb != status.do_not_disturb {
+            self.do_not_disturb_changed(emitter).await?;
+        }
+        if old.active_count != status.active_count {
+            self.active_count_changed(emitter).await?;
+        }
+        if old.history_count != status.history_count {
+            self.history_count_changed(emitter).await?;
+        }
+        if old.unread_count != status.unread_count {
+            self.unread_count_changed(emitter).await?;
+        }
+        if old.paused_until != status.paused_until {
+            self.paused_until_changed(emitter).await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&self, input: Input) -> zbus::fdo::Result<()> {
+        self.tx.send(input).await.map_err(|err| {
+            tracing::error!("Failed to send control request to channel");
+            zbus::fdo::Error::Failed(err.to_string())
+        })
+    }
+
+    /// Send a request to the app and wait for its reply
+    async fn request<T>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<T>) -> ControlRequest,
+    ) -> zbus::fdo::Result<T> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Input::Control(request(tx))).await?;
+
+        match tokio::time::timeout(tokio::time::Duration::from_secs(2), rx).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(err)) => {
+                tracing::error!("Failed to receive control reply: {}", err);
+                Err(zbus::fdo::Error::Failed("Channel closed".to_string()))
+            }
+            Err(_) => {
+                tracing::error!("Timeout waiting for control reply");
+                Err(zbus::fdo::Error::Failed("Timeout".to_string()))
+            }
+        }
+    }
+}
+
+#[interface(name = "io.github.olafkfreund.CosmicExtNotifications.Control")]
+impl Control {
+    /// Manual do-not-disturb, the same setting as in cosmic-config
+    #[zbus(property)]
+    async fn do_not_disturb(&self) -> bool {
+        self.status.do_not_disturb
+    }
+
+    #[zbus(property)]
+    async fn set_do_not_disturb(&mut self, value: bool) -> zbus::fdo::Result<()> {
+        self.send(Input::Control(ControlRequest::SetDoNotDisturb(value)))
+            .await?;
+        self.status.do_not_disturb = value;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    async fn active_count(&self) -> u32 {
+        self.status.active_count
+    }
+
+    #[zbus(property)]
+    async fn history_count(&self) -> u32 {
+        self.status.history_count
+    }
+
+    /// Notifications held back from the screen and not shown since
+    #[zbus(property)]
+    async fn unread_count(&self) -> u32 {
+        self.status.unread_count
+    }
+
+    /// Unix seconds when popups resume, 0 if they are not paused and -1 if
+    /// they are paused until turned back on
+    #[zbus(property)]
+    async fn paused_until(&self) -> i64 {
+        self.status.paused_until
+    }
+
+    /// Turn do-not-disturb on until the given Unix time
+    async fn set_do_not_disturb_until(&self, until: i64) -> zbus::fdo::Result<()> {
+        let until = DateTime::from_timestamp(until, 0)
+            .filter(|until| *until > Utc::now())
+            .ok_or_else(|| {
+                zbus::fdo::Error::InvalidArgs(format!("{until} is not a time in the future"))
+            })?;
+        self.send(Input::Control(ControlRequest::SetDoNotDisturbUntil(until)))
+            .await
+    }
+
+    /// Cards on screen, newest first, serialized as JSON like the applet's
+    /// `get_history_full`
+    async fn list_active(&self) -> zbus::fdo::Result<Vec<String>> {
+        let notifications = self.request(|tx| ControlRequest::ListActive { tx }).await?;
+        notifications
+            .iter()
+            .map(|n| {
+                serde_json::to_string(n).map_err(|e| {
+                    tracing::error!("Failed to serialize notification {}: {}", n.id, e);
+                    zbus::fdo::Error::Failed(format!("Serialization error: {}", e))
+                })
+            })
+            .collect()
+    }
+
+    /// Dismiss a card on screen
+    async fn dismiss(&self, id: u32) -> zbus::fdo::Result<()> {
+        if self
+            .request(|tx| ControlRequest::Dismiss { id, tx })
+            .await?
+        {
+            Ok(())
+        } else {
+            Err(zbus::fdo::Error::FileNotFound(format!(
+                "No notification {id} on screen"
+            )))
+        }
+    }
+
+    /// Dismiss every card on screen, returning how many were dismissed
+    async fn dismiss_all(&self) -> zbus::fdo::Result<u32> {
+        self.request(|tx| ControlRequest::DismissAll { tx }).await
+    }
+
+    /// Dismiss the cards of an app, by app name or app ID, returning how many
+    /// were dismissed
+    async fn dismiss_app(&self, app: &str) -> zbus::fdo::Result<u32> {
+        let app = app.to_string();
+        self.request(|tx| ControlRequest::DismissApp { app, tx })
+            .await
+    }
+
+    /// Clear notification history
+    async fn clear_history(&self) -> zbus::fdo::Result<()> {
+        self.send(Input::ClearHistory).await
+    }
+
+    /// Show a notification from history as a card again, keeping it in history
+    async fn show_history_item(&self, id: u32) -> zbus::fdo::Result<()> {
+        if self
+            .request(|tx| ControlRequest::ShowHistoryItem { id, tx })
+            .await?
+        {
+            Ok(())
+        } else {
+            Err(zbus::fdo::Error::FileNotFound(format!(
+                "No notification {id} in history"
+            )))
+        }
+    }
+
+    /// Read the configuration again, e.g. after editing its files by hand
+    async fn reload_config(&self) -> zbus::fdo::Result<()> {
+        if self
+            .request(|tx| ControlRequest::ReloadConfig { tx })
+            .await?
+        {
+            Ok(())
+        } else {
+            Err(zbus::fdo::Error::Failed(
+                "Configuration is not available".to_string(),
+            ))
+        }
+    }
+
+    /// Take the keyboard to navigate the cards on screen, e.g. from a global
+    /// shortcut, until Escape is pressed
+    async fn focus_popups(&self) -> zbus::fdo::Result<()> {
+        if self
+            .request(|tx| ControlRequest::FocusPopups { tx })
+            .await?
+        {
+            Ok(())
+        } else {
+            Err(zbus::fdo::Error::FileNotFound(
+                "No notifications on screen".to_string(),
+            ))
+        }
+    }
+
+    /// Invoke an action of a notification on screen or in history
+    async fn invoke_action(&self, id: u32, key: &str) -> zbus::fdo::Result<()> {
+        let action = key.to_string();
+        if self
+            .request(|tx| ControlRequest::InvokeAction { id, action, tx })
+            .await?
+        {
+            Ok(())
+        } else {
+            Err(zbus::fdo::Error::FileNotFound(format!(
+                "Notification {id} has no action `{key}`"
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::CHANNEL_BUFFER_SIZE;
+    use cosmic::iced::futures::StreamExt;
+    use std::time::Duration;
+    use tokio::{net::UnixStream, sync::mpsc::Receiver};
+    use zbus::{Connection, Guid, MessageStream, connection::Builder, message::Type as MessageType};
+
+    const IFACE: &str = "io.github.olafkfreund.CosmicExtNotifications.Control";
+
+    async fn serve() -> (Connection, Connection, Receiver<Input>) {
+        let (server_socket, client_socket) = UnixStream::pair().unwrap();
+        let (tx, rx) = tokio::sync::mpsc::channel(CHANNEL_BUFFER_SIZE);
+        let guid = Guid::generate();
+        let server = async {
+            Builder::socket(server_socket)
+                .p2p()
+                .server(guid)
+                .unwrap()
+                .serve_at(PATH, Control::new(tx))
+                .unwrap()
+                .build()
+                .await
+                .unwrap()
+        };
+        let client = async { Builder::socket(client_socket).p2p().build().await.unwrap() };
+        let (server, client) = tokio::join!(server, client);
+        (server, client, rx)
+    }
+
+    async fn get_property<T>(client: &Connection, name: &str) -> T
+    where
+        T: TryFrom<zbus::zvariant::OwnedValue>,
+        T::Error: std::fmt::Debug,
+    {
+        let reply = client
+            .call_method(
+                None::<&str>,
+                PATH,
+                Some("org.freedesktop.DBus.Properties"),
+                "Get",
+                &(IFACE, name),
+            )
+            .await
+            .unwrap();
+        let value: zbus::zvariant::OwnedValue = reply.body().deserialize().unwrap();
+        T::try_from(value).unwrap()
+    }
+
+    /// Name of the D-Bus error a method call failed with
+    fn error_name(reply: zbus::Result<zbus::Message>) -> String {
+        match reply {
+            Err(zbus::Error::MethodError(name, ..)) => name.to_string(),
+            other => panic!("expected an error reply, got {other:?}"),
+        }
+    }
+
+    /// Names of the properties announced in the next `PropertiesChanged`
+    async fn next_changed(signals: &mut MessageStream) -> Vec<String> {
+        let fut = async {
+            while let Some(msg) = signals.next().await {
+                let msg = msg.unwrap();
+                if msg.message_type() == MessageType::Signal
+                    && msg
+                        .header()
+                        .member()
+                        .is_some_and(|m| m.as_str() == "PropertiesChanged")
+                {
+                    let (_, changed, _): (
+                        String,
+                        std::collections::HashMap<String, zbus::zvariant::OwnedValue>,
+                        Vec<String>,
+                    ) = msg.body().deserialize().unwrap();
+                    let mut names: Vec<_> = changed.into_keys().collect();
+                    names.sort();
+                    return names;
+                }
+            }
+            panic!("signal stream ended");
+        };
+        tokio::time::timeout(Duration::from_secs(2), fut)
+            .await
+            .expect("timed out waiting for PropertiesChanged")
+    }
+
+    #[tokio::test]
+    async fn test_status_updates_emit_changed_properties() {
+        let (server, client, _rx) = serve().await;
+        let mut signals = MessageStream::from(&client);
+        let iface_ref = server
+            .object_server()
+            .interface::<_, Control>(PATH)
+            .await
+            .unwrap();
+
+        let status = Status {
+            active_count: 2,
+            paused_until: -1,
+            ..Default::default()
+        };
+        iface_ref
+            .get_mut()
+            .await
+            .update(iface_ref.signal_emitter(), status)
+            .await
+            .unwrap();
+        // One signal per changed property, none for the rest
+        assert_eq!(next_changed(&mut signals).await, ["ActiveCount"]);
+        assert_eq!(next_changed(&mut signals).await, ["PausedUntil"]);
+
+        assert_eq!(get_property::<u32>(&client, "ActiveCount").await, 2);
+        assert_eq!(get_property::<u32>(&client, "HistoryCount").await, 0);
+        assert_eq!(get_property::<i64>(&client, "PausedUntil").await, -1);
+    }
+
+    #[tokio::test]
+    async fn test_set_do_not_disturb() {
+        let (_server, client, mut rx) = serve().await;
+
+        client
+            .call_method(
+                None::<&str>,
+                PATH,
+                Some("org.freedesktop.DBus.Properties"),
+                "Set",
+                &(IFACE, "DoNotDisturb", zbus::zvariant::Value::from(true)),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            rx.recv().await,
+            Some(Input::Control(ControlRequest::SetDoNotDisturb(true)))
+        ));
+        assert!(get_property::<bool>(&client, "DoNotDisturb").await);
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_answered_by_the_app() {
+        let (_server, client, mut rx) = serve().await;
+        tokio::spawn(async move {
+            while let Some(input) = rx.recv().await {
+                match input {
+                    Input::Control(ControlRequest::DismissApp { app, tx }) => {
+                        _ = tx.send(if app == "Slack" { 3 } else { 0 });
+                    }
+                    Input::Control(ControlRequest::ShowHistoryItem { id, tx }) => {
+                        _ = tx.send(id == 7);
+                    }
+                    // Nothing on screen to focus
+                    Input::Control(ControlRequest::FocusPopups { tx }) => {
+                        _ = tx.send(false);
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let reply = client
+            .call_method(None::<&str>, PATH, Some(IFACE), "DismissApp", &"Slack")
+            .await
+            .unwrap();
+        assert_eq!(reply.body().deserialize::<u32>().unwrap(), 3);
+
+        assert!(
+            client
+                .call_method(None::<&str>, PATH, Some(IFACE), "ShowHistoryItem", &7u32)
+                .await
+                .is_ok()
+        );
+        let reply = client
+            .call_method(None::<&str>, PATH, Some(IFACE), "ShowHistoryItem", &8u32)
+            .await;
+        assert_eq!(error_name(reply), "org.freedesktop.DBus.Error.FileNotFound");
+        let reply = client
+            .call_method(None::<&str>, PATH, Some(IFACE), "FocusPopups", &())
+            .await;
+        assert_eq!(error_name(reply), "org.freedesktop.DBus.Error.FileNotFound");
+    }
+
+    #[tokio::test]
+    async fn test_past_time_is_an_invalid_argument() {
+        let (_server, client, _rx) = serve().await;
+
+        let reply = client
+            .call_method(
+                None::<&str>,
+                PATH,
+                Some(IFACE),
+                "SetDoNotDisturbUntil",
+                &0i64,
+            )
+            .await;
+        assert_eq!(error_name(reply), "org.freedesktop.DBus.Error.InvalidArgs");
+    }
+}
@@ -0,0 +1,501 @@
+use crate::constants::{EVENT_SOCKET_BUFFER_SIZE, EVENT_SOCKET_WRITE_TIMEOUT, MAX_EVENT_SUBSCRIBERS};
+use crate::state::history::content_hash;
+use cosmic_ext_notifications_util::{CloseReason, Hint, Image, Notification};
+use serde::Serialize;
+use std::{
+    fs::{self, Permissions},
+    io,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream, unix::OwnedWriteHalf},
+    sync::{broadcast, watch},
+    task::JoinHandle,
+};
+
+use super::control::Status;
+
+/// Name of the socket in `$XDG_RUNTIME_DIR`
+pub const SOCKET_NAME: &str = "cosmic-ext-notifications.sock";
+
+/// Image data of a notification, referenced by the SHA-256 of its bytes
+///
+/// The hash is the one history stores the image under.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ImageRef {
+    /// RGBA `image-data` hint
+    Data {
+        width: u32,
+        height: u32,
+        hash: String,
+    },
+    /// `icon_data` hint
+    IconData { hash: String },
+}
+
+/// Event written to subscribers as one line of JSON
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum StreamEvent {
+    /// A notification arrived
+    Notified {
+        notification: Notification,
+        images: Vec<ImageRef>,
+    },
+    /// A notification replaced an open one with the same ID
+    Replaced {
+        notification: Notification,
+        images: Vec<ImageRef>,
+    },
+    /// A notification was closed
+    Closed { id: u32, reason: &'static str },
+    /// An action of a notification was invoked
+    ActionInvoked { id: u32, action: String },
+    /// Do-not-disturb was turned on or off, or its end moved
+    DndChanged {
+        do_not_disturb: bool,
+        paused_until: i64,
+    },
+    /// Cards on screen or notifications in history came or went
+    CountChanged {
+        active: u32,
+        history: u32,
+        unread: u32,
+    },
+    /// The subscriber fell behind and missed this many events
+    Lagged { missed: u64 },
+}
+
+impl StreamEvent {
+    pub fn notified(notification: &Notification) -> Self {
+        let (notification, images) = strip_images(notification);
+        StreamEvent::Notified {
+            notification,
+            images,
+        }
+    }
+
+    pub fn replaced(notification: &Notification) -> Self {
+        let (notification, images) = strip_images(notification);
+        StreamEvent::Replaced {
+            notification,
+            images,
+        }
+    }
+
+    pub fn closed(id: u32, reason: CloseReason) -> Self {
+        StreamEvent::Closed {
+            id,
+            reason: reason_name(reason),
+        }
+    }
+
+    fn dnd_changed(status: &Status) -> Self {
+        StreamEvent::DndChanged {
+            do_not_disturb: status.do_not_disturb,
+            paused_until: status.paused_until,
+        }
+    }
+
+    fn count_changed(status: &Status) -> Self {
+        StreamEvent::CountChanged {
+            active: status.active_count,
+            history: status.history_count,
+            unread: status.unread_count,
+        }
+    }
+
+    /// Serialize the event as a line of JSON
+    fn to_line(&self) -> Option<Arc<str>> {
+        match serde_json::to_string(self) {
+            Ok(mut line) => {
+                line.push('\n');
+                Some(line.into())
+            }
+            Err(err) => {
+                tracing::error!("Failed to serialize {:?} event: {err}", self);
+                None
+            }
+        }
+    }
+}
+
+/// Name of a close reason as scripts see it
+pub fn reason_name(reason: CloseReason) -> &'static str {
+    match reason {
+        CloseReason::Expired => "expired",
+        CloseReason::Dismissed => "dismissed",
+        CloseReason::CloseNotification => "closed",
+        CloseReason::Undefined => "undefined",
+    }
+}
+
+/// The notification without its image data, and references to the images
+fn strip_images(notification: &Notification) -> (Notification, Vec<ImageRef>) {
+    let mut stripped = notification.clone();
+    let mut images = Vec::new();
+
+    stripped.hints.clear();
+    for hint in &notification.hints {
+        match hint {
+            Hint::Image(Image::Data {
+                width,
+                height,
+                data,
+            }) => images.push(ImageRef::Data {
+                width: *width,
+                height: *height,
+                hash: content_hash(data),
+            }),
+            Hint::IconData(data) => images.push(ImageRef::IconData {
+                hash: content_hash(data),
+            }),
+            hint => stripped.hints.push(hint.clone()),
+        }
+    }
+
+    (stripped, images)
+}
+
+/// Opt-in Unix socket streaming events to status bars and scripts
+///
+/// Every subscriber first gets the current do-not-disturb state and counts,
+/// then one line of JSON per event. Events are queued per subscriber up to
+/// `EVENT_SOCKET_BUFFER_SIZE`; a subscriber that falls further behind skips
+/// the oldest ones and is told how many with a `lagged` event, and one that
+/// stops reading altogether is disconnected. The daemon never waits for a
+/// subscriber.
+#[derive(Debug, Default)]
+pub struct EventSocket {
+    server: Option<Server>,
+    /// Latest daemon state, to tell what changed
+    status: Status,
+}
+
+impl EventSocket {
+    /// Start or stop serving the socket
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled == self.server.is_some() {
+            return;
+        }
+        if !enabled {
+            self.server = None;
+            tracing::info!("Stopped the event socket");
+            return;
+        }
+
+        let Some(path) = dirs::runtime_dir().map(|dir| dir.join(SOCKET_NAME)) else {
+            tracing::error!("Not starting the event socket, XDG_RUNTIME_DIR is not set");
+            return;
+        };
+        match Server::bind(path.clone(), self.status) {
+            Ok(server) => {
+                tracing::info!("Streaming events on {}", path.display());
+                self.server = Some(server);
+            }
+            Err(err) => tracing::error!(
+                "Failed to start the event socket at {}: {err}",
+                path.display()
+            ),
+        }
+    }
+
+    /// Send an event to every subscriber
+    ///
+    /// The event is only built if someone is listening.
+    pub fn publish(&self, event: impl FnOnce() -> StreamEvent) {
+        let Some(server) = &self.server else {
+            return;
+        };
+        if server.tx.receiver_count() == 0 {
+            return;
+        }
+        if let Some(line) = event().to_line() {
+            // Only fails once every subscriber is gone
+            _ = server.tx.send(line);
+        }
+    }
+
+    /// Take the latest daemon state, publishing what changed
+    pub fn update_status(&mut self, status: Status) {
+        let old = std::mem::replace(&mut self.status, status);
+        if let Some(server) = &self.server {
+            server.status.send_replace(status);
+        }
+        if (old.do_not_disturb, old.paused_until) != (status.do_not_disturb, status.paused_until) {
+            self.publish(|| StreamEvent::dnd_changed(&status));
+        }
+        if (old.active_count, old.history_count, old.unread_count)
+            != (
+                status.active_count,
+                status.history_count,
+                status.unread_count,
+            )
+        {
+            self.publish(|| StreamEvent::count_changed(&status));
+        }
+    }
+}
+
+/// Listening socket, removed again when dropped
+#[derive(Debug)]
+struct Server {
+    path: PathBuf,
+    tx: broadcast::Sender<Arc<str>>,
+    status: watch::Sender<Status>,
+    accept: JoinHandle<()>,
+}
+
+impl Server {
+    fn bind(path: PathBuf, status: Status) -> io::Result<Self> {
+        remove_stale_socket(&path)?;
+        let listener = UnixListener::bind(&path)?;
+        fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+
+        let (tx, _) = broadcast::channel(EVENT_SOCKET_BUFFER_SIZE);
+        let (status, status_rx) = watch::channel(status);
+        let accept = tokio::spawn(accept(listener, tx.clone(), status_rx));
+        Ok(Self {
+            path,
+            tx,
+            status,
+            accept,
+        })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        // Subscribers see the channel close and hang up once the accept
+        // task's sender is gone too
+        self.accept.abort();
+        if let Err(err) = fs::remove_file(&self.path) {
+            tracing::warn!("Failed to remove {}: {err}", self.path.display());
+        }
+    }
+}
+
+/// Remove a socket left behind by a daemon that did not shut down cleanly
+///
+/// Refuses to touch anything that is not a socket, or a socket another
+/// instance is still serving.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "path exists and is not a socket",
+        ));
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "another instance is serving events",
+        ));
+    }
+    fs::remove_file(path)
+}
+
+async fn accept(
+    listener: UnixListener,
+    tx: broadcast::Sender<Arc<str>>,
+    status: watch::Receiver<Status>,
+) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                tracing::error!("Failed to accept event subscriber: {err}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        if tx.receiver_count() >= MAX_EVENT_SUBSCRIBERS {
+            tracing::warn!("Refusing event subscriber, {MAX_EVENT_SUBSCRIBERS} already connected");
+            continue;
+        }
+        let rx = tx.subscribe();
+        let status = *status.borrow();
+        tokio::spawn(serve(stream, rx, status));
+    }
+}
+
+/// Write events to one subscriber until it hangs up or falls silent
+async fn serve(stream: UnixStream, mut rx: broadcast::Receiver<Arc<str>>, status: Status) {
+    let (mut reader, mut writer) = stream.into_split();
+
+    let snapshot: String = [
+        StreamEvent::dnd_changed(&status),
+        StreamEvent::count_changed(&status),
+    ]
+    .iter()
+    .filter_map(StreamEvent::to_line)
+    .map(|line| line.to_string())
+    .collect();
+    if write(&mut writer, &snapshot).await.is_err() {
+        return;
+    }
+
+    let mut buf = [0; 64];
+    loop {
+        let line = tokio::select! {
+            // Subscribers only listen, reading just notices them hang up
+            read = reader.read(&mut buf) => match read {
+                Ok(0) | Err(_) => break,
+                Ok(_) => continue,
+            },
+            line = rx.recv() => match line {
+                Ok(line) => line,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    match (StreamEvent::Lagged { missed }).to_line() {
+                        Some(line) => line,
+                        None => continue,
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        if let Err(err) = write(&mut writer, &line).await {
+            tracing::debug!("Dropped event subscriber: {err}");
+            break;
+        }
+    }
+}
+
+async fn write(writer: &mut OwnedWriteHalf, line: &str) -> io::Result<()> {
+    tokio::time::timeout(
+        Duration::from_secs(EVENT_SOCKET_WRITE_TIMEOUT),
+        writer.write_all(line.as_bytes()),
+    )
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "subscriber stopped reading"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    fn notification(hints: Vec<Hint>) -> Notification {
+        let mut n = Notification::new(
+            "Test",
+            7,
+            "",
+            "Summary",
+            "Body",
+            vec![],
+            HashMap::new(),
+            5000,
+        );
+        n.hints = hints;
+        n
+    }
+
+    #[test]
+    fn test_images_are_referenced_by_hash() {
+        let data = Arc::new(vec![1, 2, 3, 4]);
+        let n = notification(vec![
+            Hint::Urgency(2),
+            Hint::Image(Image::Data {
+                width: 1,
+                height: 1,
+                data: data.clone(),
+            }),
+        ]);
+        let StreamEvent::Notified {
+            notification,
+            images,
+        } = StreamEvent::notified(&n)
+        else {
+            panic!("expected a notified event");
+        };
+        assert_eq!(notification.hints, vec![Hint::Urgency(2)]);
+        assert_eq!(
+            images,
+            vec![ImageRef::Data {
+                width: 1,
+                height: 1,
+                hash: content_hash(&data),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_gets_snapshot_and_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SOCKET_NAME);
+        let status = Status {
+            active_count: 2,
+            ..Status::default()
+        };
+
+        let mut events = EventSocket {
+            server: Some(Server::bind(path.clone(), status).unwrap()),
+            status,
+        };
+        let stream = UnixStream::connect(&path).await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+
+        let line = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(
+            line,
+            r#"{"event":"dnd_changed","do_not_disturb":false,"paused_until":0}"#
+        );
+        let line = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(
+            line,
+            r#"{"event":"count_changed","active":2,"history":0,"unread":0}"#
+        );
+
+        // Wait for the subscriber to be registered before publishing
+        while events.server.as_ref().unwrap().tx.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+        events.publish(|| StreamEvent::closed(7, CloseReason::Dismissed));
+        events.update_status(Status {
+            active_count: 1,
+            ..status
+        });
+        let line = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(line, r#"{"event":"closed","id":7,"reason":"dismissed"}"#);
+        let line = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(
+            line,
+            r#"{"event":"count_changed","active":1,"history":0,"unread":0}"#
+        );
+
+        // Stopping removes the socket and hangs up on subscribers
+        events.set_enabled(false);
+        assert!(!path.exists());
+        assert_eq!(lines.next_line().await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_live_or_foreign_path_is_not_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SOCKET_NAME);
+
+        fs::write(&path, "not a socket").unwrap();
+        assert!(remove_stale_socket(&path).is_err());
+        fs::remove_file(&path).unwrap();
+
+        let live = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert_eq!(
+            remove_stale_socket(&path).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+        drop(live);
+        remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use zbus::{
+    Connection, ObjectServer, interface,
+    message::Header,
+    names::WellKnownName,
+    zvariant::{OwnedValue, Value},
+};
+
+use super::portal::{self, Invocation, RemoteAction, Translated};
+
+/// Object path of the `org.gtk.Notifications` server
+pub const PATH: &str = "/org/gtk/Notifications";
+
+/// Bus name GLib looks for before falling back to `org.freedesktop.Notifications`
+pub const BUS_NAME: &str = "org.gtk.Notifications";
+
+/// Notification server for `GNotification`
+///
+/// GLib sends notifications of unsandboxed `GApplication`s here whenever the
+/// name is owned. Actions are activated in the app through its
+/// `org.freedesktop.Application` interface instead of `ActionInvoked`.
+pub struct Gtk;
+
+/// Object path a `GApplication` exports its actions at
+fn application_path(app_id: &str) -> String {
+    format!("/{}", app_id.replace('.', "/").replace('-', "_"))
+}
+
+/// Activate an action of a notification in the app that sent it
+///
+/// An action without a name activates the app itself. Waits for the reply,
+/// which may take as long as starting the app.
+pub async fn activate(conn: &Connection, invocation: Invocation, token: &str) {
+    let app_id = invocation.app_id.as_str();
+    let path = application_path(app_id);
+    let mut platform_data: HashMap<&str, Value<'_>> = HashMap::new();
+    if !token.is_empty() {
+        platform_data.insert("activation-token", Value::from(token));
+        platform_data.insert("desktop-startup-id", Value::from(token));
+    }
+
+    let name = invocation.action.name.as_str();
+    let result = if name.is_empty() {
+        conn.call_method(
+            Some(app_id),
+            path.as_str(),
+            Some("org.freedesktop.Application"),
+            "Activate",
+            &(platform_data,),
+        )
+        .await
+    } else if let Some(action) = name.strip_prefix("app.") {
+        let parameter: Vec<OwnedValue> = invocation.action.target.into_iter().collect();
+        conn.call_method(
+            Some(app_id),
+            path.as_str(),
+            Some("org.freedesktop.Application"),
+            "ActivateAction",
+            &(action, parameter, platform_data),
+        )
+        .await
+    } else {
+        tracing::warn!("Ignoring action {name} of {app_id}, only app actions can be activated");
+        return;
+    };
+    if let Err(err) = result {
+        tracing::warn!("Failed to activate {app_id}: {err}");
+    }
+}
+
+#[interface(name = "org.gtk.Notifications")]
+impl Gtk {
+    /// Show a notification for an app, replacing its notification with the
+    /// same ID if that is still open
+    async fn add_notification(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] object_server: &ObjectServer,
+        app_id: &str,
+        id: &str,
+        notification: HashMap<String, OwnedValue>,
+    ) -> zbus::fdo::Result<()> {
+        // Actions are sent to the app ID as a bus name
+        if WellKnownName::try_from(app_id).is_err() {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "Invalid app ID {app_id:?}"
+            )));
+        }
+
+        let mut translated = Translated::new(&notification);
+        // Clicking a notification without a default action activates the app
+        if !translated.targets.contains_key("default") {
+            let activate = RemoteAction {
+                name: String::new(),
+                target: None,
+            };
+            translated.add_action("default", String::new(), activate);
+        }
+        translated
+            .hints
+            .insert("desktop-entry", Value::from(app_id.to_string()));
+
+        let caller = header.sender().map(|name| name.to_string());
+        portal::notifications(object_server)
+            .await?
+            .get_mut()
+            .await
+            .add_gtk_notification(conn, caller, app_id, id, translated)
+            .await;
+        Ok(())
+    }
+
+    /// Close an app's notification
+    async fn remove_notification(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] object_server: &ObjectServer,
+        app_id: &str,
+        id: &str,
+    ) -> zbus::fdo::Result<()> {
+        let caller = header.sender().map(|name| name.to_string());
+        portal::notifications(object_server)
+            .await?
+            .get()
+            .await
+            .remove_gtk_notification(caller.as_deref(), app_id, id)
+            .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_application_path() {
+        assert_eq!(
+            application_path("org.gnome.Calendar"),
+            "/org/gnome/Calendar"
+        );
+        assert_eq!(
+            application_path("org.example.my-app"),
+            "/org/example/my_app"
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use crate::constants::MAX_CONCURRENT_HOOKS;
+use cosmic_ext_notifications_config::{Hook, HookEvent};
+use cosmic_ext_notifications_util::{CloseReason, Notification, strip_html};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io,
+    process::{ExitStatus, Stdio},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{io::AsyncWriteExt, process::Command, sync::Semaphore};
+
+use super::events::reason_name;
+
+/// What a hook is told about a notification
+///
+/// Written as JSON to the hook's stdin, and as `NOTIFY_*` environment
+/// variables. The body has its markup stripped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Payload {
+    event: &'static str,
+    id: u32,
+    app_name: String,
+    /// Resolved app ID, or the `desktop-entry` hint
+    app_id: Option<String>,
+    summary: String,
+    body: String,
+    urgency: u8,
+    category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+}
+
+impl Payload {
+    fn notified(notification: &Notification) -> Self {
+        Self {
+            event: "notified",
+            id: notification.id,
+            app_name: notification.app_name.clone(),
+            app_id: notification
+                .app_id()
+                .or(notification.desktop_entry())
+                .map(str::to_string),
+            summary: notification.summary.clone(),
+            body: strip_html(&notification.body),
+            urgency: notification.urgency(),
+            category: notification.category().map(str::to_string),
+            reason: None,
+        }
+    }
+
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("NOTIFY_EVENT", self.event.to_string()),
+            ("NOTIFY_ID", self.id.to_string()),
+            ("NOTIFY_APP_NAME", self.app_name.clone()),
+            ("NOTIFY_SUMMARY", self.summary.clone()),
+            ("NOTIFY_BODY", self.body.clone()),
+            ("NOTIFY_URGENCY", self.urgency.to_string()),
+        ];
+        if let Some(app_id) = &self.app_id {
+            env.push(("NOTIFY_APP_ID", app_id.clone()));
+        }
+        if let Some(category) = &self.category {
+            env.push(("NOTIFY_CATEGORY", category.clone()));
+        }
+        if let Some(reason) = self.reason {
+            env.push(("NOTIFY_CLOSE_REASON", reason.to_string()));
+        }
+        env
+    }
+}
+
+/// Runs the hooks of per-app and content rules
+///
+/// Hooks are started without a shell, so nothing in a notification is ever
+/// interpreted as a command. At most `MAX_CONCURRENT_HOOKS` run at once; a
+/// hook started beyond that is skipped, like a sound over the playback
+/// limit, so a flood of notifications cannot fork a flood of processes.
+#[derive(Debug)]
+pub struct Hooks {
+    permits: Arc<Semaphore>,
+    /// Hooks to run once an open notification closes, with what they are
+    /// told about it
+    closing: HashMap<u32, (Payload, Vec<Hook>)>,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_HOOKS)),
+            closing: HashMap::new(),
+        }
+    }
+}
+
+impl Hooks {
+    /// Run the `Notified` hooks of an accepted notification and remember
+    /// its `Closed` hooks
+    ///
+    /// A replacement takes over the hooks of the notification it replaces.
+    pub fn notified(&mut self, notification: &Notification, hooks: Vec<Hook>) {
+        if hooks.is_empty() {
+            self.closing.remove(&notification.id);
+            return;
+        }
+
+        let payload = Payload::notified(notification);
+        for hook in hooks
+            .iter()
+            .filter(|hook| hook.runs_on(HookEvent::Notified))
+        {
+            self.spawn(hook.clone(), payload.clone());
+        }
+
+        let closing: Vec<_> = hooks
+            .into_iter()
+            .filter(|hook| hook.runs_on(HookEvent::Closed))
+            .collect();
+        if closing.is_empty() {
+            self.closing.remove(&notification.id);
+        } else {
+            self.closing.insert(notification.id, (payload, closing));
+        }
+    }
+
+    /// Run the `Closed` hooks of a notification
+    pub fn closed(&mut self, id: u32, reason: CloseReason) {
+        let Some((mut payload, hooks)) = self.closing.remove(&id) else {
+            return;
+        };
+        payload.event = "closed";
+        payload.reason = Some(reason_name(reason));
+        for hook in hooks {
+            self.spawn(hook, payload.clone());
+        }
+    }
+
+    fn spawn(&self, hook: Hook, payload: Payload) {
+        let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+            tracing::warn!(
+                "Maximum concurrent hooks ({MAX_CONCURRENT_HOOKS}) reached, skipping {} for notification {}",
+                hook.command.display(),
+                payload.id
+            );
+            return;
+        };
+        tokio::spawn(async move {
+            match run(&hook, &payload).await {
+                Ok(status) if status.success() => {}
+                Ok(status) => tracing::warn!("Hook {} {status}", hook.command.display()),
+                Err(err) => tracing::warn!("Hook {} failed: {err}", hook.command.display()),
+            }
+            drop(permit);
+        });
+    }
+}
+
+/// Run a hook until it exits, killing it once its timeout passes
+async fn run(hook: &Hook, payload: &Payload) -> io::Result<ExitStatus> {
+    let json = serde_json::to_vec(payload)?;
+    let mut child = Command::new(&hook.command)
+        .args(&hook.args)
+        .envs(payload.env())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdin = child.stdin.take();
+
+    let timeout = Duration::from_secs(hook.timeout_secs.max(1).into());
+    let exited = tokio::time::timeout(timeout, async {
+        if let Some(mut stdin) = stdin {
+            // Hooks need not read stdin, one that exits first closes the pipe
+            _ = stdin.write_all(&json).await;
+        }
+        child.wait().await
+    })
+    .await;
+
+    match exited {
+        Ok(status) => status,
+        Err(_) => {
+            _ = child.kill().await;
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("killed after {}s", timeout.as_secs()),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmic_ext_notifications_util::Hint;
+    use std::time::Instant;
+
+    fn notification(summary: &str) -> Notification {
+        let mut n = Notification::new(
+            "Pager",
+            7,
+            "",
+            summary,
+            "<b>Disk</b> full",
+            vec![],
+            HashMap::new(),
+            5000,
+        );
+        n.hints = vec![
+            Hint::Urgency(2),
+            Hint::Category("x-pager.alert".to_string()),
+        ];
+        n
+    }
+
+    fn hook(command: &str, args: &[&str], timeout_secs: u32) -> Hook {
+        Hook {
+            command: command.into(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            on: vec![HookEvent::Notified, HookEvent::Closed],
+            timeout_secs,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hook_gets_env_and_json_without_shell_expansion() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        // The test script itself is a shell script, the notification text
+        // only ever reaches it as data
+        let hook = hook(
+            "/bin/sh",
+            &[
+                "-c",
+                r#"cat > "$0.json"; printf '%s|%s|%s' "$NOTIFY_SUMMARY" "$NOTIFY_BODY" "$NOTIFY_CLOSE_REASON" > "$0.env""#,
+                out.to_str().unwrap(),
+            ],
+            10,
+        );
+
+        let mut payload = Payload::notified(&notification("$(touch pwned) `id`"));
+        payload.event = "closed";
+        payload.reason = Some(reason_name(CloseReason::Dismissed));
+        assert!(run(&hook, &payload).await.unwrap().success());
+
+        let env = std::fs::read_to_string(dir.path().join("out.env")).unwrap();
+        assert_eq!(env, "$(touch pwned) `id`|Disk full|dismissed");
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join("out.json")).unwrap()).unwrap();
+        assert_eq!(json["event"], "closed");
+        assert_eq!(json["id"], 7);
+        assert_eq!(json["urgency"], 2);
+        assert_eq!(json["category"], "x-pager.alert");
+    }
+
+    #[tokio::test]
+    async fn test_hook_is_killed_after_timeout() {
+        let payload = Payload::notified(&notification("Slow"));
+        let start = Instant::now();
+        let err = run(&hook("sleep", &["30"], 1), &payload).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_closed_hooks_wait_for_close() {
+        let mut hooks = Hooks::default();
+        let on_close = Hook {
+            on: vec![HookEvent::Closed],
+            ..hook("true", &[], 10)
+        };
+
+        hooks.notified(&notification("A"), vec![on_close.clone()]);
+        assert_eq!(hooks.closing[&7].0.summary, "A");
+        // A replacement without hooks drops them
+        hooks.notified(&notification("B"), Vec::new());
+        assert!(hooks.closing.is_empty());
+
+        hooks.notified(&notification("C"), vec![on_close]);
+        hooks.closed(8, CloseReason::Expired);
+        assert_eq!(hooks.closing.len(), 1);
+        hooks.closed(7, CloseReason::Expired);
+        assert!(hooks.closing.is_empty());
+    }
+}
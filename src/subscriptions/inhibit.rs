@@ -0,0 +1,197 @@
+use crate::constants::MAX_INHIBITORS_PER_CLIENT;
+use std::collections::BTreeMap;
+
+/// A client holding popups back, e.g. while presenting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inhibitor {
+    pub application: String,
+    pub reason: String,
+    /// Unique bus name of the client, `None` on peer-to-peer connections
+    owner: Option<String>,
+}
+
+/// Why an inhibitor could not be added or removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InhibitError {
+    /// The client already holds `MAX_INHIBITORS_PER_CLIENT` inhibitors
+    TooMany,
+    /// No inhibitor with this cookie
+    UnknownCookie,
+    /// The inhibitor belongs to another client
+    NotOwner,
+}
+
+/// Inhibitors by cookie, in the order they were added
+#[derive(Debug)]
+pub struct Inhibitors {
+    next_cookie: u32,
+    inhibitors: BTreeMap<u32, Inhibitor>,
+}
+
+impl Default for Inhibitors {
+    fn default() -> Self {
+        Self {
+            next_cookie: 1,
+            inhibitors: BTreeMap::new(),
+        }
+    }
+}
+
+impl Inhibitors {
+    /// Check if any client is holding popups back
+    pub fn is_active(&self) -> bool {
+        !self.inhibitors.is_empty()
+    }
+
+    /// Application and reason of every inhibitor
+    pub fn list(&self) -> Vec<(String, String)> {
+        self.inhibitors
+            .values()
+            .map(|i| (i.application.clone(), i.reason.clone()))
+            .collect()
+    }
+
+    /// Add an inhibitor for `owner`, returning its cookie
+    pub fn add(
+        &mut self,
+        application: &str,
+        reason: &str,
+        owner: Option<&str>,
+    ) -> Result<u32, InhibitError> {
+        let held = self
+            .inhibitors
+            .values()
+            .filter(|i| i.owner.as_deref() == owner)
+            .count();
+        if held >= MAX_INHIBITORS_PER_CLIENT {
+            return Err(InhibitError::TooMany);
+        }
+
+        // Cookies are never 0 and skip ones still in use after wrapping
+        let mut cookie = self.next_cookie;
+        while cookie == 0 || self.inhibitors.contains_key(&cookie) {
+            cookie = cookie.wrapping_add(1);
+        }
+        self.next_cookie = cookie.wrapping_add(1);
+
+        self.inhibitors.insert(
+            cookie,
+            Inhibitor {
+                application: application.to_string(),
+                reason: reason.to_string(),
+                owner: owner.map(str::to_string),
+            },
+        );
+        Ok(cookie)
+    }
+
+    /// Remove an inhibitor, which only the client that added it may do
+    pub fn remove(&mut self, cookie: u32, caller: Option<&str>) -> Result<Inhibitor, InhibitError> {
+        match self.inhibitors.get(&cookie) {
+            None => Err(InhibitError::UnknownCookie),
+            Some(inhibitor) if inhibitor.owner.as_deref() != caller => Err(InhibitError::NotOwner),
+            Some(_) => self
+                .inhibitors
+                .remove(&cookie)
+                .ok_or(InhibitError::UnknownCookie),
+        }
+    }
+
+    /// Remove every inhibitor of a client that left the bus, returning how
+    /// many there were
+    pub fn remove_owner(&mut self, owner: &str) -> usize {
+        let before = self.inhibitors.len();
+        self.inhibitors
+            .retain(|_, i| i.owner.as_deref() != Some(owner));
+        before - self.inhibitors.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut inhibitors = Inhibitors::default();
+        assert!(!inhibitors.is_active());
+
+        let first = inhibitors
+            .add("Impress", "Presenting", Some(":1.7"))
+            .unwrap();
+        let second = inhibitors.add("OBS", "Recording", Some(":1.8")).unwrap();
+        assert_ne!(first, 0);
+        assert_ne!(first, second);
+        assert!(inhibitors.is_active());
+        assert_eq!(
+            inhibitors.list(),
+            [
+                ("Impress".to_string(), "Presenting".to_string()),
+                ("OBS".to_string(), "Recording".to_string()),
+            ]
+        );
+
+        assert_eq!(
+            inhibitors.remove(first, Some(":1.8")),
+            Err(InhibitError::NotOwner)
+        );
+        assert_eq!(
+            inhibitors.remove(first, Some(":1.7")).unwrap().application,
+            "Impress"
+        );
+        assert_eq!(
+            inhibitors.remove(first, Some(":1.7")),
+            Err(InhibitError::UnknownCookie)
+        );
+        assert!(inhibitors.is_active());
+    }
+
+    #[test]
+    fn test_remove_owner() {
+        let mut inhibitors = Inhibitors::default();
+        inhibitors
+            .add("Impress", "Presenting", Some(":1.7"))
+            .unwrap();
+        inhibitors
+            .add("Impress", "Slideshow", Some(":1.7"))
+            .unwrap();
+        let obs = inhibitors.add("OBS", "Recording", Some(":1.8")).unwrap();
+
+        assert_eq!(inhibitors.remove_owner(":1.7"), 2);
+        assert_eq!(inhibitors.remove_owner(":1.7"), 0);
+        assert_eq!(
+            inhibitors.list(),
+            [("OBS".to_string(), "Recording".to_string())]
+        );
+        inhibitors.remove(obs, Some(":1.8")).unwrap();
+        assert!(!inhibitors.is_active());
+    }
+
+    #[test]
+    fn test_per_client_limit() {
+        let mut inhibitors = Inhibitors::default();
+        for _ in 0..MAX_INHIBITORS_PER_CLIENT {
+            inhibitors.add("Spammer", "", Some(":1.9")).unwrap();
+        }
+        assert_eq!(
+            inhibitors.add("Spammer", "", Some(":1.9")),
+            Err(InhibitError::TooMany)
+        );
+        assert!(
+            inhibitors
+                .add("Impress", "Presenting", Some(":1.7"))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_cookies_skip_zero_and_used() {
+        let mut inhibitors = Inhibitors::default();
+        let kept = inhibitors.add("Impress", "", Some(":1.7")).unwrap();
+        inhibitors.next_cookie = u32::MAX;
+        assert_eq!(inhibitors.add("OBS", "", Some(":1.8")), Ok(u32::MAX));
+        // Wraps past 0 and the cookie still in use
+        assert_eq!(kept, 1);
+        assert_eq!(inhibitors.add("OBS", "", Some(":1.8")), Ok(2));
+    }
+}
@@ -1,2 +1,9 @@
 pub mod applet;
+pub mod control;
+pub mod events;
+pub mod gtk;
+pub mod hooks;
+pub mod inhibit;
 pub mod notifications;
+pub mod portal;
+pub mod sender;
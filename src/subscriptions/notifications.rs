@@ -7,17 +7,35 @@ use cosmic::{
     },
     iced_futures::Subscription,
 };
-use cosmic_ext_notifications_util::{ActionId, CloseReason, Notification};
+use cosmic_ext_notifications_config::{
+    NotificationsConfig, RateLimit, RateLimitPolicy, Rules, Subject,
+};
+use cosmic_ext_notifications_util::{
+    ActionId, CloseReason, Hint, HistoryPage, HistoryQuery, Notification, Routing, SenderIdentity,
+};
 use futures::channel::mpsc;
-use std::{collections::HashMap, fmt::Debug, num::NonZeroU64, time::{Duration, Instant}};
-use tokio::sync::mpsc::{Receiver, Sender, channel};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    num::NonZeroU64,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedSender, channel, unbounded_channel};
 use tracing::error;
 
 use zbus::{
-    Connection, connection::Builder as ConnectionBuilder, interface, object_server::SignalEmitter,
+    Connection, connection::Builder as ConnectionBuilder, fdo::DBusProxy, interface,
+    message::Header, object_server::SignalEmitter,
 };
 
 use super::applet::NotificationsApplet;
+use super::control::{self, Control, ControlRequest, Status};
+use super::events::{EventSocket, StreamEvent};
+use super::gtk::{self, Gtk};
+use super::hooks::Hooks;
+use super::inhibit::{InhibitError, Inhibitors};
+use super::portal::{self, Portal, RemoteIds, Translated};
+use super::sender::SenderCache;
 
 #[derive(Debug)]
 pub struct Conns {
@@ -28,7 +46,8 @@ pub struct Conns {
 }
 
 impl Conns {
-    pub async fn new() -> zbus::Result<Self> {
+    /// Connect to the session bus, numbering notifications from `first_id`
+    pub async fn new(first_id: u32) -> zbus::Result<Self> {
         let (tx, rx) = channel(CHANNEL_BUFFER_SIZE);
         let panel = match applet::setup_panel_conn(tx.clone()).await {
             Ok(conn) => Some(conn),
@@ -42,20 +61,27 @@ impl Conns {
             .name("org.freedesktop.Notifications")?
             .serve_at(
                 "/org/freedesktop/Notifications",
-                Notifications(
-                    tx.clone(),
-                    NonZeroU64::new(1).unwrap(),
-                    Vec::new(),
-                    RateLimiter::new(),
-                ),
+                Notifications::with_first_id(forward_inputs(tx.clone()), first_id),
             )?
             // Also serve the applet interface on session bus for history API access
             .serve_at(
                 "/com/system76/NotificationsApplet",
                 NotificationsApplet { tx: tx.clone() },
             )?
+            .serve_at(control::PATH, Control::new(tx.clone()))?
+            .serve_at(portal::PATH, Portal)?
+            .serve_at(gtk::PATH, Gtk)?
             .build()
             .await?;
+        // Only needed when xdg-desktop-portal is set up to use us
+        if let Err(err) = conn.request_name(portal::BUS_NAME).await {
+            error!("Failed to own {}: {}", portal::BUS_NAME, err);
+        }
+        // GLib falls back to `Notify` if another server holds it
+        if let Err(err) = conn.request_name(gtk::BUS_NAME).await {
+            error!("Failed to own {}: {}", gtk::BUS_NAME, err);
+        }
+        watch_name_owners(&conn, tx.clone());
 
         Ok(Self {
             tx,
@@ -64,6 +90,74 @@ impl Conns {
             _panel: panel,
         })
     }
+
+    /// ID the notifications interface would hand out next
+    async fn next_id(&self) -> Option<u32> {
+        let iface_ref = self
+            .notifications
+            .object_server()
+            .interface::<_, Notifications>("/org/freedesktop/Notifications")
+            .await
+            .ok()?;
+        let next_id = iface_ref.get().await.next_id;
+        u32::try_from(next_id.get()).ok()
+    }
+}
+
+/// Queue that forwards inputs in order to the input channel
+///
+/// The notifications interface sends while holding its lock, which the input
+/// loop may be waiting for, so it must never wait for room in the channel.
+/// The task ends once the channel or every sender of the queue is gone.
+fn forward_inputs(tx: Sender<Input>) -> UnboundedSender<Input> {
+    let (queue, mut rx) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(input) = rx.recv().await {
+            if tx.send(input).await.is_err() {
+                break;
+            }
+        }
+    });
+    queue
+}
+
+/// Report clients that leave the bus, so their inhibitors can be dropped
+///
+/// Stops once the input channel is closed.
+fn watch_name_owners(conn: &Connection, tx: Sender<Input>) {
+    let conn = conn.clone();
+    tokio::spawn(async move {
+        let changes = match DBusProxy::new(&conn).await {
+            Ok(proxy) => proxy.receive_name_owner_changed().await,
+            Err(err) => Err(err),
+        };
+        let mut changes = match changes {
+            Ok(changes) => changes,
+            Err(err) => {
+                error!("Failed to watch bus name owners: {}", err);
+                return;
+            }
+        };
+        loop {
+            let change = tokio::select! {
+                _ = tx.closed() => break,
+                change = futures::StreamExt::next(&mut changes) => change,
+            };
+            let Some(change) = change else {
+                break;
+            };
+            let Ok(args) = change.args() else {
+                continue;
+            };
+            // A unique name losing its owner means the client disconnected
+            if args.new_owner().is_none() && args.name().starts_with(':') {
+                let name = args.name().to_string();
+                if tx.send(Input::NameLost(name)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
 }
 
 /// State machine for D-Bus connection lifecycle
@@ -81,7 +175,12 @@ enum ConnectionState {
 }
 
 /// Process a single input message from the D-Bus interface
-async fn process_input(output: &mut mpsc::Sender<Event>, conns: &Conns, input: Input) {
+async fn process_input(
+    output: &mut mpsc::Sender<Event>,
+    conns: &Conns,
+    event_socket: &mut EventSocket,
+    input: Input,
+) {
     match input {
         Input::Activated { token, id, action } => {
             let object_server = conns.notifications.object_server();
@@ -92,6 +191,27 @@ async fn process_input(output: &mut mpsc::Sender<Event>, conns: &Conns, input: I
                 return;
             };
 
+            // Notifications sent through the portal are answered through it,
+            // GTK apps get their action activated directly
+            let (portal_invocation, gtk_invocation) = {
+                let iface = iface_ref.get().await;
+                (
+                    iface.portal_ids.invocation(id, &action),
+                    iface.gtk_ids.invocation(id, &action),
+                )
+            };
+            if let Some(invocation) = portal_invocation {
+                Portal::invoke(object_server, invocation, &token).await;
+                event_socket.publish(|| StreamEvent::ActionInvoked { id, action });
+                return;
+            }
+            if let Some(invocation) = gtk_invocation {
+                let conn = conns.notifications.clone();
+                tokio::spawn(async move { gtk::activate(&conn, invocation, &token).await });
+                event_socket.publish(|| StreamEvent::ActionInvoked { id, action });
+                return;
+            }
+
             if let Err(err) =
                 Notifications::activation_token(iface_ref.signal_emitter(), id, &token)
                     .await
@@ -106,30 +226,80 @@ async fn process_input(output: &mut mpsc::Sender<Event>, conns: &Conns, input: I
                 error!("Failed to signal activated notification {}", err);
             }
             tracing::trace!("Activated application");
+            event_socket.publish(|| StreamEvent::ActionInvoked { id, action });
+        }
+        Input::Replied { id, text } => {
+            let object_server = conns.notifications.object_server();
+            let Ok(iface_ref) = object_server
+                .interface::<_, Notifications>("/org/freedesktop/Notifications")
+                .await
+            else {
+                return;
+            };
+            if let Err(err) =
+                Notifications::notification_replied(iface_ref.signal_emitter(), id, &text).await
+            {
+                error!("Failed to signal notification reply {}", err);
+            }
         }
+        // The single place `NotificationClosed` is emitted from, so every
+        // notification is reported closed exactly once with its reason
         Input::Closed(id, reason) => {
             let object_server = conns.notifications.object_server();
             if let Ok(iface_ref) = object_server
                 .interface::<_, Notifications>("/org/freedesktop/Notifications")
                 .await
             {
-                _ = Notifications::notification_closed(
+                if let Err(err) = Notifications::notification_closed(
                     iface_ref.signal_emitter(),
                     id,
                     reason as u32,
                 )
-                .await;
+                .await
+                {
+                    error!("Failed to signal closed notification {}", err);
+                }
+                let mut iface = iface_ref.get_mut().await;
+                iface.hooks.closed(id, reason);
+                iface.portal_ids.remove(id);
+                iface.gtk_ids.remove(id);
+                iface.owners.remove(&id);
+                iface.coalesced.retain(|_, (card, _)| *card != id);
+                iface
+                    .signal_applets(AppletSignal::Removed { id, reason })
+                    .await;
+            }
+            event_socket.publish(|| StreamEvent::closed(id, reason));
+        }
+        Input::ClearHistory => {
+            _ = output.send(Event::ClearHistory).await;
+        }
+        Input::HistoryCleared => {
+            let object_server = conns.notifications.object_server();
+            if let Ok(iface_ref) = object_server
+                .interface::<_, Notifications>("/org/freedesktop/Notifications")
+                .await
+            {
+                iface_ref
+                    .get_mut()
+                    .await
+                    .signal_applets(AppletSignal::HistoryCleared)
+                    .await;
             }
         }
         Input::Notification(notification) => {
+            event_socket.publish(|| StreamEvent::notified(&notification));
             _ = output.send(Event::Notification(notification)).await;
         }
         Input::Replace(notification) => {
+            event_socket.publish(|| StreamEvent::replaced(&notification));
             _ = output.send(Event::Replace(notification)).await;
         }
         Input::CloseNotification(id) => {
+            // The app answers with `Input::Closed` if the card was still open
             _ = output.send(Event::CloseNotification(id)).await;
-
+        }
+        Input::AppletConn(c) => {
             let object_server = conns.notifications.object_server();
             let Ok(iface_ref) = object_server
                 .interface::<_, Notifications>("/org/freedesktop/Notifications")
@@ -137,14 +307,11 @@ async fn process_input(output: &mut mpsc::Sender<Event>, conns: &Conns, input: I
             else {
                 return;
             };
-            if let Err(err) =
-                Notifications::notification_closed(iface_ref.signal_emitter(), id, 3)
-                    .await
-            {
-                error!("Failed to signal close notification {}", err);
-            }
+            let mut iface = iface_ref.get_mut().await;
+            iface.applet_conns.push(c);
         }
-        Input::Dismissed(id) => {
+        Input::Config(config) => {
+            event_socket.set_enabled(config.event_socket);
             let object_server = conns.notifications.object_server();
             let Ok(iface_ref) = object_server
                 .interface::<_, Notifications>("/org/freedesktop/Notifications")
@@ -152,14 +319,9 @@ async fn process_input(output: &mut mpsc::Sender<Event>, conns: &Conns, input: I
             else {
                 return;
             };
-            if let Err(err) =
-                Notifications::notification_closed(iface_ref.signal_emitter(), id, 2)
-                    .await
-            {
-                error!("Failed to signal dismissed notification {}", err);
-            }
+            iface_ref.get_mut().await.set_config(*config);
         }
-        Input::AppletConn(c) => {
+        Input::ReserveIds(first_free) => {
             let object_server = conns.notifications.object_server();
             let Ok(iface_ref) = object_server
                 .interface::<_, Notifications>("/org/freedesktop/Notifications")
@@ -167,8 +329,7 @@ async fn process_input(output: &mut mpsc::Sender<Event>, conns: &Conns, input: I
             else {
                 return;
             };
-            let mut iface = iface_ref.get_mut().await;
-            iface.2.push(c);
+            iface_ref.get_mut().await.reserve_ids(first_free);
         }
         Input::AppletActivated { id, action } => {
             if let Err(err) = output
@@ -188,6 +349,60 @@ async fn process_input(output: &mut mpsc::Sender<Event>, conns: &Conns, input: I
                 );
             }
         }
+        Input::QueryHistory { query, tx } => {
+            if let Err(err) = output.send(Event::QueryHistory { query, tx }).await {
+                tracing::error!("Failed to send QueryHistory event to subscription channel: {err}");
+            }
+        }
+        Input::MissedDigest(mut notification) => {
+            let object_server = conns.notifications.object_server();
+            let Ok(iface_ref) = object_server
+                .interface::<_, Notifications>("/org/freedesktop/Notifications")
+                .await
+            else {
+                return;
+            };
+            notification.id = iface_ref.get_mut().await.next_id();
+            _ = output.send(Event::MissedDigest(*notification)).await;
+        }
+        Input::Inhibited(active) => {
+            _ = output.send(Event::Inhibited(active)).await;
+        }
+        Input::NameLost(name) => {
+            let object_server = conns.notifications.object_server();
+            let Ok(iface_ref) = object_server
+                .interface::<_, Notifications>("/org/freedesktop/Notifications")
+                .await
+            else {
+                return;
+            };
+            let mut iface = iface_ref.get_mut().await;
+            let removed = iface.inhibitors.remove_owner(&name);
+            if removed == 0 {
+                return;
+            }
+            tracing::info!("Dropped {removed} inhibitor(s) of {name}, which left the bus");
+            if let Err(err) = iface.inhibited_changed(iface_ref.signal_emitter()).await {
+                error!("Failed to signal inhibitor change {}", err);
+            }
+            _ = output
+                .send(Event::Inhibited(iface.inhibitors.is_active()))
+                .await;
+        }
+        Input::Control(request) => {
+            _ = output.send(Event::Control(request)).await;
+        }
+        Input::Status(status) => {
+            event_socket.update_status(status);
+            let object_server = conns.notifications.object_server();
+            let Ok(iface_ref) = object_server.interface::<_, Control>(control::PATH).await else {
+                return;
+            };
+            let mut iface = iface_ref.get_mut().await;
+            if let Err(err) = iface.update(iface_ref.signal_emitter(), status).await {
+                error!("Failed to signal status change {}", err);
+            }
+        }
         Input::CleanupRateLimiter => {
             let object_server = conns.notifications.object_server();
             if let Ok(iface_ref) = object_server
@@ -195,10 +410,10 @@ async fn process_input(output: &mut mpsc::Sender<Event>, conns: &Conns, input: I
                 .await
             {
                 let mut iface = iface_ref.get_mut().await;
-                iface.3.cleanup();
+                iface.rate_limiter.cleanup();
                 tracing::debug!(
                     "Rate limiter cleanup: {} apps tracked",
-                    iface.3.limits.len()
+                    iface.rate_limiter.buckets.len()
                 );
             }
         }
@@ -216,16 +431,45 @@ pub enum Input {
         id: u32,
         action: ActionId,
     },
+    /// Text typed into the inline reply field of a notification
+    Replied {
+        id: u32,
+        text: String,
+    },
     Notification(Notification),
     Replace(Notification),
     CloseNotification(u32),
+    /// A notification is gone; emits `NotificationClosed` with the reason
     Closed(u32, CloseReason),
-    Dismissed(u32),
     AppletConn(Connection),
     GetHistory {
         tx: tokio::sync::oneshot::Sender<Vec<Notification>>,
     },
+    /// Filtered and paged history request from the applet interface
+    QueryHistory {
+        query: Box<HistoryQuery>,
+        tx: tokio::sync::oneshot::Sender<HistoryPage>,
+    },
+    /// Request from the applet interface to clear history
+    ClearHistory,
+    /// History was cleared, sent by the app so applets can be told
+    HistoryCleared,
     CleanupRateLimiter,
+    /// Digest card from the app, sent back with a fresh ID
+    MissedDigest(Box<Notification>),
+    /// Latest configuration, used to apply per-app rules on ingest
+    Config(Box<NotificationsConfig>),
+    /// Lowest ID not taken by the app's cards or history; no lower ID is
+    /// handed out from then on
+    ReserveIds(u32),
+    /// Popups were inhibited or released through `Inhibit`/`UnInhibit`
+    Inhibited(bool),
+    /// A client left the bus, dropping its inhibitors
+    NameLost(String),
+    /// Request from the control interface
+    Control(ControlRequest),
+    /// Latest daemon state from the app, for the control interface
+    Status(Status),
 }
 
 #[derive(Debug)]
@@ -238,6 +482,17 @@ pub enum Event {
     GetHistory {
         tx: tokio::sync::oneshot::Sender<Vec<Notification>>,
     },
+    QueryHistory {
+        query: Box<HistoryQuery>,
+        tx: tokio::sync::oneshot::Sender<HistoryPage>,
+    },
+    ClearHistory,
+    /// Digest card of missed notifications, with its ID filled in
+    MissedDigest(Notification),
+    /// Whether any client is inhibiting popups
+    Inhibited(bool),
+    /// Request from the control interface
+    Control(ControlRequest),
 }
 
 impl Clone for Event {
@@ -251,23 +506,40 @@ impl Clone for Event {
                 id: *id,
                 action: action.clone()
             },
+            Event::ClearHistory => Event::ClearHistory,
+            Event::MissedDigest(n) => Event::MissedDigest(n.clone()),
+            Event::Inhibited(active) => Event::Inhibited(*active),
             Event::GetHistory { .. } => {
                 panic!("GetHistory event cannot be cloned - it contains a oneshot sender")
             }
+            Event::QueryHistory { .. } => {
+                panic!("QueryHistory event cannot be cloned - it contains a oneshot sender")
+            }
+            Event::Control(_) => {
+                panic!("Control event cannot be cloned - it may contain a oneshot sender")
+            }
         }
     }
 }
 
-pub fn notifications() -> Subscription<Event> {
+/// Serve the notifications interface
+///
+/// New notifications are numbered from `first_id`, so IDs restored from
+/// persisted history are not handed out again. After a reconnect numbering
+/// carries on where the lost connection stopped.
+pub fn notifications(first_id: u32) -> Subscription<Event> {
     struct SomeWorker;
 
     Subscription::run_with_id(
         std::any::TypeId::of::<SomeWorker>(),
-        stream::channel(100, |output| async move {
+        stream::channel(100, move |output| async move {
+            let mut first_id = first_id;
             let mut state = ConnectionState::Connecting {
                 output,
                 retry_count: 0,
             };
+            // Outlives reconnects, so subscribers stay connected
+            let mut event_socket = EventSocket::default();
 
             loop {
                 state = match state {
@@ -285,7 +557,7 @@ pub fn notifications() -> Subscription<Event> {
                             tokio::time::sleep(delay).await;
                         }
 
-                        match Conns::new().await {
+                        match Conns::new(first_id).await {
                             Ok(conns) => {
                                 tracing::info!("D-Bus connection established");
                                 _ = output.send(Event::Ready(conns.tx.clone())).await;
@@ -319,11 +591,13 @@ pub fn notifications() -> Subscription<Event> {
                         loop {
                             match conns.rx.recv().await {
                                 Some(input) => {
-                                    process_input(&mut output, &conns, input).await;
+                                    process_input(&mut output, &conns, &mut event_socket, input)
+                                        .await;
                                 }
                                 None => {
                                     // Channel closed - connection lost
                                     tracing::warn!("D-Bus connection lost, will reconnect");
+                                    first_id = conns.next_id().await.unwrap_or(first_id);
                                     break ConnectionState::Connecting {
                                         output,
                                         retry_count: 0,
@@ -338,207 +612,663 @@ pub fn notifications() -> Subscription<Event> {
     )
 }
 
+/// Token bucket of one client
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    limit: RateLimit,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(limit.burst.max(1)),
+            updated: now,
+            limit,
+        }
+    }
+
+    /// Add the tokens earned since the last update, up to the burst size
+    fn refill(&mut self, now: Instant) {
+        let earned = now.duration_since(self.updated).as_secs_f64()
+            * f64::from(self.limit.per_minute)
+            / 60.0;
+        self.tokens = (self.tokens + earned).min(f64::from(self.limit.burst.max(1)));
+        self.updated = now;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= f64::from(self.limit.burst.max(1))
+    }
+}
+
 /// Rate limiter to prevent notification spam attacks
+///
+/// Every client gets a token bucket holding up to `burst` notifications,
+/// refilled at `per_minute` notifications per minute.
 struct RateLimiter {
-    // app_name -> (window_start, count_in_window)
-    pub(super) limits: HashMap<String, (Instant, u32)>,
+    // client key (see `SenderIdentity::key`) -> bucket
+    pub(super) buckets: HashMap<String, Bucket>,
 }
 
 impl RateLimiter {
     fn new() -> Self {
         Self {
-            limits: HashMap::new(),
+            buckets: HashMap::new(),
         }
     }
 
-    /// Check if a notification from the given app should be accepted.
+    /// Check if a notification from the given client should be accepted.
     /// Returns true if under rate limit, false if rate limited.
-    fn check_and_update(&mut self, app_name: &str) -> bool {
-        const WINDOW: Duration = Duration::from_secs(60);
+    fn check_and_update(&mut self, client: &str, limit: RateLimit) -> bool {
+        if limit.per_minute == 0 {
+            return true;
+        }
 
         // If too many apps tracked, force cleanup first
-        if self.limits.len() >= RATE_LIMIT_MAX_APPS {
+        if self.buckets.len() >= RATE_LIMIT_MAX_APPS && !self.buckets.contains_key(client) {
             self.cleanup();
-        }
 
-        // If still too many after cleanup, reject (likely attack)
-        if self.limits.len() >= RATE_LIMIT_MAX_APPS {
-            tracing::warn!(
-                "Rate limiter tracking too many apps ({}), rejecting notification from '{}'",
-                self.limits.len(),
-                app_name
-            );
-            return false;
+            // If still too many after cleanup, reject (likely attack)
+            if self.buckets.len() >= RATE_LIMIT_MAX_APPS {
+                tracing::warn!(
+                    "Rate limiter tracking too many apps ({}), rejecting notification from '{}'",
+                    self.buckets.len(),
+                    client
+                );
+                return false;
+            }
         }
 
         let now = Instant::now();
-
-        let entry = self
-            .limits
-            .entry(app_name.to_string())
-            .or_insert((now, 0));
-
-        // Reset window if expired
-        if now.duration_since(entry.0) > WINDOW {
-            *entry = (now, 1);
-            return true;
-        }
-
-        // Check rate limit
-        if entry.1 >= RATE_LIMIT_PER_MINUTE {
+        let bucket = self
+            .buckets
+            .entry(client.to_string())
+            .or_insert_with(|| Bucket::new(limit, now));
+        // Rule changes apply from the next refill on
+        bucket.refill(now);
+        bucket.limit = limit;
+
+        if bucket.tokens < 1.0 {
             tracing::warn!(
-                "Rate limiting notifications from '{}' - exceeded {} notifications per minute",
-                app_name,
-                RATE_LIMIT_PER_MINUTE
+                "Rate limiting notifications from '{}' - exceeded {} notifications per minute (burst {})",
+                client,
+                limit.per_minute,
+                limit.burst
             );
             return false;
         }
 
-        entry.1 += 1;
+        bucket.tokens -= 1.0;
         true
     }
 
-    /// Clean up old entries periodically to prevent memory growth
+    /// Clean up full buckets periodically to prevent memory growth
     fn cleanup(&mut self) {
-        const WINDOW: Duration = Duration::from_secs(60);
         let now = Instant::now();
-        self.limits
-            .retain(|_, (start, _)| now.duration_since(*start) <= WINDOW);
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(now);
+            !bucket.is_full()
+        });
     }
 }
 
-pub struct Notifications(Sender<Input>, NonZeroU64, Vec<Connection>, RateLimiter);
+pub struct Notifications {
+    /// Inputs for the input loop, see `forward_inputs`
+    tx: UnboundedSender<Input>,
+    next_id: NonZeroU64,
+    applet_conns: Vec<Connection>,
+    /// Sequence number of the last state-change signal sent to applets
+    applet_seq: u64,
+    rate_limiter: RateLimiter,
+    /// Identities of the clients that sent notifications
+    senders: SenderCache,
+    /// Unique bus name of the client that owns each open notification,
+    /// `None` on peer-to-peer connections
+    owners: HashMap<u32, Option<String>>,
+    /// Summary card of each rate-limited client, as (card ID, notifications counted)
+    coalesced: HashMap<String, (u32, u32)>,
+    /// Clients holding popups back through `Inhibit`
+    inhibitors: Inhibitors,
+    config: NotificationsConfig,
+    /// Content rules of `config`, compiled
+    rules: Rules,
+    hooks: Hooks,
+    /// Open notifications sent through the portal
+    portal_ids: RemoteIds,
+    /// Open notifications sent through `org.gtk.Notifications`
+    gtk_ids: RemoteIds,
+}
+
+/// State change forwarded to applet connections
+#[derive(Debug)]
+enum AppletSignal {
+    /// A notification was closed
+    Removed { id: u32, reason: CloseReason },
+    /// A notification was replaced, serialized as JSON
+    Updated { id: u32, notification: String },
+    /// History was cleared
+    HistoryCleared,
+}
 
-#[interface(name = "org.freedesktop.Notifications")]
 impl Notifications {
-    async fn close_notification(&self, id: u32) {
-        if let Err(err) = self.0.send(Input::CloseNotification(id)).await {
-            tracing::error!("Failed to send close notification: {}", err);
+    pub fn new(tx: UnboundedSender<Input>) -> Self {
+        Self::with_first_id(tx, 1)
+    }
+
+    /// Create the interface, allocating IDs from `first_id` onwards
+    pub fn with_first_id(tx: UnboundedSender<Input>, first_id: u32) -> Self {
+        Self {
+            tx,
+            next_id: NonZeroU64::new(u64::from(first_id)).unwrap_or(NonZeroU64::MIN),
+            applet_conns: Vec::new(),
+            applet_seq: 0,
+            rate_limiter: RateLimiter::new(),
+            senders: SenderCache::default(),
+            owners: HashMap::new(),
+            coalesced: HashMap::new(),
+            inhibitors: Inhibitors::default(),
+            config: NotificationsConfig::default(),
+            rules: Rules::default(),
+            hooks: Hooks::default(),
+            portal_ids: RemoteIds::default(),
+            gtk_ids: RemoteIds::default(),
         }
     }
 
-    /// "action-icons"	Supports using icons instead of text for displaying actions. Using icons for actions must be enabled on a per-notification basis using the "action-icons" hint.
-    /// "actions"	The server will provide the specified actions to the user. Even if this cap is missing, actions may still be specified by the client, however the server is free to ignore them.
-    /// "body"	Supports body text. Some implementations may only show the summary (for instance, onscreen displays, marquee/scrollers)
-    /// "body-hyperlinks"	The server supports hyperlinks in the notifications.
-    /// "body-images"	The server supports images in the notifications.
-    /// "body-markup"	Supports markup in the body text. If marked up text is sent to a server that does not give this cap, the markup will show through as regular text so must be stripped clientside.
-    /// "icon-multi"	The server will render an animation of all the frames in a given image array. The client may still specify multiple frames even if this cap and/or "icon-static" is missing, however the server is free to ignore them and use only the primary frame.
-    /// "icon-static"	Supports display of exactly 1 frame of any given image array. This value is mutually exclusive with "icon-multi", it is a protocol error for the server to specify both.
-    /// "persistence"	The server supports persistence of notifications. Notifications will be retained until they are acknowledged or removed by the user or recalled by the sender. The presence of this capability allows clients to depend on the server to ensure a notification is seen and eliminate the need for the client to display a reminding function (such as a status icon) of its own.
-    /// "sound"	The server supports sounds on notifications. If returned, the server must support the "sound-file" and "suppress-sound" hints.
-    async fn get_capabilities(&self) -> Vec<&'static str> {
-        vec![
-            "body",           // Supports body text
-            "icon-static",    // Displays single-frame notification icons
-            "persistence",    // Notifications retained until acknowledged
-            "actions",        // Supports action buttons
-            "action-icons",   // Uses icons for action buttons when hint is set
-            "body-markup",    // Renders bold/italic styling in body
-            "body-hyperlinks",// Supports clickable links in body
-            "sound",          // Plays sound-file and sound-name hints
-        ]
+    /// Take a new configuration, compiling its content rules
+    ///
+    /// Rules with an invalid pattern are logged and left out.
+    fn set_config(&mut self, config: NotificationsConfig) {
+        let (rules, errors) = Rules::new(&config.rules);
+        for err in errors {
+            tracing::error!("Ignoring notification {err}");
+        }
+        self.rules = rules;
+        self.config = config;
     }
 
-    #[zbus(out_args("name", "vendor", "version", "spec_version"))]
-    async fn get_server_information(
-        &self,
-    ) -> (&'static str, &'static str, &'static str, &'static str) {
-        ("cosmic-ext-notifications", "olafkfreund", VERSION, "1.2")
+    /// Never hand out IDs below `first_free` from now on
+    fn reserve_ids(&mut self, first_free: u32) {
+        if u64::from(first_free) > self.next_id.get() {
+            self.next_id = NonZeroU64::new(u64::from(first_free)).unwrap_or(NonZeroU64::MIN);
+        }
     }
 
+    /// Allocate the next notification ID
+    fn next_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id = match self.next_id.checked_add(1) {
+            Some(id) => id,
+            None => {
+                tracing::warn!("Notification ID overflowed");
+                NonZeroU64::new(1).unwrap()
+            }
+        };
+        // Truncate u64 to u32 for D-Bus compatibility.
+        // Note: D-Bus spec requires u32, so we truncate. Collision risk is mitigated by:
+        // 1. Short notification lifetime (typically seconds/minutes)
+        // 2. Would need 4.2B notifications to wrap
+        // For extra safety, we could track active IDs, but overhead not justified.
+        id.get() as u32
+    }
+
+    /// Send a state-change signal to every applet connection
     ///
-    /// app_name	STRING	The optional name of the application sending the notification. Can be blank.
-    ///
-    /// replaces_id	UINT32	The optional notification ID that this notification replaces. The server must atomically (ie with no flicker or other visual cues) replace the given notification with this one. This allows clients to effectively modify the notification while it's active. A value of value of 0 means that this notification won't replace any existing notifications.
-    ///
-    /// app_icon	STRING	The optional program icon of the calling application. See Icons and Images. Can be an empty string, indicating no icon.
-    ///
-    /// summary	STRING	The summary text briefly describing the notification.
-    ///
-    /// body	STRING	The optional detailed body text. Can be empty.
-    ///
-    /// actions	as	Actions are sent over as a list of pairs. Each even element in the list (starting at index 0) represents the identifier for the action. Each odd element in the list is the localized string that will be displayed to the user.
+    /// Each signal carries the next sequence number, so an applet that sees
+    /// a gap knows it missed a change and should re-fetch history.
+    /// Connections that are gone are dropped.
+    async fn signal_applets(&mut self, signal: AppletSignal) {
+        self.applet_seq += 1;
+        let seq = self.applet_seq;
+
+        let mut new_conns = Vec::with_capacity(self.applet_conns.len());
+        for c in self.applet_conns.drain(..) {
+            let object_server = c.object_server();
+            let Ok(Ok(iface_ref)) = tokio::time::timeout(
+                tokio::time::Duration::from_millis(100),
+                object_server
+                    .interface::<_, NotificationsApplet>("/com/system76/NotificationsApplet"),
+            )
+            .await
+            else {
+                continue;
+            };
+            let emitter = iface_ref.signal_emitter();
+            let res = tokio::time::timeout(tokio::time::Duration::from_millis(500), async {
+                match &signal {
+                    AppletSignal::Removed { id, reason } => {
+                        NotificationsApplet::notification_removed(emitter, *id, *reason as u32, seq)
+                            .await
+                    }
+                    AppletSignal::Updated { id, notification } => {
+                        NotificationsApplet::notification_updated(emitter, *id, notification, seq)
+                            .await
+                    }
+                    AppletSignal::HistoryCleared => {
+                        NotificationsApplet::history_cleared(emitter, seq).await
+                    }
+                }
+            })
+            .await;
+            match res {
+                Ok(Err(err)) => error!("Failed to signal applet of {:?}: {}", signal, err),
+                Err(err) => error!("Failed to signal applet of {:?}: {}", signal, err),
+                Ok(_) => {}
+            }
+            new_conns.push(c);
+        }
+        self.applet_conns = new_conns;
+    }
+
+    /// ID to replace, or 0 if `replaces_id` is not an open notification of
+    /// the caller
+    fn owned_replace_id(&self, replaces_id: u32, caller: Option<&str>) -> u32 {
+        if replaces_id == 0 {
+            return 0;
+        }
+        match self.owners.get(&replaces_id) {
+            Some(owner) if owner.as_deref() == caller => replaces_id,
+            Some(owner) => {
+                tracing::warn!(
+                    "Denied {:?} replacing notification {} owned by {:?}",
+                    caller,
+                    replaces_id,
+                    owner
+                );
+                0
+            }
+            None => {
+                tracing::debug!(
+                    "Notification {replaces_id} to replace is not open, using a new ID"
+                );
+                0
+            }
+        }
+    }
+
+    /// Check if the caller may close a notification
     ///
-    /// hints	a{sv}	Optional hints that can be passed to the server from the client program. Although clients and servers should never assume each other supports any specific hints, they can be used to pass along information, such as the process PID or window ID, that the server may be able to make use of. See Hints. Can be empty.
-    /// expire_timeout	INT32
+    /// Only the client that sent an open notification may close it. IDs that
+    /// are not open are left to the app, which ignores them.
+    fn may_close(&self, id: u32, caller: Option<&str>) -> bool {
+        match self.owners.get(&id) {
+            Some(owner) if owner.as_deref() != caller => {
+                tracing::warn!(
+                    "Denied {:?} closing notification {} owned by {:?}",
+                    caller,
+                    id,
+                    owner
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Count a rate-limited notification on the client's summary card
     ///
-    /// The timeout time in milliseconds since the display of the notification at which the notification should automatically close.
-    /// If -1, the notification's expiration time is dependent on the notification server's settings, and may vary for the type of notification. If 0, never expire.
-    #[allow(clippy::too_many_arguments)]
-    async fn notify(
+    /// The first rejected notification opens a transient card such as
+    /// "Slack: 1 more notification", later ones update its count until the
+    /// card is closed.
+    async fn coalesce(
         &mut self,
+        client: &str,
         app_name: &str,
-        replaces_id: u32,
         app_icon: &str,
-        summary: &str,
-        body: &str,
-        actions: Vec<&str>,
-        hints: HashMap<&str, zbus::zvariant::Value<'_>>,
-        expire_timeout: i32,
-    ) -> u32 {
-        // Periodic cleanup of rate limiter to prevent memory growth
-        // Only cleanup periodically to avoid overhead
-        if self.1.get() % RATE_LIMIT_CLEANUP_INTERVAL == 0 {
-            self.3.cleanup();
-        }
-
-        // Check rate limit for new notifications (not replacements)
-        if replaces_id == 0 && !self.3.check_and_update(app_name) {
-            // Rate limited - return a non-zero dummy ID without processing.
-            // Use 1 as a safe fallback that won't conflict with active notifications
-            // and doesn't indicate an error (0 in D-Bus spec can trigger retries)
-            let dummy_id = 1u32;
-            tracing::debug!(
-                "Notification from '{}' rejected due to rate limiting, returning dummy ID {}",
-                app_name, dummy_id
-            );
-            return dummy_id;
-        }
+        sender: Option<SenderIdentity>,
+    ) {
+        let (id, count, replace) = match self.coalesced.get_mut(client) {
+            Some((id, count)) => {
+                *count += 1;
+                (*id, *count, true)
+            }
+            None => {
+                let id = self.next_id();
+                self.coalesced.insert(client.to_string(), (id, 1));
+                (id, 1, false)
+            }
+        };
 
-        let id = if replaces_id == 0 {
-            let id = self.1;
-            self.1 = match self.1.checked_add(1) {
-                Some(id) => id,
-                None => {
-                    tracing::warn!("Notification ID overflowed");
-                    NonZeroU64::new(1).unwrap()
-                }
-            };
-            // Truncate u64 to u32 for D-Bus compatibility.
-            // Note: D-Bus spec requires u32, so we truncate. Collision risk is mitigated by:
-            // 1. Short notification lifetime (typically seconds/minutes)
-            // 2. Would need 4.2B notifications to wrap
-            // For extra safety, we could track active IDs, but overhead not justified.
-            id.get() as u32
+        let name = if app_name.is_empty() {
+            sender
+                .as_ref()
+                .and_then(|s| s.app_id.clone())
+                .unwrap_or_else(|| client.to_string())
         } else {
-            replaces_id
+            app_name.to_string()
         };
-        let hints_clone = hints
-            .iter()
-            .filter_map(|(k, v)| Some((*k, v.try_clone().ok()?)))
-            .collect();
-        let n = Notification::new(
-            app_name,
+        let summary = Notification {
             id,
-            app_icon,
-            summary,
-            body,
-            actions.clone(),
-            hints_clone,
-            expire_timeout,
-        );
+            app_name: app_name.to_string(),
+            app_icon: app_icon.to_string(),
+            summary: format!(
+                "{name}: {count} more notification{}",
+                if count == 1 { "" } else { "s" }
+            ),
+            body: String::new(),
+            actions: Vec::new(),
+            hints: vec![Hint::Transient(true), Hint::Urgency(0)],
+            expire_timeout: -1,
+            time: SystemTime::now(),
+            sender,
+            routing: Routing::default(),
+        };
 
-        if !n.transient() {
-            let mut new_conns = Vec::with_capacity(self.2.len());
-            for c in self.2.drain(..) {
-                let object_server = c.object_server();
-                let Ok(Ok(iface_ref)) = tokio::time::timeout(
-                    tokio::time::Duration::from_millis(100),
-                    object_server
-                        .interface::<_, NotificationsApplet>("/com/system76/NotificationsApplet"),
-                )
-                .await
+        let input = if replace {
+            Input::Replace(summary)
+        } else {
+            Input::Notification(summary)
+        };
+        if let Err(err) = self.tx.send(input) {
+            tracing::error!("Failed to send rate limit summary: {}", err);
+        }
+    }
+
+    /// Announce a change of inhibitors on the bus and to the app
+    async fn inhibitors_changed(&self, emitter: &SignalEmitter<'_>) {
+        if let Err(err) = self.inhibited_changed(emitter).await {
+            error!("Failed to signal inhibitor change {}", err);
+        }
+        if let Err(err) = self.tx.send(Input::Inhibited(self.inhibitors.is_active())) {
+            tracing::error!("Failed to send inhibitor change: {}", err);
+        }
+    }
+
+    /// Report a notification rejected during `Notify` as closed
+    ///
+    /// The running `Notify` call holds the interface lock until its reply is
+    /// sent, so waiting for the lock guarantees clients learn the ID before
+    /// they see it closed.
+    fn close_rejected(&self, conn: &Connection, id: u32, reason: CloseReason) {
+        self.send_after_reply(conn, Input::Closed(id, reason));
+    }
+
+    /// Close a notification dropped by its rule during `Notify`
+    ///
+    /// A dropped replacement closes the card it was meant to replace, which
+    /// the app then reports closed like any other card. A new notification
+    /// never had a card, so it is reported closed right away.
+    fn close_dropped(&self, conn: &Connection, id: u32, replaces_id: u32) {
+        if replaces_id == 0 {
+            self.close_rejected(conn, id, CloseReason::Undefined);
+        } else {
+            self.send_after_reply(conn, Input::CloseNotification(replaces_id));
+        }
+    }
+
+    /// Send an input once the running `Notify` call has replied
+    fn send_after_reply(&self, conn: &Connection, input: Input) {
+        let conn = conn.clone();
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            if let Ok(iface_ref) = conn
+                .object_server()
+                .interface::<_, Notifications>("/org/freedesktop/Notifications")
+                .await
+            {
+                drop(iface_ref.get().await);
+            }
+            if let Err(err) = tx.send(input) {
+                tracing::error!("Failed to send close for rejected notification: {}", err);
+            }
+        });
+    }
+
+    /// Pass on a notification the portal sent for an app, replacing the
+    /// app's open notification with the same ID
+    pub(super) async fn add_portal_notification(
+        &mut self,
+        conn: &Connection,
+        caller: Option<String>,
+        app_id: &str,
+        id: &str,
+        notification: Translated,
+    ) {
+        let replaces_id = self.portal_ids.get(app_id, id).unwrap_or(0);
+        // The portal vouches for the app ID
+        let sender = SenderIdentity {
+            bus_name: caller.clone().unwrap_or_default(),
+            app_id: (!app_id.is_empty()).then(|| app_id.to_string()),
+            ..SenderIdentity::default()
+        };
+        let numeric = self
+            .submit(
+                conn,
+                caller,
+                Some(sender),
+                app_id,
+                replaces_id,
+                &notification.app_icon,
+                &notification.summary,
+                &notification.body,
+                notification.actions.iter().map(String::as_str).collect(),
+                notification.hints,
+                notification.expire_timeout,
+            )
+            .await;
+        self.portal_ids
+            .insert(numeric, app_id, id, notification.targets);
+    }
+
+    /// Close an app's notification sent through the portal
+    pub(super) async fn remove_portal_notification(&self, app_id: &str, id: &str) {
+        let Some(numeric) = self.portal_ids.get(app_id, id) else {
+            return;
+        };
+        if let Err(err) = self.tx.send(Input::CloseNotification(numeric)) {
+            tracing::error!("Failed to send close notification: {}", err);
+        }
+    }
+
+    /// Pass on a `GNotification`, replacing the app's open notification
+    /// with the same ID if the caller sent that one
+    pub(super) async fn add_gtk_notification(
+        &mut self,
+        conn: &Connection,
+        caller: Option<String>,
+        app_id: &str,
+        id: &str,
+        notification: Translated,
+    ) {
+        let replaces_id = self.gtk_ids.get(app_id, id).unwrap_or(0);
+        let replaces_id = self.owned_replace_id(replaces_id, caller.as_deref());
+        // The app ID is chosen by the caller, identify it like for `Notify`
+        let sender = match caller.as_deref() {
+            Some(name) => Some(self.senders.resolve(conn, name).await),
+            None => None,
+        };
+        let numeric = self
+            .submit(
+                conn,
+                caller,
+                sender,
+                app_id,
+                replaces_id,
+                &notification.app_icon,
+                &notification.summary,
+                &notification.body,
+                notification.actions.iter().map(String::as_str).collect(),
+                notification.hints,
+                notification.expire_timeout,
+            )
+            .await;
+        self.gtk_ids
+            .insert(numeric, app_id, id, notification.targets);
+    }
+
+    /// Close a `GNotification` if the caller sent it
+    pub(super) async fn remove_gtk_notification(
+        &self,
+        caller: Option<&str>,
+        app_id: &str,
+        id: &str,
+    ) {
+        let Some(numeric) = self.gtk_ids.get(app_id, id) else {
+            return;
+        };
+        if !self.may_close(numeric, caller) {
+            return;
+        }
+        if let Err(err) = self.tx.send(Input::CloseNotification(numeric)) {
+            tracing::error!("Failed to send close notification: {}", err);
+        }
+    }
+
+    /// Apply app and content rules and limits to a notification and pass
+    /// it on, returning its ID
+    ///
+    /// `caller` is the bus name allowed to close or replace it and `sender`
+    /// the identity it is attributed to. `replaces_id` must already be
+    /// checked to belong to `caller`.
+    #[allow(clippy::too_many_arguments)]
+    async fn submit(
+        &mut self,
+        conn: &Connection,
+        caller: Option<String>,
+        sender: Option<SenderIdentity>,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: Vec<&str>,
+        mut hints: HashMap<&str, zbus::zvariant::Value<'_>>,
+        expire_timeout: i32,
+    ) -> u32 {
+        // Periodic cleanup of rate limiter to prevent memory growth
+        // Only cleanup periodically to avoid overhead
+        if self.next_id.get() % RATE_LIMIT_CLEANUP_INTERVAL == 0 {
+            self.rate_limiter.cleanup();
+        }
+
+        let client = sender
+            .as_ref()
+            .map_or_else(|| app_name.to_string(), SenderIdentity::key);
+
+        // Look up the app's rule up front, it may override the rate limit. A
+        // sandbox app ID takes the place of the client's desktop-entry hint,
+        // the app ID of a systemd unit is tried after it.
+        let desktop_entry = hints
+            .get("desktop-entry")
+            .and_then(|v| <&str>::try_from(v).ok());
+        let desktop_entries = match &sender {
+            Some(sender) => sender.desktop_entries(desktop_entry),
+            None => desktop_entry.into_iter().collect(),
+        };
+        let rule = self
+            .config
+            .find_app_rule_by_entries(app_name, &desktop_entries)
+            .cloned();
+
+        let id = if replaces_id == 0 {
+            self.next_id()
+        } else {
+            replaces_id
+        };
+
+        if rule.as_ref().is_some_and(|rule| !rule.enabled) {
+            tracing::debug!("Dropping notification {id} from disabled app '{app_name}'");
+            self.close_dropped(conn, id, replaces_id);
+            return id;
+        }
+
+        // Content rules see the notification as sent, apart from the
+        // urgency its app rule sets
+        let evaluation = (!self.rules.is_empty()).then(|| {
+            let hint_names: Vec<&str> = hints.keys().copied().collect();
+            self.rules.evaluate(&Subject {
+                app_name,
+                desktop_entries: &desktop_entries,
+                category: hints.get("category").and_then(|v| <&str>::try_from(v).ok()),
+                urgency: rule
+                    .as_ref()
+                    .and_then(|rule| rule.urgency_override)
+                    .or_else(|| hints.get("urgency").and_then(|v| u8::try_from(v).ok()))
+                    .unwrap_or(1),
+                summary,
+                body,
+                hints: &hint_names,
+            })
+        });
+        if let Some(evaluation) = evaluation.as_ref().filter(|e| e.outcome.drop) {
+            tracing::debug!(
+                "Dropping notification {id} from '{app_name}' by rule {}",
+                evaluation.matched.join(", ")
+            );
+            self.close_dropped(conn, id, replaces_id);
+            return id;
+        }
+        let body = match &evaluation {
+            Some(evaluation) if evaluation.outcome.redact_body => "",
+            _ => body,
+        };
+
+        // Check rate limit for new notifications (not replacements)
+        let limit = self.config.rate_limit_for(rule.as_ref());
+        if replaces_id == 0 && !self.rate_limiter.check_and_update(&client, limit) {
+            // Rate limited - the ID is still unique (0 in D-Bus spec can
+            // trigger retries) and reported closed right away
+            tracing::debug!(
+                "Notification {} from '{}' ({}) rejected due to rate limiting",
+                id,
+                app_name,
+                client
+            );
+            if self.config.rate_limit_policy == RateLimitPolicy::Coalesce {
+                self.coalesce(&client, app_name, app_icon, sender).await;
+            }
+            self.close_rejected(conn, id, CloseReason::Undefined);
+            return id;
+        }
+
+        let hints_clone = hints
+            .iter()
+            .filter_map(|(k, v)| Some((*k, v.try_clone().ok()?)))
+            .collect();
+        let mut n = Notification::new(
+            app_name,
+            id,
+            app_icon,
+            summary,
+            body,
+            actions.clone(),
+            hints_clone,
+            expire_timeout,
+        );
+        n.sender = sender;
+
+        // Apply per-app and content rules before anyone else sees the
+        // notification
+        if let Some(rule) = &rule {
+            n.apply_app_rule(rule);
+            if rule.urgency_override.is_some() {
+                hints.insert("urgency", zbus::zvariant::Value::U8(n.urgency()));
+            }
+        }
+        if let Some(evaluation) = &evaluation {
+            tracing::debug!(
+                "Notification {id} matched rules {}",
+                evaluation.matched.join(", ")
+            );
+            let outcome = &evaluation.outcome;
+            n.apply_rule_outcome(outcome);
+            if outcome.urgency.is_some() {
+                hints.insert("urgency", zbus::zvariant::Value::U8(n.urgency()));
+            }
+        }
+
+        if !n.transient() {
+            let mut new_conns = Vec::with_capacity(self.applet_conns.len());
+            for c in self.applet_conns.drain(..) {
+                let object_server = c.object_server();
+                let Ok(Ok(iface_ref)) = tokio::time::timeout(
+                    tokio::time::Duration::from_millis(100),
+                    object_server
+                        .interface::<_, NotificationsApplet>("/com/system76/NotificationsApplet"),
+                )
+                .await
                 else {
                     continue;
                 };
@@ -557,7 +1287,7 @@ impl Notifications {
                         body,
                         actions.clone(),
                         hints_clone,
-                        expire_timeout,
+                        n.expire_timeout,
                     ),
                 )
                 .await
@@ -568,23 +1298,211 @@ impl Notifications {
                 }
                 new_conns.push(c);
             }
-            self.2 = new_conns;
+            self.applet_conns = new_conns;
+
+            if replaces_id != 0 {
+                match serde_json::to_string(&n) {
+                    Ok(notification) => {
+                        self.signal_applets(AppletSignal::Updated { id, notification })
+                            .await;
+                    }
+                    Err(err) => error!("Failed to serialize notification {}: {}", id, err),
+                }
+            }
         }
 
-        if let Err(err) = self
-            .0
-            .send(if replaces_id == 0 {
-                Input::Notification(n)
-            } else {
-                Input::Replace(n)
-            })
-            .await
-        {
+        let hooks = rule
+            .iter()
+            .flat_map(|rule| rule.hooks.iter().cloned())
+            .chain(
+                evaluation
+                    .into_iter()
+                    .flat_map(|evaluation| evaluation.outcome.hooks),
+            )
+            .collect();
+        self.hooks.notified(&n, hooks);
+
+        self.owners.insert(id, caller);
+        if let Err(err) = self.tx.send(if replaces_id == 0 {
+            Input::Notification(n)
+        } else {
+            Input::Replace(n)
+        }) {
             tracing::error!("Failed to send notification: {}", err);
         }
 
         id
     }
+}
+
+#[interface(name = "org.freedesktop.Notifications")]
+impl Notifications {
+    async fn close_notification(&self, #[zbus(header)] header: Header<'_>, id: u32) {
+        if !self.may_close(id, header.sender().map(|name| name.as_str())) {
+            return;
+        }
+        if let Err(err) = self.tx.send(Input::CloseNotification(id)) {
+            tracing::error!("Failed to send close notification: {}", err);
+        }
+    }
+
+    /// "action-icons"	Supports using icons instead of text for displaying actions. Using icons for actions must be enabled on a per-notification basis using the "action-icons" hint.
+    /// "actions"	The server will provide the specified actions to the user. Even if this cap is missing, actions may still be specified by the client, however the server is free to ignore them.
+    /// "body"	Supports body text. Some implementations may only show the summary (for instance, onscreen displays, marquee/scrollers)
+    /// "body-hyperlinks"	The server supports hyperlinks in the notifications.
+    /// "body-images"	The server supports images in the notifications.
+    /// "body-markup"	Supports markup in the body text. If marked up text is sent to a server that does not give this cap, the markup will show through as regular text so must be stripped clientside.
+    /// "icon-multi"	The server will render an animation of all the frames in a given image array. The client may still specify multiple frames even if this cap and/or "icon-static" is missing, however the server is free to ignore them and use only the primary frame.
+    /// "icon-static"	Supports display of exactly 1 frame of any given image array. This value is mutually exclusive with "icon-multi", it is a protocol error for the server to specify both.
+    /// "persistence"	The server supports persistence of notifications. Notifications will be retained until they are acknowledged or removed by the user or recalled by the sender. The presence of this capability allows clients to depend on the server to ensure a notification is seen and eliminate the need for the client to display a reminding function (such as a status icon) of its own.
+    /// "sound"	The server supports sounds on notifications. If returned, the server must support the "sound-file" and "suppress-sound" hints.
+    async fn get_capabilities(&self) -> Vec<&'static str> {
+        vec![
+            "body",            // Supports body text
+            "icon-static",     // Displays single-frame notification icons
+            "persistence",     // Notifications retained until acknowledged
+            "actions",         // Supports action buttons
+            "action-icons",    // Uses icons for action buttons when hint is set
+            "body-markup",     // Renders bold/italic styling in body
+            "body-hyperlinks", // Supports clickable links in body
+            "sound",           // Plays sound-file and sound-name hints
+            "inline-reply",    // Text field for the inline-reply action
+        ]
+    }
+
+    #[zbus(out_args("name", "vendor", "version", "spec_version"))]
+    async fn get_server_information(
+        &self,
+    ) -> (&'static str, &'static str, &'static str, &'static str) {
+        ("cosmic-ext-notifications", "olafkfreund", VERSION, "1.2")
+    }
+
+    ///
+    /// app_name	STRING	The optional name of the application sending the notification. Can be blank.
+    ///
+    /// replaces_id	UINT32	The optional notification ID that this notification replaces. The server must atomically (ie with no flicker or other visual cues) replace the given notification with this one. This allows clients to effectively modify the notification while it's active. A value of value of 0 means that this notification won't replace any existing notifications.
+    ///
+    /// app_icon	STRING	The optional program icon of the calling application. See Icons and Images. Can be an empty string, indicating no icon.
+    ///
+    /// summary	STRING	The summary text briefly describing the notification.
+    ///
+    /// body	STRING	The optional detailed body text. Can be empty.
+    ///
+    /// actions	as	Actions are sent over as a list of pairs. Each even element in the list (starting at index 0) represents the identifier for the action. Each odd element in the list is the localized string that will be displayed to the user.
+    ///
+    /// hints	a{sv}	Optional hints that can be passed to the server from the client program. Although clients and servers should never assume each other supports any specific hints, they can be used to pass along information, such as the process PID or window ID, that the server may be able to make use of. See Hints. Can be empty.
+    /// expire_timeout	INT32
+    ///
+    /// The timeout time in milliseconds since the display of the notification at which the notification should automatically close.
+    /// If -1, the notification's expiration time is dependent on the notification server's settings, and may vary for the type of notification. If 0, never expire.
+    #[allow(clippy::too_many_arguments)]
+    async fn notify(
+        &mut self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: Vec<&str>,
+        hints: HashMap<&str, zbus::zvariant::Value<'_>>,
+        expire_timeout: i32,
+    ) -> u32 {
+        // Only the sender of an open notification may replace it, anything
+        // else gets a new ID as if `replaces_id` was 0
+        let caller = header.sender().map(|name| name.to_string());
+        let replaces_id = self.owned_replace_id(replaces_id, caller.as_deref());
+
+        // Identify the client from its connection; `app_name` is untrusted
+        let sender = match header.sender() {
+            Some(name) => Some(self.senders.resolve(conn, name.as_str()).await),
+            None => None,
+        };
+
+        self.submit(
+            conn,
+            caller,
+            sender,
+            app_name,
+            replaces_id,
+            app_icon,
+            summary,
+            body,
+            actions,
+            hints,
+            expire_timeout,
+        )
+        .await
+    }
+
+    /// Hold popups back, e.g. while presenting, until `UnInhibit` is called
+    /// with the returned cookie or the caller leaves the bus
+    ///
+    /// Held back notifications are treated like under do not disturb.
+    async fn inhibit(
+        &mut self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        application: &str,
+        reason: &str,
+    ) -> zbus::fdo::Result<u32> {
+        let caller = header.sender().map(|name| name.as_str());
+        let cookie = match self.inhibitors.add(application, reason, caller) {
+            Ok(cookie) => cookie,
+            Err(_) => {
+                tracing::warn!(
+                    "Denied {:?} inhibiting notifications: too many inhibitors",
+                    caller
+                );
+                return Err(zbus::fdo::Error::LimitsExceeded(format!(
+                    "At most {MAX_INHIBITORS_PER_CLIENT} inhibitors per client"
+                )));
+            }
+        };
+        tracing::info!("Notifications inhibited by '{application}' ({caller:?}): {reason}");
+        self.inhibitors_changed(&emitter).await;
+        Ok(cookie)
+    }
+
+    /// Release an inhibitor added by the caller
+    async fn un_inhibit(
+        &mut self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        cookie: u32,
+    ) -> zbus::fdo::Result<()> {
+        let caller = header.sender().map(|name| name.as_str());
+        match self.inhibitors.remove(cookie, caller) {
+            Ok(inhibitor) => {
+                tracing::info!(
+                    "Notifications no longer inhibited by '{}'",
+                    inhibitor.application
+                );
+                self.inhibitors_changed(&emitter).await;
+                Ok(())
+            }
+            Err(InhibitError::NotOwner) => {
+                tracing::warn!(
+                    "Denied {:?} releasing inhibitor {} of another client",
+                    caller,
+                    cookie
+                );
+                Err(zbus::fdo::Error::AccessDenied(format!(
+                    "Inhibitor {cookie} belongs to another client"
+                )))
+            }
+            Err(_) => Err(zbus::fdo::Error::InvalidArgs(format!(
+                "No inhibitor with cookie {cookie}"
+            ))),
+        }
+    }
+
+    /// Application and reason of every current inhibitor
+    #[zbus(property)]
+    async fn inhibited(&self) -> Vec<(String, String)> {
+        self.inhibitors.list()
+    }
 
     #[zbus(signal)]
     async fn action_invoked(
@@ -600,6 +1518,14 @@ impl Notifications {
         activation_token: &str,
     ) -> zbus::Result<()>;
 
+    /// Text the user replied with through the `inline-reply` action
+    #[zbus(signal)]
+    async fn notification_replied(
+        signal_ctxt: &SignalEmitter<'_>,
+        id: u32,
+        text: &str,
+    ) -> zbus::Result<()>;
+
     /// id	UINT32	The ID of the notification that was closed.
     /// reason	UINT32
     ///
@@ -623,15 +1549,350 @@ impl Notifications {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use cosmic_ext_notifications_config::{AppPattern, AppRule, Rule, RuleAction};
+    use futures::StreamExt;
+    use tokio::net::UnixStream;
+    use zbus::{
+        Guid, MessageStream,
+        message::Type as MessageType,
+        zvariant::{OwnedValue, Value},
+    };
+
+    const PATH: &str = "/org/freedesktop/Notifications";
+    const IFACE: &str = "org.freedesktop.Notifications";
+
+    /// A client connected peer-to-peer to a `Notifications` server whose input
+    /// loop runs like in production, so signals reach the client for real.
+    struct TestBus {
+        tx: Sender<Input>,
+        server: Connection,
+        client: Connection,
+        signals: MessageStream,
+        events: mpsc::Receiver<Event>,
+    }
+
+    impl TestBus {
+        async fn new(config: NotificationsConfig) -> Self {
+            let (server_socket, client_socket) = UnixStream::pair().unwrap();
+            let (tx, rx) = channel(CHANNEL_BUFFER_SIZE);
+            let mut iface = Notifications::new(forward_inputs(tx.clone()));
+            iface.set_config(config);
+
+            let guid = Guid::generate();
+            let server = async {
+                ConnectionBuilder::socket(server_socket)
+                    .p2p()
+                    .server(guid)
+                    .unwrap()
+                    .serve_at(PATH, iface)
+                    .unwrap()
+                    .serve_at(portal::PATH, Portal)
+                    .unwrap()
+                    .serve_at(gtk::PATH, Gtk)
+                    .unwrap()
+                    .build()
+                    .await
+                    .unwrap()
+            };
+            let client = async {
+                ConnectionBuilder::socket(client_socket)
+                    .p2p()
+                    .build()
+                    .await
+                    .unwrap()
+            };
+            let (server, client) = tokio::join!(server, client);
+
+            let (mut output, events) = mpsc::channel(100);
+            let mut conns = Conns {
+                notifications: server.clone(),
+                tx: tx.clone(),
+                rx,
+                _panel: None,
+            };
+            tokio::spawn(async move {
+                let mut event_socket = EventSocket::default();
+                while let Some(input) = conns.rx.recv().await {
+                    process_input(&mut output, &conns, &mut event_socket, input).await;
+                }
+            });
+
+            let signals = MessageStream::from(&client);
+            Self {
+                tx,
+                server,
+                client,
+                signals,
+                events,
+            }
+        }
+
+        /// Submit a notification as if a client with the given identity had
+        /// sent it over the bus
+        async fn submit_as(
+            &self,
+            sender: SenderIdentity,
+            app_name: &str,
+            hints: HashMap<&str, Value<'_>>,
+        ) -> u32 {
+            let iface_ref = self
+                .server
+                .object_server()
+                .interface::<_, Notifications>(PATH)
+                .await
+                .unwrap();
+            let mut iface = iface_ref.get_mut().await;
+            iface
+                .submit(
+                    &self.server,
+                    Some(sender.bus_name.clone()),
+                    Some(sender),
+                    app_name,
+                    0,
+                    "",
+                    "Summary",
+                    "Body",
+                    Vec::new(),
+                    hints,
+                    -1,
+                )
+                .await
+        }
+
+        async fn notify(
+            &self,
+            app_name: &str,
+            replaces_id: u32,
+            hints: HashMap<&str, Value<'_>>,
+            expire_timeout: i32,
+        ) -> u32 {
+            let reply = self
+                .client
+                .call_method(
+                    None::<&str>,
+                    PATH,
+                    Some(IFACE),
+                    "Notify",
+                    &(
+                        app_name,
+                        replaces_id,
+                        "",
+                        "Summary",
+                        "Body",
+                        Vec::<&str>::new(),
+                        hints,
+                        expire_timeout,
+                    ),
+                )
+                .await
+                .unwrap();
+            reply.body().deserialize().unwrap()
+        }
+
+        async fn close_notification(&self, id: u32) {
+            self.client
+                .call_method(None::<&str>, PATH, Some(IFACE), "CloseNotification", &id)
+                .await
+                .unwrap();
+        }
+
+        /// Next signal with the given member name, as (id, second argument)
+        async fn next_signal<T>(&mut self, member: &str) -> T
+        where
+            T: for<'de> zbus::zvariant::DynamicDeserialize<'de>,
+        {
+            next_signal_on(&mut self.signals, member).await
+        }
+
+        /// Register an applet connection, returning the signals it receives
+        async fn connect_applet(&self) -> MessageStream {
+            let (server_socket, client_socket) = UnixStream::pair().unwrap();
+            let guid = Guid::generate();
+            let server = async {
+                ConnectionBuilder::socket(server_socket)
+                    .p2p()
+                    .server(guid)
+                    .unwrap()
+                    .serve_at(
+                        "/com/system76/NotificationsApplet",
+                        NotificationsApplet {
+                            tx: self.tx.clone(),
+                        },
+                    )
+                    .unwrap()
+                    .build()
+                    .await
+                    .unwrap()
+            };
+            let client = async {
+                ConnectionBuilder::socket(client_socket)
+                    .p2p()
+                    .build()
+                    .await
+                    .unwrap()
+            };
+            let (server, client) = tokio::join!(server, client);
+            self.tx.send(Input::AppletConn(server)).await.unwrap();
+            MessageStream::from(client)
+        }
+
+        async fn next_event(&mut self) -> Event {
+            tokio::time::timeout(Duration::from_secs(2), self.events.next())
+                .await
+                .expect("timed out waiting for event")
+                .expect("event stream ended")
+        }
+
+        async fn next_notification(&mut self) -> Notification {
+            loop {
+                match self.next_event().await {
+                    Event::Notification(n) | Event::Replace(n) => return n,
+                    _ => continue,
+                }
+            }
+        }
+
+        /// Next message of any kind received by the client
+        async fn next_message(&mut self) -> zbus::Message {
+            tokio::time::timeout(Duration::from_secs(2), self.signals.next())
+                .await
+                .expect("timed out waiting for a message")
+                .expect("message stream ended")
+                .unwrap()
+        }
+
+        /// All `NotificationClosed` signals received until the bus goes quiet
+        async fn closed_signals(&mut self) -> Vec<(u32, u32)> {
+            let mut closed = Vec::new();
+            while let Ok(Some(msg)) =
+                tokio::time::timeout(Duration::from_millis(300), self.signals.next()).await
+            {
+                let msg = msg.unwrap();
+                if msg.message_type() == MessageType::Signal
+                    && msg
+                        .header()
+                        .member()
+                        .is_some_and(|m| m.as_str() == "NotificationClosed")
+                {
+                    closed.push(msg.body().deserialize().unwrap());
+                }
+            }
+            closed
+        }
+
+        /// Assert that no event is delivered to the app within a short window
+        async fn assert_no_event(&mut self) {
+            let res = tokio::time::timeout(Duration::from_millis(200), self.events.next()).await;
+            assert!(res.is_err(), "unexpected event delivered to the app");
+        }
+    }
+
+    /// Next signal with the given member name received on `signals`
+    async fn next_signal_on<T>(signals: &mut MessageStream, member: &str) -> T
+    where
+        T: for<'de> zbus::zvariant::DynamicDeserialize<'de>,
+    {
+        let fut = async {
+            while let Some(msg) = signals.next().await {
+                let msg = msg.unwrap();
+                if msg.message_type() != MessageType::Signal {
+                    continue;
+                }
+                if msg.header().member().is_some_and(|m| m.as_str() == member) {
+                    return msg.body().deserialize::<T>().unwrap();
+                }
+            }
+            panic!("signal stream ended while waiting for {member}");
+        };
+        tokio::time::timeout(Duration::from_secs(2), fut)
+            .await
+            .unwrap_or_else(|_| panic!("timed out waiting for {member}"))
+    }
+
+    /// A chat message notification with one button, in the dictionary form
+    /// of the portal and `org.gtk.Notifications`
+    fn chat_notification(title: &str) -> HashMap<&'static str, Value<'_>> {
+        HashMap::from([
+            ("title", Value::from(title)),
+            ("body", Value::from("Lunch?")),
+            ("priority", Value::from("urgent")),
+            (
+                "buttons",
+                Value::from(vec![HashMap::from([
+                    ("label", Value::from("Reply")),
+                    ("action", Value::from("app.reply")),
+                    ("target", Value::from("thread-9")),
+                ])]),
+            ),
+        ])
+    }
+
+    /// Send a chat message notification the way xdg-desktop-portal
+    /// forwards it
+    async fn add_portal_notification(client: &Connection, title: &str) {
+        client
+            .call_method(
+                None::<&str>,
+                portal::PATH,
+                Some("org.freedesktop.impl.portal.Notification"),
+                "AddNotification",
+                &("org.example.Chat", "msg", chat_notification(title)),
+            )
+            .await
+            .unwrap();
+    }
+
+    /// Send a chat message notification the way `GNotification` does
+    async fn add_gtk_notification(
+        client: &Connection,
+        app_id: &str,
+    ) -> zbus::Result<zbus::Message> {
+        client
+            .call_method(
+                None::<&str>,
+                gtk::PATH,
+                Some("org.gtk.Notifications"),
+                "AddNotification",
+                &(app_id, "msg", chat_notification("Alice")),
+            )
+            .await
+    }
+
+    /// Records the actions activated in a GTK app
+    struct FakeApplication(Sender<(String, Vec<OwnedValue>, HashMap<String, OwnedValue>)>);
+
+    #[interface(name = "org.freedesktop.Application")]
+    impl FakeApplication {
+        async fn activate_action(
+            &self,
+            action: String,
+            parameter: Vec<OwnedValue>,
+            platform_data: HashMap<String, OwnedValue>,
+        ) {
+            _ = self.0.send((action, parameter, platform_data)).await;
+        }
+    }
+
+    fn config_with_rule(rule: AppRule) -> NotificationsConfig {
+        NotificationsConfig {
+            app_rules: vec![rule],
+            ..Default::default()
+        }
+    }
+
+    const LIMIT: RateLimit = RateLimit {
+        per_minute: 60,
+        burst: 20,
+    };
 
     #[test]
-    fn test_rate_limiter_allows_under_limit() {
+    fn test_rate_limiter_allows_burst() {
         let mut limiter = RateLimiter::new();
 
-        // Should allow first 60 notifications
-        for i in 1..=60 {
+        // Should allow a full burst
+        for i in 1..=LIMIT.burst {
             assert!(
-                limiter.check_and_update("test_app"),
+                limiter.check_and_update("test_app", LIMIT),
                 "Notification {} should be allowed",
                 i
             );
@@ -639,39 +1900,39 @@ mod tests {
     }
 
     #[test]
-    fn test_rate_limiter_blocks_over_limit() {
+    fn test_rate_limiter_blocks_over_burst() {
         let mut limiter = RateLimiter::new();
 
-        // Fill up to the limit
-        for _ in 1..=60 {
-            limiter.check_and_update("test_app");
+        // Use up the burst
+        for _ in 1..=LIMIT.burst {
+            limiter.check_and_update("test_app", LIMIT);
         }
 
-        // 61st should be blocked
+        // The next one should be blocked
         assert!(
-            !limiter.check_and_update("test_app"),
+            !limiter.check_and_update("test_app", LIMIT),
             "Notification over limit should be blocked"
         );
     }
 
     #[test]
-    fn test_rate_limiter_resets_after_window() {
+    fn test_rate_limiter_refills_over_time() {
         let mut limiter = RateLimiter::new();
 
-        // Fill up to the limit
-        for _ in 1..=60 {
-            limiter.check_and_update("test_app");
+        for _ in 1..=LIMIT.burst {
+            limiter.check_and_update("test_app", LIMIT);
         }
 
-        // Manually advance time by modifying the entry
-        if let Some(entry) = limiter.limits.get_mut("test_app") {
-            entry.0 = Instant::now() - Duration::from_secs(61);
+        // Manually advance time by two seconds, earning two tokens
+        if let Some(bucket) = limiter.buckets.get_mut("test_app") {
+            bucket.updated -= Duration::from_secs(2);
         }
 
-        // Should allow again after window expires
+        assert!(limiter.check_and_update("test_app", LIMIT));
+        assert!(limiter.check_and_update("test_app", LIMIT));
         assert!(
-            limiter.check_and_update("test_app"),
-            "Should allow after time window expires"
+            !limiter.check_and_update("test_app", LIMIT),
+            "Only earned tokens should be available"
         );
     }
 
@@ -679,47 +1940,70 @@ mod tests {
     fn test_rate_limiter_per_app_isolation() {
         let mut limiter = RateLimiter::new();
 
-        // Fill up limit for app1
-        for _ in 1..=60 {
-            limiter.check_and_update("app1");
+        // Use up the burst for app1
+        for _ in 1..=LIMIT.burst {
+            limiter.check_and_update("app1", LIMIT);
         }
 
         // app1 should be blocked
         assert!(
-            !limiter.check_and_update("app1"),
+            !limiter.check_and_update("app1", LIMIT),
             "app1 should be rate limited"
         );
 
         // app2 should still be allowed
         assert!(
-            limiter.check_and_update("app2"),
+            limiter.check_and_update("app2", LIMIT),
             "app2 should not be affected by app1's rate limit"
         );
     }
 
+    #[test]
+    fn test_rate_limiter_per_app_limits() {
+        let mut limiter = RateLimiter::new();
+        let strict = RateLimit {
+            per_minute: 6,
+            burst: 2,
+        };
+
+        assert!(limiter.check_and_update("chatty", strict));
+        assert!(limiter.check_and_update("chatty", strict));
+        assert!(!limiter.check_and_update("chatty", strict));
+
+        // A limit of 0 per minute disables rate limiting
+        let unlimited = RateLimit {
+            per_minute: 0,
+            burst: 0,
+        };
+        for _ in 0..1000 {
+            assert!(limiter.check_and_update("trusted", unlimited));
+        }
+        assert!(!limiter.buckets.contains_key("trusted"));
+    }
+
     #[test]
     fn test_rate_limiter_cleanup() {
         let mut limiter = RateLimiter::new();
 
         // Add entries for multiple apps
-        limiter.check_and_update("app1");
-        limiter.check_and_update("app2");
-        limiter.check_and_update("app3");
+        limiter.check_and_update("app1", LIMIT);
+        limiter.check_and_update("app2", LIMIT);
+        limiter.check_and_update("app3", LIMIT);
 
-        assert_eq!(limiter.limits.len(), 3, "Should have 3 apps tracked");
+        assert_eq!(limiter.buckets.len(), 3, "Should have 3 apps tracked");
 
-        // Manually age the entries
-        for (_, entry) in limiter.limits.iter_mut() {
-            entry.0 = Instant::now() - Duration::from_secs(61);
+        // Manually age two of the entries until their buckets are full again
+        for key in ["app1", "app2"] {
+            limiter.buckets.get_mut(key).unwrap().updated -= Duration::from_secs(61);
         }
 
-        // Cleanup should remove old entries
+        // Cleanup should only remove full buckets
         limiter.cleanup();
 
         assert_eq!(
-            limiter.limits.len(),
-            0,
-            "Cleanup should remove expired entries"
+            limiter.buckets.keys().collect::<Vec<_>>(),
+            ["app3"],
+            "Cleanup should remove refilled entries"
         );
     }
 
@@ -728,17 +2012,832 @@ mod tests {
         let mut limiter = RateLimiter::new();
 
         // Empty app names should still be rate limited
-        for i in 1..=60 {
+        for i in 1..=LIMIT.burst {
             assert!(
-                limiter.check_and_update(""),
+                limiter.check_and_update("", LIMIT),
                 "Empty app name notification {} should be allowed",
                 i
             );
         }
 
         assert!(
-            !limiter.check_and_update(""),
-            "Empty app name should be rate limited after 60"
+            !limiter.check_and_update("", LIMIT),
+            "Empty app name should be rate limited after the burst"
         );
     }
+
+    #[test]
+    fn test_rate_limiter_max_apps_limit() {
+        let mut limiter = RateLimiter::new();
+
+        for i in 0..RATE_LIMIT_MAX_APPS {
+            assert!(limiter.check_and_update(&format!("app{}", i), LIMIT));
+        }
+
+        // No bucket is full again yet, so a new app is rejected
+        assert!(!limiter.check_and_update("one_too_many", LIMIT));
+        assert_eq!(limiter.buckets.len(), RATE_LIMIT_MAX_APPS);
+
+        // Known apps keep their buckets
+        assert!(limiter.check_and_update("app0", LIMIT));
+
+        // Once a bucket refills, cleanup makes room for the new app
+        limiter.buckets.get_mut("app1").unwrap().updated -= Duration::from_secs(61);
+        assert!(limiter.check_and_update("one_too_many", LIMIT));
+        assert!(!limiter.buckets.contains_key("app1"));
+    }
+
+    #[tokio::test]
+    async fn test_notify_drops_disabled_app() {
+        let mut bus = TestBus::new(config_with_rule(AppRule {
+            app_name: "Steam".to_string(),
+            enabled: false,
+            ..Default::default()
+        }))
+        .await;
+
+        let id = bus.notify("Steam", 0, HashMap::new(), -1).await;
+        assert_ne!(id, 0);
+
+        let (closed_id, reason) = bus.next_signal::<(u32, u32)>("NotificationClosed").await;
+        assert_eq!(closed_id, id);
+        assert_eq!(reason, CloseReason::Undefined as u32);
+        bus.assert_no_event().await;
+    }
+
+    #[tokio::test]
+    async fn test_notify_keeps_apps_without_rule() {
+        let mut bus = TestBus::new(config_with_rule(AppRule {
+            app_name: "Steam".to_string(),
+            enabled: false,
+            ..Default::default()
+        }))
+        .await;
+
+        let id = bus.notify("Firefox", 0, HashMap::new(), -1).await;
+        let n = bus.next_notification().await;
+        assert_eq!(n.id, id);
+        assert_eq!(n.app_name, "Firefox");
+    }
+
+    #[tokio::test]
+    async fn test_notify_applies_urgency_override() {
+        let mut bus = TestBus::new(config_with_rule(AppRule {
+            app_name: "Slack".to_string(),
+            urgency_override: Some(2),
+            ..Default::default()
+        }))
+        .await;
+
+        let mut hints = HashMap::new();
+        hints.insert("urgency", Value::U8(0));
+        bus.notify("Slack", 0, hints, -1).await;
+
+        let n = bus.next_notification().await;
+        assert_eq!(n.urgency(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_notify_applies_timeout_override() {
+        let mut bus = TestBus::new(config_with_rule(AppRule {
+            app_name: "Slack".to_string(),
+            timeout_override: Some(15000),
+            ..Default::default()
+        }))
+        .await;
+
+        bus.notify("Slack", 0, HashMap::new(), 2000).await;
+
+        let n = bus.next_notification().await;
+        assert_eq!(n.expire_timeout, 15000);
+        assert_eq!(n.routing.timeout, Some(15000));
+    }
+
+    #[tokio::test]
+    async fn test_notify_suppresses_sound_for_muted_app() {
+        let mut bus = TestBus::new(config_with_rule(AppRule {
+            app_name: "Firefox".to_string(),
+            sound_enabled: false,
+            ..Default::default()
+        }))
+        .await;
+
+        let mut hints = HashMap::new();
+        hints.insert("sound-name", Value::from("message-new-instant"));
+        bus.notify("Firefox", 0, hints, -1).await;
+
+        let n = bus.next_notification().await;
+        assert!(n.suppress_sound());
+    }
+
+    #[tokio::test]
+    async fn test_notify_matches_rule_by_desktop_entry() {
+        let mut bus = TestBus::new(config_with_rule(AppRule {
+            app_name: "firefox".to_string(),
+            desktop_entry: Some("org.mozilla.firefox".to_string()),
+            enabled: false,
+            ..Default::default()
+        }))
+        .await;
+
+        let mut hints = HashMap::new();
+        hints.insert("desktop-entry", Value::from("org.mozilla.firefox"));
+        let id = bus.notify("Firefox Nightly", 0, hints, -1).await;
+
+        let (closed_id, _) = bus.next_signal::<(u32, u32)>("NotificationClosed").await;
+        assert_eq!(closed_id, id);
+        bus.assert_no_event().await;
+    }
+
+    #[tokio::test]
+    async fn test_unit_app_id_matches_rules_after_the_hint() {
+        let mut bus = TestBus::new(NotificationsConfig {
+            app_rules: vec![
+                AppRule {
+                    app_name: "Firefox".to_string(),
+                    desktop_entry: Some("firefox".to_string()),
+                    enabled: false,
+                    ..Default::default()
+                },
+                AppRule {
+                    app_name: "Terminal".to_string(),
+                    desktop_entry: Some("com.system76.CosmicTerm".to_string()),
+                    urgency_override: Some(0),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        })
+        .await;
+        // Started from a terminal, a client runs in the terminal's unit
+        let terminal = SenderIdentity {
+            bus_name: ":1.42".to_string(),
+            app_id: Some("com.system76.CosmicTerm".to_string()),
+            executable: Some("/usr/bin/firefox".into()),
+            ..Default::default()
+        };
+
+        // A rule keyed on the hint still matches
+        let mut hints = HashMap::new();
+        hints.insert("desktop-entry", Value::from("firefox"));
+        let id = bus.submit_as(terminal.clone(), "Firefox", hints).await;
+        let (closed_id, _) = bus.next_signal::<(u32, u32)>("NotificationClosed").await;
+        assert_eq!(closed_id, id);
+        bus.assert_no_event().await;
+
+        // Without a hint the unit's rule applies
+        let script = SenderIdentity {
+            bus_name: ":1.43".to_string(),
+            executable: Some("/usr/bin/notify-send".into()),
+            ..terminal
+        };
+        bus.submit_as(script, "notify-send", HashMap::new()).await;
+        let n = bus.next_notification().await;
+        assert_eq!(n.app_name, "notify-send");
+        assert_eq!(n.urgency(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_app_id_overrides_the_hint() {
+        let mut bus = TestBus::new(NotificationsConfig {
+            app_rules: vec![
+                AppRule {
+                    app_name: "Slack".to_string(),
+                    desktop_entry: Some("com.slack.Slack".to_string()),
+                    enabled: false,
+                    ..Default::default()
+                },
+                AppRule {
+                    app_name: "Chat".to_string(),
+                    desktop_entry: Some("org.example.Chat".to_string()),
+                    urgency_override: Some(2),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        })
+        .await;
+        let flatpak = SenderIdentity {
+            bus_name: ":1.42".to_string(),
+            app_id: Some("org.example.Chat".to_string()),
+            sandboxed: true,
+            ..Default::default()
+        };
+
+        // A sandboxed app cannot claim another app's rule through the hint
+        let mut hints = HashMap::new();
+        hints.insert("desktop-entry", Value::from("com.slack.Slack"));
+        let id = bus.submit_as(flatpak, "Slack", hints).await;
+        let n = bus.next_notification().await;
+        assert_eq!(n.id, id);
+        assert_eq!(n.urgency(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_notify_drops_by_content_rule() {
+        let mut bus = TestBus::new(NotificationsConfig {
+            rules: vec![Rule {
+                app: Some(AppPattern::Glob("Build*".to_string())),
+                hints: vec!["x-build-status".to_string()],
+                actions: vec![RuleAction::Drop],
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+        .await;
+
+        let mut hints = HashMap::new();
+        hints.insert("x-build-status", Value::from("ok"));
+        let id = bus.notify("Build Bot", 0, hints, -1).await;
+        let (closed_id, _) = bus.next_signal::<(u32, u32)>("NotificationClosed").await;
+        assert_eq!(closed_id, id);
+        bus.assert_no_event().await;
+
+        // Without the hint the rule does not match
+        bus.notify("Build Bot", 0, HashMap::new(), -1).await;
+        assert_eq!(bus.next_notification().await.app_name, "Build Bot");
+    }
+
+    #[tokio::test]
+    async fn test_content_rule_dropped_replacement_closes_open_card() {
+        let mut bus = TestBus::new(NotificationsConfig {
+            rules: vec![Rule {
+                hints: vec!["x-build-status".to_string()],
+                actions: vec![RuleAction::Drop],
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+        .await;
+
+        let id = bus.notify("Build Bot", 0, HashMap::new(), -1).await;
+        bus.next_notification().await;
+
+        let mut hints = HashMap::new();
+        hints.insert("x-build-status", Value::from("ok"));
+        assert_eq!(bus.notify("Build Bot", id, hints, -1).await, id);
+
+        // The card is closed through the app, which reports it closed once
+        assert!(matches!(
+            bus.next_event().await,
+            Event::CloseNotification(closed) if closed == id
+        ));
+        assert!(bus.closed_signals().await.is_empty());
+        bus.tx
+            .send(Input::Closed(id, CloseReason::CloseNotification))
+            .await
+            .unwrap();
+        assert_eq!(
+            bus.closed_signals().await,
+            [(id, CloseReason::CloseNotification as u32)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_notify_applies_content_rule_actions() {
+        let mut bus = TestBus::new(NotificationsConfig {
+            rules: vec![Rule {
+                body: Some("^Body$".to_string()),
+                urgency: vec![0],
+                actions: vec![
+                    RuleAction::SetUrgency(2),
+                    RuleAction::RedactBody,
+                    RuleAction::SkipHistory,
+                    RuleAction::SetTimeout(30000),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+        .await;
+
+        let mut hints = HashMap::new();
+        hints.insert("urgency", Value::U8(0));
+        bus.notify("Mail", 0, hints, 5000).await;
+
+        let n = bus.next_notification().await;
+        assert_eq!(n.urgency(), 2);
+        assert_eq!(n.body, "");
+        assert_eq!(n.expire_timeout, 30000);
+        assert!(n.routing.skip_history && !n.routing.history_only);
+        assert_eq!(n.routing.timeout, Some(30000));
+
+        // Normal urgency does not match
+        bus.notify("Mail", 0, HashMap::new(), 5000).await;
+        assert_eq!(bus.next_notification().await.body, "Body");
+    }
+
+    #[tokio::test]
+    async fn test_config_update_applies_to_later_notifications() {
+        let mut bus = TestBus::new(NotificationsConfig::default()).await;
+
+        bus.notify("Steam", 0, HashMap::new(), -1).await;
+        assert_eq!(bus.next_notification().await.app_name, "Steam");
+
+        let config = config_with_rule(AppRule {
+            app_name: "Steam".to_string(),
+            enabled: false,
+            ..Default::default()
+        });
+        // The app pushes config changes through the input channel
+        bus.tx.send(Input::Config(Box::new(config))).await.unwrap();
+
+        let id = bus.notify("Steam", 0, HashMap::new(), -1).await;
+        let (closed_id, _) = bus.next_signal::<(u32, u32)>("NotificationClosed").await;
+        assert_eq!(closed_id, id);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_notification_closed_after_reply() {
+        let mut bus = TestBus::new(config_with_rule(AppRule {
+            app_name: "Steam".to_string(),
+            enabled: false,
+            ..Default::default()
+        }))
+        .await;
+
+        let id = bus.notify("Steam", 0, HashMap::new(), -1).await;
+
+        // The Notify reply must reach the client before the close signal
+        let reply = bus.next_message().await;
+        assert_eq!(reply.message_type(), MessageType::MethodReturn);
+        assert_eq!(reply.body().deserialize::<u32>().unwrap(), id);
+
+        assert_eq!(
+            bus.closed_signals().await,
+            [(id, CloseReason::Undefined as u32)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dropped_replacement_closes_open_card() {
+        let mut bus = TestBus::new(NotificationsConfig::default()).await;
+
+        let id = bus.notify("Steam", 0, HashMap::new(), -1).await;
+        bus.next_notification().await;
+
+        let config = config_with_rule(AppRule {
+            app_name: "Steam".to_string(),
+            enabled: false,
+            ..Default::default()
+        });
+        bus.tx.send(Input::Config(Box::new(config))).await.unwrap();
+        assert_eq!(bus.notify("Steam", id, HashMap::new(), -1).await, id);
+
+        // The card is closed through the app, which reports it closed once
+        assert!(matches!(
+            bus.next_event().await,
+            Event::CloseNotification(closed) if closed == id
+        ));
+        assert!(bus.closed_signals().await.is_empty());
+        bus.tx
+            .send(Input::Closed(id, CloseReason::CloseNotification))
+            .await
+            .unwrap();
+        assert_eq!(
+            bus.closed_signals().await,
+            [(id, CloseReason::CloseNotification as u32)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_notification_gets_unique_id_and_close() {
+        let config = NotificationsConfig {
+            rate_limit_policy: RateLimitPolicy::Drop,
+            ..Default::default()
+        };
+        let burst = config.rate_limit.burst;
+        let mut bus = TestBus::new(config).await;
+
+        let mut accepted = Vec::new();
+        for _ in 0..burst {
+            accepted.push(bus.notify("Spammer", 0, HashMap::new(), -1).await);
+        }
+        let rejected = bus.notify("Spammer", 0, HashMap::new(), -1).await;
+
+        assert!(!accepted.contains(&rejected));
+        assert_eq!(
+            bus.closed_signals().await,
+            [(rejected, CloseReason::Undefined as u32)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_notifications_coalesce() {
+        let mut bus = TestBus::new(config_with_rule(AppRule {
+            app_name: "Slack".to_string(),
+            rate_limit: Some(RateLimit {
+                per_minute: 1,
+                burst: 1,
+            }),
+            ..Default::default()
+        }))
+        .await;
+
+        let accepted = bus.notify("Slack", 0, HashMap::new(), -1).await;
+        assert_eq!(bus.next_notification().await.id, accepted);
+
+        let first = bus.notify("Slack", 0, HashMap::new(), -1).await;
+        let summary = match bus.next_event().await {
+            Event::Notification(n) => n,
+            other => panic!("Expected a summary card, got {other:?}"),
+        };
+        assert_eq!(summary.summary, "Slack: 1 more notification");
+        assert!(summary.transient());
+        assert!(![accepted, first].contains(&summary.id));
+
+        let second = bus.notify("Slack", 0, HashMap::new(), -1).await;
+        match bus.next_event().await {
+            Event::Replace(n) => {
+                assert_eq!(n.id, summary.id);
+                assert_eq!(n.summary, "Slack: 2 more notifications");
+            }
+            other => panic!("Expected the summary card to update, got {other:?}"),
+        }
+        assert_eq!(
+            bus.closed_signals().await,
+            [
+                (first, CloseReason::Undefined as u32),
+                (second, CloseReason::Undefined as u32),
+            ]
+        );
+
+        // Once the card is closed, the next rejection starts a new one
+        bus.tx
+            .send(Input::Closed(summary.id, CloseReason::Dismissed))
+            .await
+            .unwrap();
+        bus.notify("Slack", 0, HashMap::new(), -1).await;
+        match bus.next_event().await {
+            Event::Notification(n) => {
+                assert_ne!(n.id, summary.id);
+                assert_eq!(n.summary, "Slack: 1 more notification");
+            }
+            other => panic!("Expected a new summary card, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_missed_digest_gets_fresh_id() {
+        let mut bus = TestBus::new(NotificationsConfig::default()).await;
+
+        let id = bus.notify("Slack", 0, HashMap::new(), -1).await;
+        bus.next_notification().await;
+
+        let digest = Notification::new(
+            "Notifications",
+            0,
+            "",
+            "While you were away",
+            "",
+            vec![],
+            HashMap::new(),
+            0,
+        );
+        bus.tx
+            .send(Input::MissedDigest(Box::new(digest)))
+            .await
+            .unwrap();
+        match bus.next_event().await {
+            Event::MissedDigest(n) => {
+                assert_ne!(n.id, 0);
+                assert_ne!(n.id, id);
+                assert_eq!(n.summary, "While you were away");
+            }
+            other => panic!("Expected the digest back, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inhibit_and_uninhibit() {
+        let mut bus = TestBus::new(NotificationsConfig::default()).await;
+
+        let reply = bus
+            .client
+            .call_method(
+                None::<&str>,
+                PATH,
+                Some(IFACE),
+                "Inhibit",
+                &("Impress", "Presenting"),
+            )
+            .await
+            .unwrap();
+        let cookie: u32 = reply.body().deserialize().unwrap();
+        assert_ne!(cookie, 0);
+        assert!(matches!(bus.next_event().await, Event::Inhibited(true)));
+
+        let reply = bus
+            .client
+            .call_method(
+                None::<&str>,
+                PATH,
+                Some("org.freedesktop.DBus.Properties"),
+                "Get",
+                &(IFACE, "Inhibited"),
+            )
+            .await
+            .unwrap();
+        let value: zbus::zvariant::OwnedValue = reply.body().deserialize().unwrap();
+        let inhibitors: Vec<(String, String)> = value.try_into().unwrap();
+        assert_eq!(
+            inhibitors,
+            [("Impress".to_string(), "Presenting".to_string())]
+        );
+
+        bus.client
+            .call_method(None::<&str>, PATH, Some(IFACE), "UnInhibit", &cookie)
+            .await
+            .unwrap();
+        assert!(matches!(bus.next_event().await, Event::Inhibited(false)));
+
+        // Releasing it twice is an error
+        assert!(
+            bus.client
+                .call_method(None::<&str>, PATH, Some(IFACE), "UnInhibit", &cookie)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_close_notification_emits_single_signal() {
+        let mut bus = TestBus::new(NotificationsConfig::default()).await;
+
+        let id = bus.notify("Thunderbird", 0, HashMap::new(), -1).await;
+        bus.next_notification().await;
+
+        bus.close_notification(id).await;
+        assert!(matches!(
+            bus.next_event().await,
+            Event::CloseNotification(closed) if closed == id
+        ));
+        // Nothing is emitted until the app confirms the card was open
+        assert!(bus.closed_signals().await.is_empty());
+
+        bus.tx
+            .send(Input::Closed(id, CloseReason::CloseNotification))
+            .await
+            .unwrap();
+        assert_eq!(
+            bus.closed_signals().await,
+            [(id, CloseReason::CloseNotification as u32)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_closed_signals_keep_order_and_reason() {
+        let mut bus = TestBus::new(NotificationsConfig::default()).await;
+
+        let first = bus.notify("Evolution", 0, HashMap::new(), -1).await;
+        let second = bus.notify("Evolution", 0, HashMap::new(), -1).await;
+        let third = bus.notify("Evolution", 0, HashMap::new(), -1).await;
+
+        for (id, reason) in [
+            (second, CloseReason::Expired),
+            (first, CloseReason::Dismissed),
+            (third, CloseReason::CloseNotification),
+        ] {
+            bus.tx.send(Input::Closed(id, reason)).await.unwrap();
+        }
+
+        assert_eq!(
+            bus.closed_signals().await,
+            [
+                (second, CloseReason::Expired as u32),
+                (first, CloseReason::Dismissed as u32),
+                (third, CloseReason::CloseNotification as u32),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_applet_signals_carry_sequence_numbers() {
+        let mut bus = TestBus::new(NotificationsConfig::default()).await;
+        let mut applet = bus.connect_applet().await;
+
+        let id = bus.notify("Slack", 0, HashMap::new(), -1).await;
+        bus.next_notification().await;
+        bus.tx
+            .send(Input::Closed(id, CloseReason::Dismissed))
+            .await
+            .unwrap();
+        let removed: (u32, u32, u64) = next_signal_on(&mut applet, "NotificationRemoved").await;
+        assert_eq!(removed, (id, CloseReason::Dismissed as u32, 1));
+
+        let other = bus.notify("Slack", 0, HashMap::new(), -1).await;
+        bus.notify("Slack", other, HashMap::new(), -1).await;
+        let (updated, json, seq): (u32, String, u64) =
+            next_signal_on(&mut applet, "NotificationUpdated").await;
+        assert_eq!((updated, seq), (other, 2));
+        let n: Notification = serde_json::from_str(&json).unwrap();
+        assert_eq!(n.id, other);
+
+        bus.tx.send(Input::HistoryCleared).await.unwrap();
+        let (seq,): (u64,) = next_signal_on(&mut applet, "HistoryCleared").await;
+        assert_eq!(seq, 3);
+    }
+
+    #[tokio::test]
+    async fn test_replacing_unknown_id_gets_new_id() {
+        let mut bus = TestBus::new(NotificationsConfig::default()).await;
+
+        let id = bus.notify("Firefox", 4242, HashMap::new(), -1).await;
+        assert_ne!(id, 4242);
+        assert!(matches!(bus.next_event().await, Event::Notification(n) if n.id == id));
+    }
+
+    #[tokio::test]
+    async fn test_replacing_closed_notification_gets_new_id() {
+        let mut bus = TestBus::new(NotificationsConfig::default()).await;
+
+        let id = bus.notify("Firefox", 0, HashMap::new(), -1).await;
+        bus.next_notification().await;
+        let replaced = bus.notify("Firefox", id, HashMap::new(), -1).await;
+        assert_eq!(replaced, id);
+        assert!(matches!(bus.next_event().await, Event::Replace(n) if n.id == id));
+
+        bus.tx
+            .send(Input::Closed(id, CloseReason::Expired))
+            .await
+            .unwrap();
+        bus.closed_signals().await;
+        let fresh = bus.notify("Firefox", id, HashMap::new(), -1).await;
+        assert_ne!(fresh, id);
+        assert!(matches!(bus.next_event().await, Event::Notification(n) if n.id == fresh));
+    }
+
+    #[tokio::test]
+    async fn test_portal_notification_round_trip() {
+        let mut bus = TestBus::new(NotificationsConfig::default()).await;
+        add_portal_notification(&bus.client, "Alice").await;
+        let n = bus.next_notification().await;
+        assert_eq!(n.app_name, "org.example.Chat");
+        assert_eq!(n.summary, "Alice");
+        assert_eq!(n.urgency(), 2);
+
+        // The same portal ID replaces the open notification
+        add_portal_notification(&bus.client, "Alice (2)").await;
+        assert!(matches!(bus.next_event().await, Event::Replace(r) if r.id == n.id));
+
+        bus.tx
+            .send(Input::Activated {
+                token: "token-1".to_string(),
+                id: n.id,
+                action: "0".to_string(),
+            })
+            .await
+            .unwrap();
+        let (app_id, id, action, parameter): (String, String, String, Vec<OwnedValue>) =
+            bus.next_signal("ActionInvoked").await;
+        assert_eq!((app_id.as_str(), id.as_str()), ("org.example.Chat", "msg"));
+        assert_eq!(action, "app.reply");
+        assert_eq!(<&str>::try_from(&parameter[0]).unwrap(), "thread-9");
+        let platform_data = HashMap::<String, OwnedValue>::try_from(parameter[1].clone()).unwrap();
+        assert_eq!(
+            <&str>::try_from(&platform_data["activation-token"]).unwrap(),
+            "token-1"
+        );
+
+        bus.client
+            .call_method(
+                None::<&str>,
+                portal::PATH,
+                Some("org.freedesktop.impl.portal.Notification"),
+                "RemoveNotification",
+                &("org.example.Chat", "msg"),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(bus.next_event().await, Event::CloseNotification(id) if id == n.id));
+    }
+
+    #[tokio::test]
+    async fn test_gtk_notification_activates_app_action() {
+        let mut bus = TestBus::new(NotificationsConfig::default()).await;
+        let (tx, mut activated) = channel(1);
+        bus.client
+            .object_server()
+            .at("/org/example/Chat", FakeApplication(tx))
+            .await
+            .unwrap();
+
+        // The app ID has to be usable as a bus name
+        assert!(
+            add_gtk_notification(&bus.client, "not an app id")
+                .await
+                .is_err()
+        );
+        add_gtk_notification(&bus.client, "org.example.Chat")
+            .await
+            .unwrap();
+        let n = bus.next_notification().await;
+        assert_eq!(n.app_name, "org.example.Chat");
+        assert_eq!(n.desktop_entry(), Some("org.example.Chat"));
+
+        bus.tx
+            .send(Input::Activated {
+                token: "token-1".to_string(),
+                id: n.id,
+                action: "0".to_string(),
+            })
+            .await
+            .unwrap();
+        let (action, parameter, platform_data) =
+            tokio::time::timeout(Duration::from_secs(2), activated.recv())
+                .await
+                .expect("timed out waiting for ActivateAction")
+                .unwrap();
+        assert_eq!(action, "reply");
+        assert_eq!(<&str>::try_from(&parameter[0]).unwrap(), "thread-9");
+        assert_eq!(
+            <&str>::try_from(&platform_data["activation-token"]).unwrap(),
+            "token-1"
+        );
+
+        bus.client
+            .call_method(
+                None::<&str>,
+                gtk::PATH,
+                Some("org.gtk.Notifications"),
+                "RemoveNotification",
+                &("org.example.Chat", "msg"),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(bus.next_event().await, Event::CloseNotification(id) if id == n.id));
+    }
+
+    #[tokio::test]
+    async fn test_inline_reply_signal() {
+        let mut bus = TestBus::new(NotificationsConfig::default()).await;
+        let reply = bus
+            .client
+            .call_method(None::<&str>, PATH, Some(IFACE), "GetCapabilities", &())
+            .await
+            .unwrap();
+        let capabilities: Vec<String> = reply.body().deserialize().unwrap();
+        assert!(capabilities.iter().any(|c| c == "inline-reply"));
+
+        let id = bus.notify("KDE Connect", 0, HashMap::new(), -1).await;
+        bus.next_notification().await;
+        bus.tx
+            .send(Input::Replied {
+                id,
+                text: "On my way".to_string(),
+            })
+            .await
+            .unwrap();
+        let (replied, text): (u32, String) = bus.next_signal("NotificationReplied").await;
+        assert_eq!((replied, text.as_str()), (id, "On my way"));
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_inputs_never_wait_and_keep_order() {
+        let (tx, mut rx) = channel(1);
+        let queue = forward_inputs(tx);
+
+        // Sending succeeds right away while the channel is full
+        for id in 1..=10 {
+            queue.send(Input::Closed(id, CloseReason::Expired)).unwrap();
+        }
+        for expected in 1..=10 {
+            match rx.recv().await {
+                Some(Input::Closed(id, _)) => assert_eq!(id, expected),
+                other => panic!("Expected close of {expected}, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_reserved_ids_are_skipped() {
+        let (tx, _rx) = unbounded_channel();
+        let mut iface = Notifications::new(tx);
+        assert_eq!(iface.next_id(), 1);
+
+        // IDs restored from history are not handed out again
+        iface.reserve_ids(10);
+        assert_eq!(iface.next_id(), 10);
+
+        // Reserving IDs already handed out changes nothing
+        iface.reserve_ids(5);
+        assert_eq!(iface.next_id(), 11);
+    }
+
+    #[test]
+    fn test_only_owner_may_replace_or_close() {
+        let (tx, _rx) = unbounded_channel();
+        let mut iface = Notifications::new(tx);
+        iface.owners.insert(5, Some(":1.10".to_string()));
+
+        assert_eq!(iface.owned_replace_id(5, Some(":1.10")), 5);
+        assert_eq!(iface.owned_replace_id(5, Some(":1.11")), 0);
+        assert_eq!(iface.owned_replace_id(6, Some(":1.10")), 0);
+
+        assert!(iface.may_close(5, Some(":1.10")));
+        assert!(!iface.may_close(5, Some(":1.11")));
+        assert!(!iface.may_close(5, None));
+        // Closing IDs that are not open is harmless
+        assert!(iface.may_close(6, Some(":1.11")));
+    }
 }
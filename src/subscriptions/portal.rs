@@ -0,0 +1,567 @@
+use cosmic_ext_notifications_util::NotificationImage;
+use std::collections::HashMap;
+use zbus::{
+    Connection, ObjectServer,
+    fdo::DBusProxy,
+    interface,
+    message::Header,
+    names::{BusName, WellKnownName},
+    object_server::SignalEmitter,
+    zvariant::{OwnedValue, Value},
+};
+
+use super::notifications::Notifications;
+
+/// Object path of the portal backend
+pub const PATH: &str = "/org/freedesktop/portal/desktop";
+
+/// Bus name the backend is found at, as named in its `.portal` file
+pub const BUS_NAME: &str = "io.github.olafkfreund.CosmicExtNotifications.Portal";
+
+/// Bus name of xdg-desktop-portal, the only client the backend serves
+const FRONTEND: &str = "org.freedesktop.portal.Desktop";
+
+/// Action an app wants activated, with its target parameter
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteAction {
+    pub name: String,
+    pub target: Option<OwnedValue>,
+}
+
+/// A notification in the dictionary form used by the portal and
+/// `org.gtk.Notifications`, translated to the arguments of `Notify`
+#[derive(Debug, Default)]
+pub struct Translated {
+    pub summary: String,
+    /// Body markup
+    pub body: String,
+    pub app_icon: String,
+    /// Action keys and labels, in turn
+    pub actions: Vec<String>,
+    pub hints: HashMap<&'static str, Value<'static>>,
+    pub expire_timeout: i32,
+    /// What the action keys stand for
+    pub targets: HashMap<String, RemoteAction>,
+}
+
+impl Translated {
+    /// Translate the `title`, `body`, `markup-body`, `icon`, `priority`,
+    /// `default-action`, `buttons`, `category`, `display-hint` and `sound`
+    /// entries; anything else is ignored
+    pub fn new(notification: &HashMap<String, OwnedValue>) -> Self {
+        let string = |key: &str| {
+            notification
+                .get(key)
+                .and_then(|v| <&str>::try_from(inner(v)).ok())
+                .map(str::to_string)
+        };
+
+        let mut translated = Self {
+            summary: string("title").unwrap_or_default(),
+            body: string("markup-body")
+                .or_else(|| string("body").map(|body| escape_markup(&body)))
+                .unwrap_or_default(),
+            expire_timeout: -1,
+            ..Self::default()
+        };
+
+        if let Some(icon) = notification.get("icon") {
+            translated.set_icon(inner(icon));
+        }
+
+        let urgency = match string("priority").as_deref() {
+            Some("low") => 0,
+            Some("urgent") => 2,
+            _ => 1,
+        };
+        translated.hints.insert("urgency", Value::U8(urgency));
+        if let Some(category) = string("category") {
+            translated.hints.insert("category", Value::from(category));
+        }
+        if string("sound").as_deref() == Some("silent") {
+            translated.hints.insert("suppress-sound", Value::Bool(true));
+        }
+        for hint in strings(notification.get("display-hint").map(|v| inner(v))) {
+            match hint {
+                "transient" => _ = translated.hints.insert("transient", Value::Bool(true)),
+                "persistent" => translated.expire_timeout = 0,
+                _ => {}
+            }
+        }
+
+        if let Some(name) = string("default-action") {
+            let target = notification
+                .get("default-action-target")
+                .and_then(|v| inner(v).try_to_owned().ok());
+            translated.add_action("default", String::new(), RemoteAction { name, target });
+        }
+        if let Some(Value::Array(buttons)) = notification.get("buttons").map(|v| inner(v)) {
+            for (i, button) in buttons.inner().iter().enumerate() {
+                let Value::Dict(button) = inner(button) else {
+                    continue;
+                };
+                let button: HashMap<&str, &Value> = button
+                    .iter()
+                    .filter_map(|(k, v)| Some((<&str>::try_from(k).ok()?, inner(v))))
+                    .collect();
+                let (Some(label), Some(name)) = (
+                    button.get("label").and_then(|v| <&str>::try_from(*v).ok()),
+                    button.get("action").and_then(|v| <&str>::try_from(*v).ok()),
+                ) else {
+                    continue;
+                };
+                let target = button.get("target").and_then(|v| v.try_to_owned().ok());
+                translated.add_action(
+                    &i.to_string(),
+                    label.to_string(),
+                    RemoteAction {
+                        name: name.to_string(),
+                        target,
+                    },
+                );
+            }
+        }
+
+        translated
+    }
+
+    pub fn add_action(&mut self, key: &str, label: String, action: RemoteAction) {
+        self.actions.push(key.to_string());
+        self.actions.push(label);
+        self.targets.insert(key.to_string(), action);
+    }
+
+    /// Take a serialized `GIcon`: a themed icon becomes the app icon, a file
+    /// or image bytes the notification image
+    fn set_icon(&mut self, icon: &Value<'_>) {
+        let (kind, value) = match icon {
+            // Older serialization, an icon name or path
+            Value::Str(name) => {
+                if name.starts_with('/') || name.starts_with("file://") {
+                    self.hints
+                        .insert("image-path", Value::from(name.to_string()));
+                } else {
+                    self.app_icon = name.to_string();
+                }
+                return;
+            }
+            Value::Structure(icon) => match icon.fields() {
+                [kind, value] => match <&str>::try_from(kind) {
+                    Ok(kind) => (kind, inner(value)),
+                    Err(_) => return,
+                },
+                _ => return,
+            },
+            _ => return,
+        };
+
+        match kind {
+            "themed" => {
+                if let Some(name) = strings(Some(value)).next() {
+                    self.app_icon = name.to_string();
+                }
+            }
+            "file" => {
+                if let Ok(uri) = <&str>::try_from(value) {
+                    self.hints
+                        .insert("image-path", Value::from(uri.to_string()));
+                }
+            }
+            "bytes" => {
+                let Value::Array(bytes) = value else {
+                    return;
+                };
+                let bytes: Vec<u8> = bytes
+                    .inner()
+                    .iter()
+                    .filter_map(|b| u8::try_from(b).ok())
+                    .collect();
+                match NotificationImage::from_bytes(&bytes) {
+                    Ok(image) => {
+                        let rowstride = image.width * 4;
+                        self.hints.insert(
+                            "image-data",
+                            Value::from((
+                                image.width as i32,
+                                image.height as i32,
+                                rowstride as i32,
+                                true,
+                                8,
+                                4,
+                                image.data,
+                            )),
+                        );
+                    }
+                    Err(err) => tracing::warn!("Invalid notification icon: {err}"),
+                }
+            }
+            kind => tracing::debug!("Ignoring notification icon of kind {kind}"),
+        }
+    }
+}
+
+/// The value inside a variant
+fn inner<'a>(value: &'a Value<'a>) -> &'a Value<'a> {
+    match value {
+        Value::Value(value) => value,
+        value => value,
+    }
+}
+
+/// The strings of a string array
+fn strings<'a>(value: Option<&'a Value<'a>>) -> impl Iterator<Item = &'a str> {
+    let values = match value {
+        Some(Value::Array(array)) => array.inner(),
+        _ => &[],
+    };
+    values.iter().filter_map(|v| <&str>::try_from(v).ok())
+}
+
+/// Plain text as body markup
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// An open notification sent with an app-chosen string ID
+#[derive(Debug)]
+struct Remote {
+    app_id: String,
+    id: String,
+    targets: HashMap<String, RemoteAction>,
+}
+
+/// An action to activate in the app that sent a notification
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invocation {
+    pub app_id: String,
+    pub id: String,
+    pub action: RemoteAction,
+}
+
+/// Numeric IDs of the open notifications apps sent with string IDs
+#[derive(Debug, Default)]
+pub struct RemoteIds {
+    ids: HashMap<(String, String), u32>,
+    remotes: HashMap<u32, Remote>,
+}
+
+impl RemoteIds {
+    /// Numeric ID of an app's open notification
+    pub fn get(&self, app_id: &str, id: &str) -> Option<u32> {
+        self.ids.get(&(app_id.to_string(), id.to_string())).copied()
+    }
+
+    pub fn insert(
+        &mut self,
+        numeric: u32,
+        app_id: &str,
+        id: &str,
+        targets: HashMap<String, RemoteAction>,
+    ) {
+        self.remove(numeric);
+        self.ids
+            .insert((app_id.to_string(), id.to_string()), numeric);
+        self.remotes.insert(
+            numeric,
+            Remote {
+                app_id: app_id.to_string(),
+                id: id.to_string(),
+                targets,
+            },
+        );
+    }
+
+    /// Forget a notification once it is closed
+    pub fn remove(&mut self, numeric: u32) {
+        if let Some(remote) = self.remotes.remove(&numeric) {
+            let key = (remote.app_id, remote.id);
+            // The string ID may have moved on to a newer notification
+            if self.ids.get(&key) == Some(&numeric) {
+                self.ids.remove(&key);
+            }
+        }
+    }
+
+    /// What invoking an action key of a notification activates, if the
+    /// notification is one of these
+    pub fn invocation(&self, numeric: u32, key: &str) -> Option<Invocation> {
+        let remote = self.remotes.get(&numeric)?;
+        let action = remote.targets.get(key)?;
+        Some(Invocation {
+            app_id: remote.app_id.clone(),
+            id: remote.id.clone(),
+            action: action.clone(),
+        })
+    }
+}
+
+/// Notification backend of xdg-desktop-portal
+///
+/// Sandboxed apps send notifications through the portal, which passes them
+/// on here with the app's ID. They go through the same rules and limits as
+/// notifications sent with `Notify`, and invoked actions are handed back to
+/// the portal to activate in the app.
+pub struct Portal;
+
+impl Portal {
+    /// Emit `ActionInvoked` for an action of a portal notification
+    ///
+    /// The parameter holds the action's target, if it has one, followed by
+    /// platform data with the activation token.
+    pub async fn invoke(object_server: &ObjectServer, invocation: Invocation, token: &str) {
+        let Ok(iface_ref) = object_server.interface::<_, Portal>(PATH).await else {
+            return;
+        };
+        let mut parameter: Vec<OwnedValue> = invocation.action.target.into_iter().collect();
+        if !token.is_empty() {
+            let platform_data = HashMap::from([("activation-token", Value::from(token))]);
+            if let Ok(platform_data) = OwnedValue::try_from(Value::from(platform_data)) {
+                parameter.push(platform_data);
+            }
+        }
+
+        if let Err(err) = Portal::action_invoked(
+            iface_ref.signal_emitter(),
+            &invocation.app_id,
+            &invocation.id,
+            &invocation.action.name,
+            parameter,
+        )
+        .await
+        {
+            tracing::error!("Failed to signal portal action {}", err);
+        }
+    }
+}
+
+/// Check that a call comes from xdg-desktop-portal, returning its bus name
+///
+/// Apps could otherwise send notifications in another app's name. Calls on
+/// peer-to-peer connections have no sender and are let through.
+async fn frontend(conn: &Connection, header: &Header<'_>) -> zbus::fdo::Result<Option<String>> {
+    let Some(sender) = header.sender() else {
+        return Ok(None);
+    };
+    let owner = DBusProxy::new(conn)
+        .await?
+        .get_name_owner(BusName::from(WellKnownName::from_static_str_unchecked(
+            FRONTEND,
+        )))
+        .await;
+    match owner {
+        Ok(owner) if owner.as_str() == sender.as_str() => Ok(Some(sender.to_string())),
+        _ => {
+            tracing::warn!("Denied portal notification call from {sender}");
+            Err(zbus::fdo::Error::AccessDenied(format!(
+                "Only {FRONTEND} may call the backend"
+            )))
+        }
+    }
+}
+
+/// The `Notifications` server the backends pass notifications to
+pub(super) async fn notifications(
+    object_server: &ObjectServer,
+) -> zbus::fdo::Result<zbus::object_server::InterfaceRef<Notifications>> {
+    object_server
+        .interface::<_, Notifications>("/org/freedesktop/Notifications")
+        .await
+        .map_err(|err| zbus::fdo::Error::Failed(err.to_string()))
+}
+
+#[interface(name = "org.freedesktop.impl.portal.Notification")]
+impl Portal {
+    /// Show a notification for an app, replacing its notification with the
+    /// same ID if that is still open
+    async fn add_notification(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] object_server: &ObjectServer,
+        app_id: &str,
+        id: &str,
+        notification: HashMap<String, OwnedValue>,
+    ) -> zbus::fdo::Result<()> {
+        let caller = frontend(conn, &header).await?;
+        let translated = Translated::new(&notification);
+        notifications(object_server)
+            .await?
+            .get_mut()
+            .await
+            .add_portal_notification(conn, caller, app_id, id, translated)
+            .await;
+        Ok(())
+    }
+
+    /// Close an app's notification
+    async fn remove_notification(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] object_server: &ObjectServer,
+        app_id: &str,
+        id: &str,
+    ) -> zbus::fdo::Result<()> {
+        frontend(conn, &header).await?;
+        notifications(object_server)
+            .await?
+            .get()
+            .await
+            .remove_portal_notification(app_id, id)
+            .await;
+        Ok(())
+    }
+
+    #[zbus(signal)]
+    async fn action_invoked(
+        emitter: &SignalEmitter<'_>,
+        app_id: &str,
+        id: &str,
+        action: &str,
+        parameter: Vec<OwnedValue>,
+    ) -> zbus::Result<()>;
+
+    /// Options with a restricted set of values, none here
+    #[zbus(property)]
+    fn supported_options(&self) -> HashMap<String, OwnedValue> {
+        HashMap::new()
+    }
+
+    #[zbus(property, name = "version")]
+    fn version(&self) -> u32 {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::zvariant::{Array, Structure};
+
+    fn dict(entries: Vec<(&str, Value<'_>)>) -> HashMap<String, OwnedValue> {
+        entries
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.try_to_owned().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_translate_portal_notification() {
+        let button = |label: &str, action: &str, target: Option<Value<'static>>| {
+            let mut button = HashMap::from([
+                ("label", Value::from(label.to_string())),
+                ("action", Value::from(action.to_string())),
+            ]);
+            if let Some(target) = target {
+                button.insert("target", target);
+            }
+            Value::from(button)
+        };
+        let translated = Translated::new(&dict(vec![
+            ("title", Value::from("New message")),
+            ("body", Value::from("1 < 2 & <b>not bold</b>")),
+            (
+                "icon",
+                Value::from(Structure::from((
+                    "themed",
+                    Value::from(vec!["mail-unread", "mail"]),
+                ))),
+            ),
+            ("priority", Value::from("urgent")),
+            ("category", Value::from("im.received")),
+            ("default-action", Value::from("app.open")),
+            ("default-action-target", Value::from(42u32)),
+            (
+                "buttons",
+                Value::from(Array::from(vec![
+                    button("Reply", "app.reply", Some(Value::from("thread-7"))),
+                    button("Mark read", "app.mark-read", None),
+                ])),
+            ),
+            ("display-hint", Value::new(vec!["persistent"])),
+        ]));
+
+        assert_eq!(translated.summary, "New message");
+        assert_eq!(
+            translated.body,
+            "1 &lt; 2 &amp; &lt;b&gt;not bold&lt;/b&gt;"
+        );
+        assert_eq!(translated.app_icon, "mail-unread");
+        assert_eq!(translated.hints["urgency"], Value::U8(2));
+        assert_eq!(translated.hints["category"], Value::from("im.received"));
+        assert_eq!(translated.expire_timeout, 0);
+        assert_eq!(
+            translated.actions,
+            ["default", "", "0", "Reply", "1", "Mark read"]
+        );
+        assert_eq!(translated.targets["default"].name, "app.open");
+        assert_eq!(
+            translated.targets["default"].target,
+            Some(Value::from(42u32).try_to_owned().unwrap())
+        );
+        assert_eq!(
+            translated.targets["0"].target,
+            Some(Value::from("thread-7").try_to_owned().unwrap())
+        );
+        assert_eq!(translated.targets["1"].target, None);
+    }
+
+    #[test]
+    fn test_translate_defaults_and_markup_body() {
+        let translated = Translated::new(&dict(vec![
+            ("title", Value::from("Done")),
+            ("markup-body", Value::from("<b>Export</b> finished")),
+            ("icon", Value::from("/usr/share/pixmaps/app.png")),
+            ("priority", Value::from("high")),
+            ("display-hint", Value::new(vec!["transient"])),
+            ("sound", Value::from("silent")),
+        ]));
+
+        assert_eq!(translated.body, "<b>Export</b> finished");
+        assert_eq!(translated.app_icon, "");
+        assert_eq!(
+            translated.hints["image-path"],
+            Value::from("/usr/share/pixmaps/app.png")
+        );
+        assert_eq!(translated.hints["urgency"], Value::U8(1));
+        assert_eq!(translated.hints["transient"], Value::Bool(true));
+        assert_eq!(translated.hints["suppress-sound"], Value::Bool(true));
+        assert_eq!(translated.expire_timeout, -1);
+        assert!(translated.actions.is_empty());
+    }
+
+    #[test]
+    fn test_remote_ids() {
+        let mut ids = RemoteIds::default();
+        let targets = HashMap::from([(
+            "default".to_string(),
+            RemoteAction {
+                name: "app.open".to_string(),
+                target: None,
+            },
+        )]);
+        ids.insert(5, "org.example.Chat", "msg-1", targets);
+
+        assert_eq!(ids.get("org.example.Chat", "msg-1"), Some(5));
+        assert_eq!(ids.get("org.example.Other", "msg-1"), None);
+        let invocation = ids.invocation(5, "default").unwrap();
+        assert_eq!(
+            (invocation.app_id.as_str(), invocation.id.as_str()),
+            ("org.example.Chat", "msg-1")
+        );
+        assert_eq!(invocation.action.name, "app.open");
+        assert!(ids.invocation(5, "0").is_none());
+
+        ids.remove(5);
+        assert_eq!(ids.get("org.example.Chat", "msg-1"), None);
+        assert!(ids.invocation(5, "default").is_none());
+
+        // Closing an older notification keeps the ID of a newer one
+        ids.insert(6, "org.example.Chat", "msg-1", HashMap::new());
+        ids.insert(7, "org.example.Chat", "msg-1", HashMap::new());
+        ids.remove(6);
+        assert_eq!(ids.get("org.example.Chat", "msg-1"), Some(7));
+    }
+}
@@ -0,0 +1,217 @@
+use cosmic_ext_notifications_util::SenderIdentity;
+use std::{collections::HashMap, fs};
+use zbus::{Connection, fdo::DBusProxy, names::BusName};
+
+/// Resolved identities kept before the cache is reset
+const MAX_CACHED_SENDERS: usize = 256;
+
+/// Cache of sender identities by unique bus name
+///
+/// Unique names are never reused during a bus session, so an identity stays
+/// valid for as long as the name exists.
+#[derive(Debug, Default)]
+pub struct SenderCache {
+    senders: HashMap<String, SenderIdentity>,
+}
+
+impl SenderCache {
+    /// Identity of the client behind a unique bus name
+    pub async fn resolve(&mut self, conn: &Connection, bus_name: &str) -> SenderIdentity {
+        if let Some(identity) = self.senders.get(bus_name) {
+            return identity.clone();
+        }
+
+        let identity = resolve(conn, bus_name).await;
+        if self.senders.len() >= MAX_CACHED_SENDERS {
+            self.senders.clear();
+        }
+        self.senders.insert(bus_name.to_string(), identity.clone());
+        identity
+    }
+}
+
+/// Resolve the client behind a unique bus name to its process and app ID
+///
+/// Parts that cannot be resolved, such as the PID of a client on another
+/// machine, are left empty.
+async fn resolve(conn: &Connection, bus_name: &str) -> SenderIdentity {
+    let mut identity = SenderIdentity {
+        bus_name: bus_name.to_string(),
+        ..Default::default()
+    };
+
+    let pid = match (DBusProxy::new(conn).await, BusName::try_from(bus_name)) {
+        (Ok(proxy), Ok(name)) => proxy.get_connection_unix_process_id(name).await,
+        (Err(err), _) => Err(err.into()),
+        (_, Err(err)) => Err(zbus::fdo::Error::InvalidArgs(err.to_string())),
+    };
+    let pid = match pid {
+        Ok(pid) => pid,
+        Err(err) => {
+            tracing::debug!("Failed to get the PID of {bus_name}: {err}");
+            return identity;
+        }
+    };
+
+    identity.pid = Some(pid);
+    if let Some((app_id, sandboxed)) = app_id_for_pid(pid) {
+        identity.app_id = Some(app_id);
+        identity.sandboxed = sandboxed;
+    }
+    identity.executable = fs::read_link(format!("/proc/{pid}/exe")).ok();
+    if identity.app_id.is_none() {
+        tracing::debug!("No app ID for {bus_name} (pid {pid})");
+    }
+    identity
+}
+
+/// App ID of a process from its sandbox metadata or systemd unit, and
+/// whether it comes from a sandbox
+fn app_id_for_pid(pid: u32) -> Option<(String, bool)> {
+    fs::read_to_string(format!("/proc/{pid}/root/.flatpak-info"))
+        .ok()
+        .and_then(|info| flatpak_app_id(&info))
+        .map(|app_id| (app_id, true))
+        .or_else(|| {
+            fs::read_to_string(format!("/proc/{pid}/cgroup"))
+                .ok()
+                .and_then(|cgroup| app_id_from_cgroup(&cgroup))
+        })
+}
+
+/// App ID from the `[Application]` group of a `.flatpak-info` file
+fn flatpak_app_id(info: &str) -> Option<String> {
+    let mut in_application = false;
+    for line in info.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_application = line == "[Application]";
+        } else if in_application {
+            if let Some(name) = line.strip_prefix("name=") {
+                return Some(name.trim().to_string()).filter(|name| !name.is_empty());
+            }
+        }
+    }
+    None
+}
+
+/// App ID from the cgroup of a process, and whether it comes from a sandbox
+///
+/// Looks for the innermost snap scope (`snap.<name>.<app>-<id>.scope`) or
+/// systemd app unit (`app-[<launcher>-]<app-id>-<random>.scope` or
+/// `app-[<launcher>-]<app-id>@<random>.service`).
+fn app_id_from_cgroup(cgroup: &str) -> Option<(String, bool)> {
+    cgroup
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .flat_map(|path| path.rsplit('/'))
+        .find_map(|unit| {
+            snap_name(unit)
+                .map(|name| (name, true))
+                .or_else(|| systemd_app_id(unit).map(|app_id| (app_id, false)))
+        })
+}
+
+fn snap_name(unit: &str) -> Option<String> {
+    let name = unit.strip_prefix("snap.")?.split('.').next()?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+fn systemd_app_id(unit: &str) -> Option<String> {
+    let unit = unit.strip_prefix("app-")?;
+    let app = if let Some(scope) = unit.strip_suffix(".scope") {
+        // The random part after the last dash identifies the instance
+        scope.rsplit_once('-')?.0
+    } else if let Some(service) = unit.strip_suffix(".service") {
+        service.split_once('@').map_or(service, |(app, _)| app)
+    } else {
+        return None;
+    };
+
+    // Dashes inside the app ID are escaped, so the last part is the app ID
+    let app_id = unescape_unit_name(app.rsplit('-').next()?);
+    (!app_id.is_empty()).then_some(app_id)
+}
+
+/// Undo systemd unit name escaping (`\x2d` and friends)
+fn unescape_unit_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(pos) = rest.find("\\x") {
+        out.push_str(&rest[..pos]);
+        let escaped = &rest[pos + 2..];
+        match escaped
+            .get(..2)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            Some(byte) => {
+                out.push(char::from(byte));
+                rest = &escaped[2..];
+            }
+            None => {
+                out.push_str("\\x");
+                rest = escaped;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flatpak_app_id() {
+        let info = "[Application]\nname=org.mozilla.firefox\nruntime=runtime/org.freedesktop.Platform\n\n[Instance]\nname=other\n";
+        assert_eq!(flatpak_app_id(info).as_deref(), Some("org.mozilla.firefox"));
+        assert_eq!(flatpak_app_id("[Instance]\nname=other\n"), None);
+    }
+
+    #[test]
+    fn test_app_id_from_systemd_scope() {
+        let cgroup = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-cosmic-org.mozilla.firefox-4242.scope\n";
+        assert_eq!(
+            app_id_from_cgroup(cgroup),
+            Some(("org.mozilla.firefox".to_string(), false))
+        );
+
+        let cgroup = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-gnome-com.slack.Slack-99.scope";
+        assert_eq!(
+            app_id_from_cgroup(cgroup),
+            Some(("com.slack.Slack".to_string(), false))
+        );
+    }
+
+    #[test]
+    fn test_app_id_from_systemd_service() {
+        let cgroup = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-org.gnome.Evolution@a1b2.service";
+        assert_eq!(
+            app_id_from_cgroup(cgroup),
+            Some(("org.gnome.Evolution".to_string(), false))
+        );
+
+        let cgroup =
+            "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-my\\x2dtool.service";
+        assert_eq!(
+            app_id_from_cgroup(cgroup),
+            Some(("my-tool".to_string(), false))
+        );
+    }
+
+    #[test]
+    fn test_app_id_from_snap_scope() {
+        let cgroup = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/snap.firefox.firefox-1234.scope";
+        assert_eq!(
+            app_id_from_cgroup(cgroup),
+            Some(("firefox".to_string(), true))
+        );
+    }
+
+    #[test]
+    fn test_no_app_id_outside_app_units() {
+        let cgroup = "0::/user.slice/user-1000.slice/session-2.scope";
+        assert_eq!(app_id_from_cgroup(cgroup), None);
+        assert_eq!(app_id_from_cgroup(""), None);
+    }
+}
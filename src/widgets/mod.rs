@@ -7,5 +7,5 @@ pub mod rich_card;
 
 // Re-export items used by app.rs and rendering/cards.rs
 pub use notification_image::{notification_image, ImageSize};
-pub use progress_bar::{notification_progress, should_show_progress};
+pub use progress_bar::{notification_progress, should_show_progress, styled_progress};
 pub use rich_card::RichCardConfig;
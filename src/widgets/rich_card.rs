@@ -23,6 +23,8 @@ pub struct RichCardConfig {
     pub enable_links: bool,
     /// Whether animated images and card animations are enabled
     pub enable_animations: bool,
+    /// Whether to show a bar counting down until the card expires
+    pub show_countdown: bool,
 }
 
 impl Default for RichCardConfig {
@@ -35,6 +37,7 @@ impl Default for RichCardConfig {
             max_image_size: 128,
             enable_links: true,
             enable_animations: true,
+            show_countdown: false,
         }
     }
 }
@@ -51,6 +54,7 @@ impl RichCardConfig {
             max_image_size: config.max_image_size.clamp(32, 256),
             enable_links: config.enable_links,
             enable_animations: config.enable_animations,
+            show_countdown: config.show_countdown,
         }
     }
 }